@@ -0,0 +1,94 @@
+//! Benchmarks for the style system.
+//!
+//! The `perf-rainbow` example, with thousands of extra rules that don't match any element, as
+//! found in the large stylesheets of real sites.  Run with `cargo bench`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use robinson::bloom::Ancestors;
+use robinson::css::{self, Stylesheet};
+use robinson::dom::{ElementData, Node, NodeType};
+use robinson::font_database::FontDatabase;
+use robinson::layout::Rect;
+use robinson::media::Device;
+use robinson::selector_map::SelectorMap;
+use robinson::style;
+
+const EXTRA_RULES: usize = 3000;
+/// The document has over 100,000 elements, too many to match with a linear scan in a reasonable
+/// time, so matching is measured for the first few hundred.
+const MATCHED_ELEMENTS: usize = 500;
+
+fn stylesheet() -> Stylesheet {
+    let mut source = include_str!("../examples/perf-rainbow.css").to_string();
+    for i in 0..EXTRA_RULES {
+        source += &format!(".unused-{i} {{ color: red; }}\n");
+        source += &format!("#id-{i} {{ margin: {i}px; }}\n");
+        source += &format!("div.x{i} {{ padding: 1px; }}\n");
+        source += &format!(".y{i} div {{ border-width: 1px; }}\n");
+    }
+    css::parse(source)
+}
+
+fn device() -> Device {
+    Device::screen(Rect { x: 0.0, y: 0.0, width: 800.0, height: 600.0 })
+}
+
+/// Every element in the tree, with its ancestors.
+fn elements<'a>(node: &'a Node, ancestors: &mut Ancestors<'a>,
+                result: &mut Vec<(&'a ElementData, Ancestors<'a>)>) {
+    if let NodeType::Element(ref elem) = node.node_type {
+        result.push((elem, ancestors.clone()));
+        ancestors.push(elem);
+        for child in &node.children {
+            elements(child, ancestors, result);
+        }
+        ancestors.pop();
+    }
+}
+
+fn bench_matching(c: &mut Criterion) {
+    let root = robinson::html::parse(include_str!("../examples/perf-rainbow.html").to_string());
+    let stylesheet = stylesheet();
+    let mut elems = Vec::new();
+    elements(&root, &mut Ancestors::default(), &mut elems);
+    elems.truncate(MATCHED_ELEMENTS);
+
+    let mut group = c.benchmark_group("match");
+    group.sample_size(10);
+    group.bench_function("linear", |b| b.iter(|| {
+        let mut count = 0;
+        for (elem, ancestors) in &elems {
+            for rule in &stylesheet.rules {
+                let matches = |s| style::matches(elem, None, ancestors.elements(), s);
+                if rule.selectors.iter().any(matches) {
+                    count += 1;
+                }
+            }
+        }
+        black_box(count)
+    }));
+    let map = SelectorMap::new(&stylesheet, &device());
+    group.bench_function("selector_map", |b| b.iter(|| {
+        let mut count = 0;
+        for (elem, ancestors) in &elems {
+            count += map.matching_rules(elem, None, ancestors).len();
+        }
+        black_box(count)
+    }));
+    group.finish();
+}
+
+fn bench_style_tree(c: &mut Criterion) {
+    let root = robinson::html::parse(include_str!("../examples/perf-rainbow.html").to_string());
+    let stylesheets = [stylesheet()];
+    let fonts = FontDatabase::new();
+    let mut group = c.benchmark_group("style");
+    group.sample_size(10);
+    group.bench_function("style_tree", |b| b.iter(|| {
+        black_box(style::style_tree(&root, &stylesheets, &device(), &fonts));
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_matching, bench_style_tree);
+criterion_main!(benches);
@@ -0,0 +1,560 @@
+//! The Unicode Bidirectional Algorithm, which finds the order to display text in when it mixes
+//! left-to-right scripts like Latin with right-to-left ones like Arabic and Hebrew.
+//!
+//! Each character gets an embedding level: even levels are displayed left to right, and odd
+//! levels right to left.  Levels come from the paragraph's direction, from explicit embeddings
+//! and isolates (which the `unicode-bidi` property inserts as control characters), and from the
+//! directions of the characters themselves, with numbers and punctuation taking the direction
+//! of their surroundings.  Each line is then displayed by reversing every run of characters at
+//! or above each odd level, from the highest level down.
+//!
+//! Letters are displayed in the form they have on their own, so Arabic letters aren't joined.
+//!
+//! https://www.unicode.org/reports/tr9/
+//! https://www.w3.org/TR/css-writing-modes-3/#text-direction
+
+use crate::ucd;
+use crate::values::Direction;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// An embedding level.  Even levels are left to right, and odd levels right to left.
+pub type Level = u8;
+
+/// The deepest level that explicit embeddings can reach.
+const MAX_DEPTH: Level = 125;
+
+/// Bidirectional character types.
+///
+/// https://www.unicode.org/reports/tr9/#Table_Bidirectional_Character_Types
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+enum Class {
+    L, R, AL,
+    EN, ES, ET, AN, CS, NSM, BN,
+    B, S, WS, ON,
+    LRE, LRO, RLE, RLO, PDF, LRI, RLI, FSI, PDI,
+}
+
+use self::Class::*;
+
+fn parse_char(hex: &str) -> char {
+    char::from_u32(u32::from_str_radix(hex, 16).expect("invalid code point")).unwrap()
+}
+
+/// The class of each range of code points, sorted.
+fn classes() -> &'static [(u32, u32, Class)] {
+    static CLASSES: OnceLock<Vec<(u32, u32, Class)>> = OnceLock::new();
+    CLASSES.get_or_init(|| {
+        ucd::parse_ranges(include_str!("../data/BidiClass.txt"), |name| match name {
+            "R" => R, "AL" => AL,
+            "EN" => EN, "ES" => ES, "ET" => ET, "AN" => AN, "CS" => CS,
+            "NSM" => NSM, "BN" => BN,
+            "B" => B, "S" => S, "WS" => WS, "ON" => ON,
+            "LRE" => LRE, "LRO" => LRO, "RLE" => RLE, "RLO" => RLO, "PDF" => PDF,
+            "LRI" => LRI, "RLI" => RLI, "FSI" => FSI, "PDI" => PDI,
+            _ => L,
+        })
+    })
+}
+
+fn class(c: char) -> Class {
+    ucd::lookup(classes(), c).unwrap_or(L)
+}
+
+/// Each paired bracket, with the bracket it pairs with and whether it is an opening bracket.
+fn brackets() -> &'static HashMap<char, (char, bool)> {
+    static BRACKETS: OnceLock<HashMap<char, (char, bool)>> = OnceLock::new();
+    BRACKETS.get_or_init(|| {
+        ucd::parse_data(include_str!("../data/BidiBrackets.txt")).map(|(start, _, fields)| {
+            (char::from_u32(start).unwrap(), (parse_char(fields[0]), fields[1] == "o"))
+        }).collect()
+    })
+}
+
+/// The character whose glyph is the mirror image of `c`'s, for brackets and other symbols whose
+/// glyphs face the other way in right-to-left text.
+pub fn mirror(c: char) -> char {
+    static MIRRORS: OnceLock<HashMap<char, char>> = OnceLock::new();
+    let mirrors = MIRRORS.get_or_init(|| {
+        ucd::parse_data(include_str!("../data/BidiMirroring.txt")).map(|(start, _, fields)| {
+            (char::from_u32(start).unwrap(), parse_char(fields[0]))
+        }).collect()
+    });
+    mirrors.get(&c).copied().unwrap_or(c)
+}
+
+/// The direction of the first character with a strong direction, skipping the contents of
+/// isolates (rules P2 and P3).  This is how `unicode-bidi: plaintext` and `dir="auto"` find the
+/// direction of text.
+pub fn first_strong_direction(text: &str) -> Option<Direction> {
+    first_strong(text.chars().map(class))
+}
+
+fn first_strong(classes: impl Iterator<Item=Class>) -> Option<Direction> {
+    let mut isolates = 0;
+    for class in classes {
+        match class {
+            L if isolates == 0 => return Some(Direction::Ltr),
+            R | AL if isolates == 0 => return Some(Direction::Rtl),
+            LRI | RLI | FSI => isolates += 1,
+            PDI if isolates > 0 => isolates -= 1,
+            PDI | B => break,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The level of text in a paragraph with this direction.
+pub fn paragraph_level(direction: Direction) -> Level {
+    match direction {
+        Direction::Ltr => 0,
+        Direction::Rtl => 1,
+    }
+}
+
+/// The lowest level above `level` that is odd (for right to left) or even.
+fn next_level(level: Level, rtl: bool) -> Level {
+    match (rtl, level % 2) {
+        (true, 0) | (false, 1) => level + 1,
+        _ => level + 2,
+    }
+}
+
+/// One entry in the directional status stack of rules X1 to X8.
+#[derive(Clone, Copy)]
+struct Status {
+    level: Level,
+    /// The class that an override gives to characters.
+    override_class: Option<Class>,
+    isolate: bool,
+}
+
+/// Find the embedding level of each byte of a paragraph, before it is broken into lines.
+///
+/// Explicit embeddings and overrides that are removed by rule X9 take the level of the
+/// character before them.
+pub fn resolve_levels(text: &str, paragraph_level: Level) -> Vec<Level> {
+    let chars: Vec<char> = text.chars().collect();
+    let original: Vec<Class> = chars.iter().map(|&c| class(c)).collect();
+    let mut classes = original.clone();
+    let mut levels = vec![paragraph_level; chars.len()];
+    let matching_pdi = matching_pdis(&original);
+
+    // X1 to X8: Explicit levels and directions.
+    let base = Status { level: paragraph_level, override_class: None, isolate: false };
+    let mut stack = vec![base];
+    let (mut overflow_isolates, mut overflow_embeddings, mut valid_isolates) = (0, 0, 0);
+    for i in 0..chars.len() {
+        let top = *stack.last().unwrap();
+        match original[i] {
+            RLE | LRE | RLO | LRO => {
+                let rtl = matches!(original[i], RLE | RLO);
+                let level = next_level(top.level, rtl);
+                levels[i] = top.level;
+                if level <= MAX_DEPTH && overflow_isolates == 0 && overflow_embeddings == 0 {
+                    let override_class = match original[i] {
+                        RLO => Some(R),
+                        LRO => Some(L),
+                        _ => None,
+                    };
+                    stack.push(Status { level, override_class, isolate: false });
+                } else if overflow_isolates == 0 {
+                    overflow_embeddings += 1;
+                }
+            }
+            RLI | LRI | FSI => {
+                levels[i] = top.level;
+                if let Some(class) = top.override_class {
+                    classes[i] = class;
+                }
+                let rtl = match original[i] {
+                    RLI => true,
+                    LRI => false,
+                    _ => {
+                        let end = matching_pdi[i].unwrap_or(chars.len());
+                        first_strong(original[i + 1..end].iter().copied())
+                            == Some(Direction::Rtl)
+                    }
+                };
+                let level = next_level(top.level, rtl);
+                if level <= MAX_DEPTH && overflow_isolates == 0 && overflow_embeddings == 0 {
+                    valid_isolates += 1;
+                    stack.push(Status { level, override_class: None, isolate: true });
+                } else {
+                    overflow_isolates += 1;
+                }
+            }
+            PDI => {
+                if overflow_isolates > 0 {
+                    overflow_isolates -= 1;
+                } else if valid_isolates > 0 {
+                    overflow_embeddings = 0;
+                    while !stack.last().unwrap().isolate {
+                        stack.pop();
+                    }
+                    stack.pop();
+                    valid_isolates -= 1;
+                }
+                let top = *stack.last().unwrap();
+                levels[i] = top.level;
+                if let Some(class) = top.override_class {
+                    classes[i] = class;
+                }
+            }
+            PDF => {
+                if overflow_isolates == 0 && overflow_embeddings > 0 {
+                    overflow_embeddings -= 1;
+                } else if overflow_isolates == 0 && !top.isolate && stack.len() >= 2 {
+                    stack.pop();
+                }
+                levels[i] = top.level;
+            }
+            B => levels[i] = paragraph_level,
+            BN => levels[i] = top.level,
+            _ => {
+                levels[i] = top.level;
+                if let Some(class) = top.override_class {
+                    classes[i] = class;
+                }
+            }
+        }
+    }
+
+    // X9: Embeddings, overrides and boundary neutrals are left out of the remaining rules.
+    let kept: Vec<usize> = (0..chars.len())
+        .filter(|&i| !matches!(original[i], RLE | LRE | RLO | LRO | PDF | BN))
+        .collect();
+    for sequence in isolating_run_sequences(&kept, &levels, &matching_pdi) {
+        resolve_sequence(&sequence, &kept, &chars, &original, &mut classes, &mut levels,
+                         paragraph_level);
+    }
+
+    // L1: Separators, and white space before them or at the end of the paragraph, are at the
+    // paragraph level.
+    let mut trailing = true;
+    for i in (0..chars.len()).rev() {
+        match original[i] {
+            B | S => {
+                levels[i] = paragraph_level;
+                trailing = true;
+            }
+            WS | FSI | LRI | RLI | PDI if trailing => levels[i] = paragraph_level,
+            RLE | LRE | RLO | LRO | PDF | BN => {}
+            _ => trailing = false,
+        }
+    }
+    // Characters that X9 left out take the level of the character before them.
+    for i in 1..chars.len() {
+        if matches!(original[i], RLE | LRE | RLO | LRO | PDF | BN) {
+            levels[i] = levels[i - 1];
+        }
+    }
+
+    chars.iter().zip(levels).flat_map(|(c, level)| std::iter::repeat_n(level, c.len_utf8()))
+        .collect()
+}
+
+/// The index of the PDI that closes each isolate initiator, if it has one (BD9).
+fn matching_pdis(classes: &[Class]) -> Vec<Option<usize>> {
+    let mut matching = vec![None; classes.len()];
+    let mut open = Vec::new();
+    for (i, &class) in classes.iter().enumerate() {
+        match class {
+            LRI | RLI | FSI => open.push(i),
+            PDI => {
+                if let Some(start) = open.pop() {
+                    matching[start] = Some(i);
+                }
+            }
+            B => open.clear(),
+            _ => {}
+        }
+    }
+    matching
+}
+
+/// Split the characters that X9 keeps into level runs, and join runs that are separated only by
+/// an isolate into isolating run sequences (BD13).  Sequences are lists of positions in `kept`.
+fn isolating_run_sequences(kept: &[usize], levels: &[Level],
+                           matching_pdi: &[Option<usize>]) -> Vec<Vec<usize>> {
+    let mut runs: Vec<Vec<usize>> = Vec::new();
+    for (k, &i) in kept.iter().enumerate() {
+        match runs.last_mut() {
+            Some(run) if levels[kept[*run.last().unwrap()]] == levels[i] => run.push(k),
+            _ => runs.push(vec![k]),
+        }
+    }
+    let is_matched_pdi = |i: usize| matching_pdi.contains(&Some(i));
+    let run_starting_at: HashMap<usize, usize> = runs.iter().enumerate()
+        .map(|(r, run)| (kept[run[0]], r))
+        .collect();
+    let mut sequences = Vec::new();
+    for run in &runs {
+        if is_matched_pdi(kept[run[0]]) {
+            continue;
+        }
+        let mut sequence = run.clone();
+        while let Some(pdi) = matching_pdi[kept[*sequence.last().unwrap()]] {
+            match run_starting_at.get(&pdi) {
+                Some(&r) => sequence.extend(&runs[r]),
+                None => break,
+            }
+        }
+        sequences.push(sequence);
+    }
+    sequences
+}
+
+/// Resolve the weak and neutral types in an isolating run sequence, and then the implicit
+/// levels of its characters (rules W1 to I2).
+fn resolve_sequence(sequence: &[usize], kept: &[usize], chars: &[char], original: &[Class],
+                    classes: &mut [Class], levels: &mut [Level], paragraph_level: Level) {
+    let indices: Vec<usize> = sequence.iter().map(|&k| kept[k]).collect();
+    let level = levels[indices[0]];
+    let first = sequence[0];
+    let last = *sequence.last().unwrap();
+    let level_before = first.checked_sub(1).map_or(paragraph_level, |k| levels[kept[k]]);
+    let level_after = match original[indices[indices.len() - 1]] {
+        LRI | RLI | FSI => paragraph_level,
+        _ => kept.get(last + 1).map_or(paragraph_level, |&i| levels[i]),
+    };
+    let direction = |level: Level| if level.is_multiple_of(2) { L } else { R };
+    let sos = direction(level.max(level_before));
+    let eos = direction(level.max(level_after));
+    let mut types: Vec<Class> = indices.iter().map(|&i| classes[i]).collect();
+    let n = types.len();
+
+    // W1: Non-spacing marks take the type of the character before them.
+    for k in 0..n {
+        if types[k] == NSM {
+            types[k] = match k.checked_sub(1).map(|j| types[j]) {
+                None => sos,
+                Some(LRI | RLI | FSI | PDI) => ON,
+                Some(class) => class,
+            };
+        }
+    }
+    // W2, W3: European numbers after Arabic letters are Arabic numbers, and Arabic letters are
+    // right to left.
+    let mut last_strong = sos;
+    for t in &mut types {
+        match *t {
+            L | R | AL => last_strong = *t,
+            EN if last_strong == AL => *t = AN,
+            _ => {}
+        }
+    }
+    for t in &mut types {
+        if *t == AL {
+            *t = R;
+        }
+    }
+    // W4: A single separator between two numbers of the same kind joins them.
+    for k in 1..n.saturating_sub(1) {
+        types[k] = match (types[k - 1], types[k], types[k + 1]) {
+            (EN, ES | CS, EN) => EN,
+            (AN, CS, AN) => AN,
+            (_, t, _) => t,
+        };
+    }
+    // W5: Terminators like currency symbols next to European numbers are part of them.
+    let mut k = 0;
+    while k < n {
+        if types[k] != ET {
+            k += 1;
+            continue;
+        }
+        let end = (k..n).find(|&j| types[j] != ET).unwrap_or(n);
+        let next_to_number = (k > 0 && types[k - 1] == EN) || (end < n && types[end] == EN);
+        if next_to_number {
+            types[k..end].fill(EN);
+        }
+        k = end;
+    }
+    // W6: Other separators and terminators are neutral.
+    for t in &mut types {
+        if matches!(*t, ES | ET | CS) {
+            *t = ON;
+        }
+    }
+    // W7: European numbers in left-to-right text are left to right.
+    let mut last_strong = sos;
+    for t in &mut types {
+        match *t {
+            L | R => last_strong = *t,
+            EN if last_strong == L => *t = L,
+            _ => {}
+        }
+    }
+
+    // N0: Paired brackets take the direction of the text inside them, or of the text around
+    // them if that agrees with the text inside.
+    let embedding = direction(level);
+    let strong = |t: Class| match t {
+        L => Some(L),
+        R | EN | AN => Some(R),
+        _ => None,
+    };
+    for (open, close) in bracket_pairs(&indices, chars, &types) {
+        let inside: Vec<Class> = types[open + 1..close].iter().filter_map(|&t| strong(t)).collect();
+        let resolved = if inside.contains(&embedding) {
+            Some(embedding)
+        } else if let Some(&opposite) = inside.first() {
+            let before = types[..open].iter().rev().find_map(|&t| strong(t)).unwrap_or(sos);
+            Some(if before == opposite { opposite } else { embedding })
+        } else {
+            None
+        };
+        if let Some(resolved) = resolved {
+            for k in [open, close] {
+                types[k] = resolved;
+                // Marks on a bracket follow it.
+                for j in k + 1..n {
+                    if original[indices[j]] != NSM {
+                        break;
+                    }
+                    types[j] = resolved;
+                }
+            }
+        }
+    }
+
+    // N1, N2: Neutrals between two characters with the same direction take that direction, and
+    // other neutrals take the embedding direction.
+    let is_neutral = |t: Class| matches!(t, B | S | WS | ON | LRI | RLI | FSI | PDI);
+    let mut k = 0;
+    while k < n {
+        if !is_neutral(types[k]) {
+            k += 1;
+            continue;
+        }
+        let end = (k..n).find(|&j| !is_neutral(types[j])).unwrap_or(n);
+        let before = if k == 0 { sos } else { strong(types[k - 1]).unwrap_or(embedding) };
+        let after = if end == n { eos } else { strong(types[end]).unwrap_or(embedding) };
+        let resolved = if before == after { before } else { embedding };
+        types[k..end].fill(resolved);
+        k = end;
+    }
+
+    // I1, I2: Raise the levels of characters whose direction differs from their level's.
+    for (&i, &t) in indices.iter().zip(&types) {
+        levels[i] += match (levels[i] % 2, t) {
+            (0, R) => 1,
+            (0, AN | EN) => 2,
+            (1, L | EN | AN) => 1,
+            _ => 0,
+        };
+        classes[i] = t;
+    }
+}
+
+/// Find the pairs of brackets in an isolating run sequence (BD16), as positions in it, sorted by
+/// their opening brackets.  Only brackets whose type is still neutral can pair.
+fn bracket_pairs(indices: &[usize], chars: &[char], types: &[Class]) -> Vec<(usize, usize)> {
+    // The canonical equivalents of angle brackets pair with each other.
+    let canonical = |c: char| match c {
+        '\u{2329}' => '\u{3008}',
+        '\u{232A}' => '\u{3009}',
+        c => c,
+    };
+    let mut pairs = Vec::new();
+    let mut open: Vec<(char, usize)> = Vec::new();
+    for (k, &i) in indices.iter().enumerate() {
+        if types[k] != ON {
+            continue;
+        }
+        match brackets().get(&chars[i]) {
+            Some(&(pair, true)) => {
+                // Stop looking for pairs if brackets are nested too deeply.
+                if open.len() == 63 {
+                    break;
+                }
+                open.push((canonical(pair), k));
+            }
+            Some(&(_, false)) => {
+                let c = canonical(chars[i]);
+                if let Some(depth) = open.iter().rposition(|&(pair, _)| pair == c) {
+                    pairs.push((open[depth].1, k));
+                    open.truncate(depth);
+                }
+            }
+            None => {}
+        }
+    }
+    pairs.sort_unstable();
+    pairs
+}
+
+/// The order to display the pieces of a line in, given their levels, as indices into `levels`
+/// from left to right (rule L2).
+pub fn visual_order(levels: &[Level]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..levels.len()).collect();
+    let Some(&highest) = levels.iter().max() else { return order };
+    let lowest_odd = levels.iter().copied().filter(|level| level % 2 == 1).min()
+        .unwrap_or(highest + 1);
+    for level in (lowest_odd..=highest).rev() {
+        let mut k = 0;
+        while k < order.len() {
+            if levels[order[k]] < level {
+                k += 1;
+                continue;
+            }
+            let end = (k..order.len()).find(|&j| levels[order[j]] < level).unwrap_or(order.len());
+            order[k..end].reverse();
+            k = end;
+        }
+    }
+    order
+}
+
+/// Right-to-left text in the order its glyphs are displayed from left to right: reversed, with
+/// mirrored brackets, and with combining marks kept after the characters they combine with.
+pub fn reverse(text: &str) -> String {
+    let mut clusters: Vec<String> = Vec::new();
+    for c in text.chars() {
+        match clusters.last_mut() {
+            Some(cluster) if class(c) == NSM => cluster.push(c),
+            _ => clusters.push(mirror(c).to_string()),
+        }
+    }
+    clusters.into_iter().rev().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The level of each character of `text`.
+    fn levels(text: &str, paragraph_level: Level) -> Vec<Level> {
+        let levels = resolve_levels(text, paragraph_level);
+        text.char_indices().map(|(i, _)| levels[i]).collect()
+    }
+
+    #[test]
+    fn resolved_levels() {
+        assert_eq!(levels("abc", 0), [0, 0, 0]);
+        assert_eq!(levels("אבג", 0), [1, 1, 1]);
+        // Numbers after right-to-left letters are displayed right to left, but stay in order.
+        assert_eq!(levels("a אב 12", 0), [0, 0, 1, 1, 1, 2, 2]);
+        // Numbers after left-to-right letters are left to right (rule W7).
+        assert_eq!(levels("abc 123", 1), [2, 2, 2, 2, 2, 2, 2]);
+        // Numbers after Arabic letters are Arabic numbers (rule W2).
+        assert_eq!(levels("ا1", 1), [1, 2]);
+        // Brackets take the direction of the text around them (rule N0).
+        assert_eq!(levels("א(b)", 1), [1, 1, 2, 1]);
+        // An isolate's contents don't affect the text around it.
+        assert_eq!(levels("a\u{2067}bc\u{2069}1", 0), [0, 0, 2, 2, 0, 0]);
+        // Embedding controls, removed by rule X9, take the level of the character before them.
+        assert_eq!(levels("\u{202B}a\u{202C}b", 0), [0, 2, 2, 0]);
+    }
+
+    #[test]
+    fn reordering() {
+        assert_eq!(visual_order(&[0, 0, 1, 1, 1, 0]), [0, 1, 4, 3, 2, 5]);
+        assert_eq!(visual_order(&[1, 2, 2, 1]), [3, 1, 2, 0]);
+        assert_eq!(visual_order(&[2, 2, 1, 0]), [2, 0, 1, 3]);
+        assert_eq!(reverse("a(b)\u{5B4}"), "(\u{5B4}b)a");
+    }
+}
@@ -0,0 +1,184 @@
+//! A Bloom filter of the ancestors of the element being styled.
+//!
+//! Most elements don't match most descendant selectors.  Before walking up the tree to match one,
+//! we check whether the ids, classes and tag names it needs from ancestors are in the filter.  A
+//! Bloom filter can have false positives but never false negatives, so if any of them is missing,
+//! the selector can't match.
+//!
+//! The filter counts how many times each slot was set, so ancestors can be removed again as the
+//! traversal leaves them.  This is the same scheme as Servo's `selectors::bloom`.
+
+use crate::css::{Selector, SimpleSelector};
+use crate::dom::ElementData;
+
+/// The number of bits of each hash used to pick a slot.
+const KEY_BITS: u32 = 12;
+const KEY_MASK: u32 = (1 << KEY_BITS) - 1;
+
+/// The most ancestor hashes that are checked for each selector.  More would reject a few more
+/// selectors, but make every check slower.
+const MAX_SELECTOR_HASHES: usize = 4;
+
+/// A counting Bloom filter of hashes, using two slots per hash.
+#[derive(Clone)]
+struct BloomFilter {
+    counters: Box<[u8]>,
+}
+
+impl Default for BloomFilter {
+    fn default() -> BloomFilter {
+        BloomFilter { counters: vec![0; 1 << KEY_BITS].into_boxed_slice() }
+    }
+}
+
+impl BloomFilter {
+    fn insert(&mut self, hash: u32) {
+        for slot in slots(hash) {
+            let counter = &mut self.counters[slot];
+            // A full counter is never decremented, since it has lost count.
+            *counter = counter.saturating_add(1);
+        }
+    }
+
+    fn remove(&mut self, hash: u32) {
+        for slot in slots(hash) {
+            let counter = &mut self.counters[slot];
+            if *counter != u8::MAX {
+                *counter -= 1;
+            }
+        }
+    }
+
+    fn might_contain(&self, hash: u32) -> bool {
+        slots(hash).iter().all(|&slot| self.counters[slot] != 0)
+    }
+}
+
+fn slots(hash: u32) -> [usize; 2] {
+    [(hash & KEY_MASK) as usize, ((hash >> KEY_BITS) & KEY_MASK) as usize]
+}
+
+/// The ancestors of the element being styled, from the root element down to its parent.
+#[derive(Clone, Default)]
+pub struct Ancestors<'a> {
+    elements: Vec<&'a ElementData>,
+    /// The ids, classes, and tag names of `elements`.
+    filter: BloomFilter,
+}
+
+impl<'a> Ancestors<'a> {
+    /// Add an element, before styling its children.
+    pub fn push(&mut self, elem: &'a ElementData) {
+        element_hashes(elem, |hash| self.filter.insert(hash));
+        self.elements.push(elem);
+    }
+
+    /// Remove the last element added, after styling its children.
+    pub fn pop(&mut self) {
+        let elem = self.elements.pop().expect("no ancestors to pop");
+        element_hashes(elem, |hash| self.filter.remove(hash));
+    }
+
+    /// The ancestor elements, from the root element down.
+    pub fn elements(&self) -> &[&'a ElementData] {
+        &self.elements
+    }
+
+    /// Could a selector with these `ancestor_hashes` match, given these ancestors?
+    pub fn might_match(&self, ancestor_hashes: &[u32]) -> bool {
+        ancestor_hashes.iter().all(|&hash| self.filter.might_contain(hash))
+    }
+}
+
+/// The hashes of some of the ids, classes, and tag names that a selector needs its ancestors to
+/// have, for `Ancestors::might_match`.
+pub fn ancestor_hashes(selector: &Selector) -> Vec<u32> {
+    let mut hashes = Vec::new();
+    let mut selector = selector;
+    while let Selector::Descendant(ref ancestor, _) | Selector::Child(ref ancestor, _) = *selector {
+        simple_selector_hashes(ancestor.subject(), |hash| hashes.push(hash));
+        selector = ancestor;
+    }
+    hashes.truncate(MAX_SELECTOR_HASHES);
+    hashes
+}
+
+fn simple_selector_hashes(selector: &SimpleSelector, mut f: impl FnMut(u32)) {
+    if let Some(ref id) = selector.id {
+        f(hash("#", id));
+    }
+    for class in &selector.class {
+        f(hash(".", class));
+    }
+    if let Some(ref tag_name) = selector.tag_name {
+        f(hash("", tag_name));
+    }
+}
+
+fn element_hashes(elem: &ElementData, mut f: impl FnMut(u32)) {
+    if let Some(id) = elem.id() {
+        f(hash("#", id));
+    }
+    for class in elem.classes() {
+        f(hash(".", class));
+    }
+    f(hash("", &elem.tag_name));
+}
+
+/// Hash an id, class, or tag name, with the prefix that distinguishes them in selectors.
+///
+/// This is 32-bit FNV-1a: http://www.isthe.com/chongo/tech/comp/fnv/
+fn hash(prefix: &str, name: &str) -> u32 {
+    prefix.bytes().chain(name.bytes()).fold(0x811c9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css;
+
+    fn element(tag_name: &str, attrs: &[(&str, &str)]) -> ElementData {
+        let attrs = attrs.iter().map(|&(name, value)| (name.to_string(), value.to_string()));
+        ElementData { tag_name: tag_name.to_string(), attrs: attrs.collect() }
+    }
+
+    fn hashes(selector: &str) -> Vec<u32> {
+        let sheet = css::parse(format!("{} {{ color: red }}", selector));
+        ancestor_hashes(&sheet.rules[0].selectors[0])
+    }
+
+    #[test]
+    fn rejects_selectors_missing_ancestors() {
+        let div = element("div", &[("id", "x"), ("class", "a b")]);
+        let p = element("p", &[]);
+        let mut ancestors = Ancestors::default();
+        ancestors.push(&div);
+        ancestors.push(&p);
+        assert!(ancestors.might_match(&hashes("div p span")));
+        assert!(ancestors.might_match(&hashes("#x.b > p > span")));
+        assert!(ancestors.might_match(&hashes("span")));
+        assert!(!ancestors.might_match(&hashes("section span")));
+        assert!(!ancestors.might_match(&hashes(".c span")));
+        // Ids, classes, and tag names with the same name are different.
+        assert!(!ancestors.might_match(&hashes(".div span")));
+        assert!(!ancestors.might_match(&hashes("x span")));
+
+        // Counts are kept, so an element can be in the filter more than once.
+        ancestors.push(&p);
+        ancestors.pop();
+        assert!(ancestors.might_match(&hashes("p span")));
+        ancestors.pop();
+        assert!(!ancestors.might_match(&hashes("p span")));
+        assert_eq!(ancestors.elements().len(), 1);
+    }
+
+    #[test]
+    fn hashes_only_ancestors() {
+        // The subject's own names aren't checked, and only the first few ancestors' are.
+        assert_eq!(hashes("a.b#c").len(), 0);
+        assert_eq!(hashes("a b").len(), 1);
+        assert_eq!(hashes("#a.b.c.d e f").len(), MAX_SELECTOR_HASHES);
+    }
+}
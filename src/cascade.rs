@@ -0,0 +1,245 @@
+//! The cascade: deciding which of the declarations that apply to an element wins.
+//!
+//! https://www.w3.org/TR/css-cascade-5/#cascade-sort
+
+use crate::css::{is_anonymous_layer, Origin, Specificity, Stylesheet};
+use crate::font_database::FontDatabase;
+use crate::media::Device;
+use crate::selector_map::SelectorMap;
+use std::collections::HashMap;
+
+/// The stylesheets that apply to a document, from all origins, in order of appearance.
+pub struct Cascade<'a> {
+    pub stylesheets: Vec<&'a Stylesheet>,
+    /// The rules of each stylesheet that apply to the device, indexed for selector matching.
+    pub selector_maps: Vec<SelectorMap<'a>>,
+    /// The position of each cascade layer in its origin's layer order, by `layer_key`.
+    /// Unlayered rules are stored under the empty name, since they come after every layer.
+    layer_order: HashMap<(Origin, String), usize>,
+    /// The fonts available to the document, which font-relative lengths like `ex` depend on.
+    pub fonts: &'a FontDatabase,
+}
+
+impl<'a> Cascade<'a> {
+    pub fn new(stylesheets: Vec<&'a Stylesheet>, device: &Device,
+               fonts: &'a FontDatabase) -> Cascade<'a> {
+        let mut layer_order = HashMap::new();
+        for origin in [Origin::UserAgent, Origin::User, Origin::Author] {
+            // Layers with the same name in different stylesheets are the same layer, ordered by
+            // where it first appears.
+            let mut names: Vec<String> = Vec::new();
+            for (i, sheet) in stylesheets.iter().enumerate().filter(|(_, s)| s.origin == origin) {
+                for layer in &sheet.layers {
+                    let key = layer_key(i, layer);
+                    if !names.contains(&key) {
+                        names.push(key);
+                    }
+                }
+            }
+            let names: Vec<&str> = names.iter().map(String::as_str).collect();
+            let mut order = Vec::new();
+            sort_layers("", &names, &mut order);
+            for (i, name) in order.into_iter().enumerate() {
+                layer_order.insert((origin, name.to_string()), i);
+            }
+        }
+        let selector_maps = stylesheets.iter()
+            .map(|&sheet| SelectorMap::new(sheet, device))
+            .collect();
+        Cascade { stylesheets, selector_maps, layer_order, fonts }
+    }
+
+    /// The position of a rule's layer in the layer order of its origin.  `sheet` is the index of
+    /// the rule's stylesheet.
+    pub fn layer_order(&self, origin: Origin, sheet: usize, layer: Option<&str>) -> usize {
+        self.layer_order[&(origin, layer.map_or(String::new(), |layer| layer_key(sheet, layer)))]
+    }
+}
+
+/// The name that identifies a layer in the cascade.  Anonymous layers from different stylesheets
+/// are different layers, even though their names can be the same, so the index of their
+/// stylesheet is added to their names: `<anonymous-0>` in the third stylesheet is
+/// `<anonymous-2-0>`.
+fn layer_key(sheet: usize, layer: &str) -> String {
+    if is_anonymous_layer(layer) {
+        layer.replace("<anonymous-", &format!("<anonymous-{}-", sheet))
+    } else {
+        layer.to_string()
+    }
+}
+
+/// Append the sub-layers of `parent` to `order`, followed by `parent` itself.
+///
+/// Rules directly inside a layer take precedence over the rules in its sub-layers, just as
+/// unlayered rules take precedence over all layers.
+fn sort_layers<'a>(parent: &'a str, names: &[&'a str], order: &mut Vec<&'a str>) {
+    for &name in names {
+        let name_parent = name.rsplit_once('.').map_or("", |(parent, _)| parent);
+        if name_parent == parent {
+            sort_layers(name, names, order);
+        }
+    }
+    order.push(parent);
+}
+
+/// The position of a declaration in the cascade.  Declarations that sort later take precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CascadeKey {
+    /// Normal declarations from each origin, followed by important declarations in the reverse
+    /// order of origin.
+    origin_and_importance: u8,
+    /// Declarations from a `style` attribute beat those from stylesheets.
+    inline: bool,
+    /// Later layers win for normal declarations, and earlier layers for important ones.
+    layer: isize,
+    specificity: Specificity,
+    /// The order of appearance: stylesheet, rule, and declaration.
+    order: (usize, usize, usize),
+}
+
+impl CascadeKey {
+    /// The key for a declaration from a stylesheet rule.
+    pub fn new(origin: Origin, important: bool, layer: usize, specificity: Specificity,
+               order: (usize, usize, usize)) -> CascadeKey {
+        let layer = layer as isize;
+        CascadeKey {
+            origin_and_importance: origin_and_importance(origin, important),
+            inline: false,
+            layer: if important { -layer } else { layer },
+            specificity,
+            order,
+        }
+    }
+
+    /// The key for the `index`th declaration in an element's `style` attribute.
+    pub fn inline(important: bool, index: usize) -> CascadeKey {
+        CascadeKey {
+            origin_and_importance: origin_and_importance(Origin::Author, important),
+            inline: true,
+            layer: 0,
+            specificity: (0, 0, 0),
+            order: (0, 0, index),
+        }
+    }
+
+    /// Whether this declaration is rolled back by a `revert` declaration with key `revert`, or
+    /// by a `revert-layer` declaration if `layer` is true.
+    ///
+    /// `revert` discards the declarations from its own origin and any later origin.
+    /// `revert-layer` discards the declarations from its own layer, and from any layer or origin
+    /// that takes precedence over it.
+    ///
+    /// https://www.w3.org/TR/css-cascade-5/#default
+    pub fn is_reverted_by(&self, revert: &CascadeKey, layer: bool) -> bool {
+        if layer {
+            (self.origin_and_importance, self.inline, self.layer)
+                >= (revert.origin_and_importance, revert.inline, revert.layer)
+        } else {
+            self.origin() >= revert.origin()
+        }
+    }
+
+    fn origin(&self) -> Origin {
+        match self.origin_and_importance {
+            0 | 5 => Origin::UserAgent,
+            1 | 4 => Origin::User,
+            _ => Origin::Author,
+        }
+    }
+}
+
+/// https://www.w3.org/TR/css-cascade-5/#cascade-origin
+fn origin_and_importance(origin: Origin, important: bool) -> u8 {
+    match (important, origin) {
+        (false, Origin::UserAgent) => 0,
+        (false, Origin::User) => 1,
+        (false, Origin::Author) => 2,
+        (true, Origin::Author) => 3,
+        (true, Origin::User) => 4,
+        (true, Origin::UserAgent) => 5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css;
+    use crate::html;
+    use crate::layout::Rect;
+    use crate::style::style_tree;
+    use crate::values::LengthPercentageAuto;
+
+    #[test]
+    fn anonymous_layers_are_named_per_stylesheet() {
+        let source = "@layer { a { color: red } } @layer { b { color: blue } }";
+        let first = css::parse(source.to_string());
+        let second = css::parse(source.to_string());
+        assert_eq!(first.layers, ["<anonymous-0>", "<anonymous-1>"]);
+        assert_eq!(first, second);
+
+        // They are still different layers in the cascade, ordered by where they appear.
+        let device = Device::screen(Default::default());
+        let fonts = FontDatabase::new();
+        let cascade = Cascade::new(vec![&first, &second], &device, &fonts);
+        let orders: Vec<usize> = [(0, "<anonymous-0>"), (0, "<anonymous-1>"),
+                                  (1, "<anonymous-0>"), (1, "<anonymous-1>")].iter()
+            .map(|&(sheet, layer)| cascade.layer_order(Origin::Author, sheet, Some(layer)))
+            .collect();
+        assert_eq!(orders, [0, 1, 2, 3]);
+        assert_eq!(cascade.layer_order(Origin::Author, 0, None), 4);
+    }
+
+    #[test]
+    fn origins_importance_and_layers() {
+        let root = html::parse("<div><p style=\"height: 1px; top: 1px !important\"></p></div>"
+            .to_string());
+        let user = css::parse_with_origin("p { width: 1px !important; height: 2px; \
+                                               left: 1px }".to_string(), Origin::User);
+        let author = css::parse("@layer a, b; \
+                                 p { width: 2px !important; height: 3px; \
+                                     top: 2px !important; left: 2px } \
+                                 @layer b { p { right: 2px; bottom: 2px !important } } \
+                                 @layer a { p { right: 1px; bottom: 1px !important } } \
+                                 p { right: 3px; bottom: 3px }".to_string());
+        let device = Device::screen(Rect { width: 800.0, height: 600.0, ..Default::default() });
+        let tree = style_tree(&root, &[user, author], &device, &FontDatabase::new());
+        let style = &tree.children[0].style;
+        let px = |value: &LengthPercentageAuto| value.resolve(0.0).unwrap();
+        // Important user declarations beat important author ones.
+        assert_eq!(px(&style.width), 1.0);
+        // Normal author declarations beat normal user ones, and inline styles beat both.
+        assert_eq!(px(&style.left), 2.0);
+        assert_eq!(px(&style.height), 1.0);
+        // Important inline declarations beat important author ones too.
+        assert_eq!(px(&style.top), 1.0);
+        // Unlayered declarations beat layered ones, and later layers beat earlier ones...
+        assert_eq!(px(&style.right), 3.0);
+        // ...unless they are important, when earlier layers win.
+        assert_eq!(px(&style.bottom), 1.0);
+    }
+
+    #[test]
+    fn revert_and_revert_layer() {
+        let root = html::parse("<div><p></p></div>".to_string());
+        let user = css::parse_with_origin("p { margin-left: 1px; margin-right: 1px }"
+            .to_string(), Origin::User);
+        let author = css::parse("p { margin-top: 5px; margin-left: 5px } \
+                                 @layer a { p { margin-right: 2px; width: 2px } } \
+                                 @layer b { p { margin-right: 3px; width: 3px } } \
+                                 @layer b { p { margin-right: revert-layer } } \
+                                 p { margin-top: revert; margin-left: revert; \
+                                     width: revert-layer; height: revert }".to_string());
+        let device = Device::screen(Rect { width: 800.0, height: 600.0, ..Default::default() });
+        let tree = style_tree(&root, &[user, author], &device, &FontDatabase::new());
+        let style = &tree.children[0].style;
+        let px = |value: &LengthPercentageAuto| value.resolve(0.0);
+        // `revert` rolls back to the user origin, or else to the user agent's.
+        assert_eq!(px(&style.margin_top), Some(16.0));
+        assert_eq!(px(&style.margin_left), Some(1.0));
+        // With nothing to revert to, it acts like `unset`.
+        assert_eq!(px(&style.height), None);
+        // `revert-layer` rolls back to the previous layer.
+        assert_eq!(px(&style.margin_right), Some(2.0));
+        assert_eq!(px(&style.width), Some(3.0));
+    }
+}
@@ -0,0 +1,315 @@
+//! Named colors and color space conversions for CSS color values.
+//!
+//! https://www.w3.org/TR/css-color-4/
+
+use crate::css::Color;
+
+/// Convert sRGB components in the range 0.0 to 1.0 to a `Color`, clamping out-of-gamut values.
+pub fn srgb(r: f32, g: f32, b: f32, alpha: f32) -> Color {
+    let channel = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    Color { r: channel(r), g: channel(g), b: channel(b), a: channel(alpha) }
+}
+
+/// https://www.w3.org/TR/css-color-4/#hsl-to-rgb
+///
+/// `hue` is in degrees; `saturation` and `lightness` are in the range 0.0 to 1.0.
+pub fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> (f32, f32, f32) {
+    let hue = hue.rem_euclid(360.0);
+    let f = |n: f32| {
+        let k = (n + hue / 30.0) % 12.0;
+        let a = saturation * lightness.min(1.0 - lightness);
+        lightness - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0)
+    };
+    (f(0.0), f(8.0), f(4.0))
+}
+
+/// https://www.w3.org/TR/css-color-4/#hwb-to-rgb
+pub fn hwb_to_rgb(hue: f32, whiteness: f32, blackness: f32) -> (f32, f32, f32) {
+    if whiteness + blackness >= 1.0 {
+        let gray = whiteness / (whiteness + blackness);
+        return (gray, gray, gray);
+    }
+    let (r, g, b) = hsl_to_rgb(hue, 1.0, 0.5);
+    let scale = |c: f32| c * (1.0 - whiteness - blackness) + whiteness;
+    (scale(r), scale(g), scale(b))
+}
+
+/// Convert CIE Lab (with a D50 white point) to sRGB.
+///
+/// https://www.w3.org/TR/css-color-4/#color-conversion-code
+pub fn lab_to_rgb(l: f32, a: f32, b: f32) -> (f32, f32, f32) {
+    // Lab to XYZ (D50).
+    const KAPPA: f32 = 24389.0 / 27.0;
+    const EPSILON: f32 = 216.0 / 24389.0;
+    let fy = (l + 16.0) / 116.0;
+    let fx = a / 500.0 + fy;
+    let fz = fy - b / 200.0;
+    let xr = if fx.powi(3) > EPSILON { fx.powi(3) } else { (116.0 * fx - 16.0) / KAPPA };
+    let yr = if l > KAPPA * EPSILON { fy.powi(3) } else { l / KAPPA };
+    let zr = if fz.powi(3) > EPSILON { fz.powi(3) } else { (116.0 * fz - 16.0) / KAPPA };
+    let (x, y, z) = (xr * 0.3457 / 0.3585, yr, zr * (1.0 - 0.3457 - 0.3585) / 0.3585);
+
+    // Bradford chromatic adaptation from D50 to D65.
+    let (x, y, z) = (
+        0.955_473_4 * x - 0.023_098_54 * y + 0.063_259_31 * z,
+        -0.028_369_7 * x + 1.009_995_5 * y + 0.021_041_4 * z,
+        0.012_314_01 * x - 0.020_507_7 * y + 1.330_366 * z,
+    );
+
+    // XYZ (D65) to linear sRGB.
+    let r = 3.240_97 * x - 1.537_383_2 * y - 0.498_610_76 * z;
+    let g = -0.969_243_6 * x + 1.875_967_5 * y + 0.041_555_06 * z;
+    let b = 0.055_630_08 * x - 0.203_976_96 * y + 1.056_971_5 * z;
+    (gamma(r), gamma(g), gamma(b))
+}
+
+/// Convert Oklab to sRGB.
+///
+/// https://bottosson.github.io/posts/oklab/
+pub fn oklab_to_rgb(l: f32, a: f32, b: f32) -> (f32, f32, f32) {
+    let l_ = (l + 0.396_337_78 * a + 0.215_803_76 * b).powi(3);
+    let m_ = (l - 0.105_561_346 * a - 0.063_854_17 * b).powi(3);
+    let s_ = (l - 0.089_484_18 * a - 1.291_485_5 * b).powi(3);
+
+    let r = 4.076_741_7 * l_ - 3.307_711_6 * m_ + 0.230_969_94 * s_;
+    let g = -1.268_438 * l_ + 2.609_757_4 * m_ - 0.341_319_38 * s_;
+    let b = -0.004_196_086_3 * l_ - 0.703_418_6 * m_ + 1.707_614_7 * s_;
+    (gamma(r), gamma(g), gamma(b))
+}
+
+/// Convert polar coordinates (chroma and hue in degrees) to the `a` and `b` axes of Lab or Oklab.
+pub fn polar_to_ab(chroma: f32, hue: f32) -> (f32, f32) {
+    let radians = hue.to_radians();
+    (chroma * radians.cos(), chroma * radians.sin())
+}
+
+/// Apply the sRGB transfer function to a linear-light value.
+fn gamma(c: f32) -> f32 {
+    if c.abs() > 0.003_130_8 {
+        c.signum() * (1.055 * c.abs().powf(1.0 / 2.4) - 0.055)
+    } else {
+        12.92 * c
+    }
+}
+
+/// Look up a named color (case-insensitively).  This includes `transparent`.
+///
+/// https://www.w3.org/TR/css-color-4/#named-colors
+pub fn named_color(name: &str) -> Option<Color> {
+    let name = name.to_ascii_lowercase();
+    if name == "transparent" {
+        return Some(Color { r: 0, g: 0, b: 0, a: 0 });
+    }
+    NAMED_COLORS.binary_search_by_key(&&*name, |&(n, _)| n).ok().map(|i| {
+        let rgb = NAMED_COLORS[i].1;
+        Color { r: (rgb >> 16) as u8, g: (rgb >> 8) as u8, b: rgb as u8, a: 255 }
+    })
+}
+
+/// The name of an opaque named color, which is the first in alphabetical order if it has several
+/// names, like `aqua` and `cyan`.
+pub fn color_name(color: Color) -> Option<&'static str> {
+    if color.a != 255 {
+        return None;
+    }
+    let rgb = (color.r as u32) << 16 | (color.g as u32) << 8 | color.b as u32;
+    NAMED_COLORS.iter().find(|&&(_, value)| value == rgb).map(|&(name, _)| name)
+}
+
+/// The CSS named colors, sorted by name.
+const NAMED_COLORS: [(&str, u32); 148] = [
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("grey", 0x808080),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check that converted sRGB components round to the color `rgb`, give or take one.
+    fn assert_rgb((r, g, b): (f32, f32, f32), rgb: u32) {
+        let color = srgb(r, g, b, 1.0);
+        let expected = [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8];
+        let actual = [color.r, color.g, color.b];
+        assert!(actual.iter().zip(expected).all(|(&a, e)| a.abs_diff(e) <= 1),
+                "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn hsl_and_hwb() {
+        assert_rgb(hsl_to_rgb(0.0, 1.0, 0.5), 0xff0000);
+        assert_rgb(hsl_to_rgb(120.0, 1.0, 0.25), 0x008000);
+        assert_rgb(hsl_to_rgb(-120.0, 1.0, 0.5), 0x0000ff);
+        assert_rgb(hsl_to_rgb(210.0, 0.5, 0.6), 0x6699cc);
+        assert_rgb(hsl_to_rgb(90.0, 0.0, 0.5), 0x808080);
+        assert_rgb(hwb_to_rgb(60.0, 0.0, 0.0), 0xffff00);
+        assert_rgb(hwb_to_rgb(240.0, 0.2, 0.4), 0x333399);
+        // Whiteness and blackness that add up to more than 100% give a gray.
+        assert_rgb(hwb_to_rgb(0.0, 0.6, 0.6), 0x808080);
+    }
+
+    #[test]
+    fn lab_and_lch() {
+        assert_rgb(lab_to_rgb(100.0, 0.0, 0.0), 0xffffff);
+        assert_rgb(lab_to_rgb(0.0, 0.0, 0.0), 0x000000);
+        assert_rgb(lab_to_rgb(54.29, 80.8, 69.89), 0xff0000);
+        assert_rgb(lab_to_rgb(29.57, 68.29, -112.03), 0x0000ff);
+        let (a, b) = polar_to_ab(106.84, 40.85);
+        assert_rgb(lab_to_rgb(54.29, a, b), 0xff0000);
+    }
+
+    #[test]
+    fn oklab_and_oklch() {
+        assert_rgb(oklab_to_rgb(1.0, 0.0, 0.0), 0xffffff);
+        assert_rgb(oklab_to_rgb(0.627_96, 0.224_86, 0.125_85), 0xff0000);
+        assert_rgb(oklab_to_rgb(0.866_44, -0.233_89, 0.179_42), 0x00ff00);
+        let (a, b) = polar_to_ab(0.313_21, 264.05);
+        assert_rgb(oklab_to_rgb(0.452_01, a, b), 0x0000ff);
+    }
+}
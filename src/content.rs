@@ -0,0 +1,356 @@
+//! Generated content: the `content` property of `::before` and `::after`, counters, quotes, and
+//! list markers.
+//!
+//! https://www.w3.org/TR/css-content-3/
+//! https://www.w3.org/TR/css-lists-3/#auto-numbering
+
+use crate::css::{PseudoElement, Value};
+use crate::dom::NodeType;
+use crate::style::StyledNode;
+use crate::values::Display;
+
+/// Fill in the text of every `::before` and `::after` box in a style tree.
+///
+/// This walks the tree in document order, since the values of counters and the nesting depth of
+/// quotes depend on everything that came before.
+pub fn generate_content(root: &mut StyledNode) {
+    let mut state = ContentState { counters: Vec::new(), quote_depth: 0 };
+    state.visit(root, 0);
+}
+
+/// One instance of a named counter.
+struct Counter {
+    name: String,
+    value: i32,
+    /// Depth in the tree of the element that created this counter.  The counter is in scope for
+    /// that element, its following siblings, and all their descendants.
+    depth: usize,
+}
+
+struct ContentState {
+    /// All counters in scope, from outermost to innermost.
+    counters: Vec<Counter>,
+    /// Number of `open-quote`s without a matching `close-quote` so far.
+    quote_depth: usize,
+}
+
+/// Pairs of open and close quotes, from outermost to innermost.
+type Quotes = Vec<(String, String)>;
+
+impl ContentState {
+    fn visit(&mut self, node: &mut StyledNode, depth: usize) {
+        if node.pseudo.is_none() && matches!(node.node.node_type, NodeType::Text(_)) {
+            return;
+        }
+
+        self.update_counters(node, depth);
+
+        if node.pseudo.is_some() {
+            // A pseudo-element has a single child, for its generated text.
+            let text = self.content_text(node, depth);
+            node.children[0].generated_text = Some(text);
+        } else {
+            for child in &mut node.children {
+                self.visit(child, depth + 1);
+            }
+        }
+
+        // Counters created by this node's children go out of scope.
+        self.counters.retain(|counter| counter.depth <= depth);
+    }
+
+    /// Apply `counter-reset`, then `counter-increment`, then `counter-set`.
+    ///
+    /// https://www.w3.org/TR/css-lists-3/#counter-properties
+    fn update_counters(&mut self, node: &StyledNode, depth: usize) {
+        for (name, value) in counter_list(node.style.counter_reset.clone(), 0) {
+            self.reset(name, value, depth);
+        }
+
+        let mut increments = counter_list(node.style.counter_increment.clone(), 1);
+        // List items implicitly increment the `list-item` counter, unless they say otherwise.
+        if node.pseudo.is_none() && node.display() == Display::ListItem
+            && !increments.iter().any(|(name, _)| name == "list-item") {
+            increments.push(("list-item".to_string(), 1));
+        }
+        for (name, value) in increments {
+            self.counter(&name, depth).value += value;
+        }
+
+        for (name, value) in counter_list(node.style.counter_set.clone(), 0) {
+            self.counter(&name, depth).value = value;
+        }
+    }
+
+    /// Create a new counter, replacing any counter of the same name created by a sibling.
+    fn reset(&mut self, name: String, value: i32, depth: usize) {
+        match self.counters.iter_mut().rev().find(|c| c.name == name) {
+            Some(counter) if counter.depth == depth => counter.value = value,
+            _ => self.counters.push(Counter { name, value, depth }),
+        }
+    }
+
+    /// Find the innermost counter named `name`, creating it if none is in scope.
+    fn counter(&mut self, name: &str, depth: usize) -> &mut Counter {
+        match self.counters.iter().rposition(|c| c.name == name) {
+            Some(i) => &mut self.counters[i],
+            None => {
+                self.reset(name.to_string(), 0, depth);
+                self.counters.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Evaluate the `content` property of a pseudo-element.
+    fn content_text(&mut self, node: &StyledNode, depth: usize) -> String {
+        let quotes = &parse_quotes(node.style.quotes.clone());
+        let is_marker = node.pseudo == Some(PseudoElement::Marker);
+        let items = match node.style.content.clone() {
+            Value::Keyword(ref k) if k == "normal" && is_marker => {
+                return self.marker_text(depth, &node.style.list_style_type);
+            }
+            Value::List(items) => items,
+            value => vec![value],
+        };
+        let mut text = String::new();
+        for item in items {
+            match item {
+                Value::Str(s) => text.push_str(&s),
+                Value::Function(name, args) => match (&*name, &args[..]) {
+                    ("attr", [Value::Keyword(attr), ..]) => {
+                        if let NodeType::Element(ref elem) = node.node.node_type {
+                            if let Some(value) = elem.attrs.get(attr) {
+                                text.push_str(value);
+                            }
+                        }
+                    }
+                    ("counter", [Value::Keyword(name), rest @ ..]) => {
+                        let style = counter_style(rest);
+                        let value = self.counter(name, depth).value;
+                        text.push_str(&format_counter(value, &style));
+                    }
+                    ("counters", [Value::Keyword(name), Value::Str(separator), rest @ ..]) => {
+                        let style = counter_style(rest);
+                        self.counter(name, depth);
+                        let values: Vec<_> = self.counters.iter()
+                            .filter(|c| c.name == *name)
+                            .map(|c| format_counter(c.value, &style))
+                            .collect();
+                        text.push_str(&values.join(separator));
+                    }
+                    _ => {}
+                },
+                Value::Keyword(keyword) => match &*keyword {
+                    "open-quote" => {
+                        if let Some((open, _)) = quote_pair(quotes, self.quote_depth) {
+                            text.push_str(open);
+                        }
+                        self.quote_depth += 1;
+                    }
+                    "close-quote" if self.quote_depth > 0 => {
+                        self.quote_depth -= 1;
+                        if let Some((_, close)) = quote_pair(quotes, self.quote_depth) {
+                            text.push_str(close);
+                        }
+                    }
+                    "no-open-quote" => self.quote_depth += 1,
+                    "no-close-quote" => self.quote_depth = self.quote_depth.saturating_sub(1),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        text
+    }
+
+    /// The default contents of a `::marker`, based on `list-style-type`.
+    ///
+    /// https://www.w3.org/TR/css-lists-3/#content-property
+    fn marker_text(&mut self, depth: usize, list_style_type: &Value) -> String {
+        match list_style_type {
+            Value::Str(s) => s.clone(),
+            Value::Keyword(style) if style == "none" => String::new(),
+            Value::Keyword(style) => {
+                let value = self.counter("list-item", depth).value;
+                let suffix = if is_symbolic(style) { " " } else { ". " };
+                format_counter(value, style) + suffix
+            }
+            _ => String::new(),
+        }
+    }
+}
+
+/// Parse the value of `counter-reset` or `counter-increment`: a list of counter names, each
+/// optionally followed by an integer.
+fn counter_list(value: Value, default: i32) -> Vec<(String, i32)> {
+    let items = match value {
+        Value::List(items) => items,
+        value => vec![value],
+    };
+    let mut result: Vec<(String, i32)> = Vec::new();
+    for item in items {
+        match item {
+            Value::Keyword(ref name) if name == "none" => {}
+            Value::Keyword(name) => result.push((name, default)),
+            Value::Number(n) => {
+                if let Some(last) = result.last_mut() {
+                    last.1 = n as i32;
+                }
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+/// The optional counter style argument of `counter()` or `counters()`.
+fn counter_style(args: &[Value]) -> String {
+    match args {
+        [Value::Keyword(style), ..] => style.clone(),
+        _ => "decimal".to_string(),
+    }
+}
+
+/// Represent a counter value as text, using one of the predefined counter styles.
+///
+/// https://www.w3.org/TR/css-counter-styles-3/#predefined-counters
+fn format_counter(value: i32, style: &str) -> String {
+    match style {
+        "none" => String::new(),
+        "disc" => "\u{2022}".to_string(),
+        "circle" => "\u{25E6}".to_string(),
+        "square" => "\u{25AA}".to_string(),
+        "decimal-leading-zero" if (0..10).contains(&value) => format!("0{}", value),
+        "lower-alpha" | "lower-latin" => alphabetic(value, &LATIN).to_lowercase(),
+        "upper-alpha" | "upper-latin" => alphabetic(value, &LATIN),
+        "lower-greek" => alphabetic(value, &GREEK),
+        "lower-roman" => roman(value).to_lowercase(),
+        "upper-roman" => roman(value),
+        _ => value.to_string(),
+    }
+}
+
+/// Counter styles that use the same symbol for every value.
+fn is_symbolic(style: &str) -> bool {
+    matches!(style, "disc" | "circle" | "square")
+}
+
+const LATIN: [char; 26] = ['A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M',
+                           'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z'];
+
+const GREEK: [char; 24] = ['α', 'β', 'γ', 'δ', 'ε', 'ζ', 'η', 'θ', 'ι', 'κ', 'λ', 'μ',
+                           'ν', 'ξ', 'ο', 'π', 'ρ', 'σ', 'τ', 'υ', 'φ', 'χ', 'ψ', 'ω'];
+
+/// Bijective base-N numbering: a, b, ..., z, aa, ab, ...  Values below 1 fall back to decimal.
+fn alphabetic(value: i32, symbols: &[char]) -> String {
+    if value < 1 {
+        return value.to_string();
+    }
+    let n = symbols.len() as i32;
+    let mut value = value;
+    let mut result = Vec::new();
+    while value > 0 {
+        value -= 1;
+        result.push(symbols[(value % n) as usize]);
+        value /= n;
+    }
+    result.iter().rev().collect()
+}
+
+/// Upper-case roman numerals, for values from 1 to 3999.  Other values fall back to decimal.
+fn roman(value: i32) -> String {
+    if !(1..=3999).contains(&value) {
+        return value.to_string();
+    }
+    const NUMERALS: [(i32, &str); 13] = [
+        (1000, "M"), (900, "CM"), (500, "D"), (400, "CD"), (100, "C"), (90, "XC"),
+        (50, "L"), (40, "XL"), (10, "X"), (9, "IX"), (5, "V"), (4, "IV"), (1, "I"),
+    ];
+    let mut value = value;
+    let mut result = String::new();
+    for &(n, numeral) in &NUMERALS {
+        while value >= n {
+            result.push_str(numeral);
+            value -= n;
+        }
+    }
+    result
+}
+
+/// Parse the value of the `quotes` property.
+fn parse_quotes(value: Value) -> Quotes {
+    let strings: Vec<String> = match value {
+        Value::Keyword(ref k) if k == "none" => return Vec::new(),
+        Value::List(items) => items.into_iter().filter_map(|item| match item {
+            Value::Str(s) => Some(s),
+            _ => None,
+        }).collect(),
+        _ => return default_quotes(),
+    };
+    strings.chunks_exact(2).map(|pair| (pair[0].clone(), pair[1].clone())).collect()
+}
+
+/// The quotes for the given nesting depth.  Deeper levels repeat the innermost pair.
+fn quote_pair(quotes: &Quotes, depth: usize) -> Option<&(String, String)> {
+    quotes.get(depth).or(quotes.last())
+}
+
+/// The quotes used for `quotes: auto`.
+fn default_quotes() -> Quotes {
+    vec![
+        ("\u{201C}".to_string(), "\u{201D}".to_string()),
+        ("\u{2018}".to_string(), "\u{2019}".to_string()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css;
+    use crate::font_database::FontDatabase;
+    use crate::html;
+    use crate::layout::Rect;
+    use crate::media::Device;
+    use crate::style::style_tree;
+
+    /// The text of every pseudo-element in a document, in order.
+    fn generated(html: &str, css: &str) -> Vec<String> {
+        fn collect(node: &StyledNode, texts: &mut Vec<String>) {
+            if let Some(text) = &node.generated_text {
+                texts.push(text.clone());
+            }
+            for child in &node.children {
+                collect(child, texts);
+            }
+        }
+        let root = html::parse(html.to_string());
+        let device = Device::screen(Rect { width: 800.0, height: 600.0, ..Default::default() });
+        let fonts = FontDatabase::new();
+        let tree = style_tree(&root, &[css::parse(css.to_string())], &device, &fonts);
+        let mut texts = Vec::new();
+        collect(&tree, &mut texts);
+        texts
+    }
+
+    #[test]
+    fn list_markers() {
+        let html = "<div><ol start=\"3\"><li>a</li><li value=\"7\">b</li><li>c</li></ol>\
+                    <ol><li>d</li></ol><ul><li>e</li></ul></div>";
+        assert_eq!(generated(html, ""), ["3. ", "7. ", "8. ", "1. ", "\u{2022} "]);
+        let css = "ol { list-style-type: lower-roman } ul { list-style-type: \"-\" }";
+        assert_eq!(generated(html, css), ["iii. ", "vii. ", "viii. ", "i. ", "-"]);
+    }
+
+    #[test]
+    fn counters_and_quotes() {
+        let html = "<div><h1></h1><h2></h2><h2></h2><h1></h1><h2></h2><q></q></div>";
+        let css = "div { counter-reset: h1 } \
+                   h1 { counter-increment: h1; counter-reset: h2 } h2 { counter-increment: h2 } \
+                   h1::before { content: counter(h1, upper-alpha) } \
+                   h2::before { content: counters(h1, \"/\") \".\" counter(h2) } \
+                   q::before { content: open-quote open-quote } \
+                   q::after { content: close-quote close-quote }";
+        assert_eq!(generated(html, css),
+                   ["A", "1.1", "1.2", "B", "2.1", "\u{201C}\u{2018}", "\u{2019}\u{201D}"]);
+    }
+}
@@ -3,47 +3,206 @@
 //! To support more CSS syntax, it would probably be easiest to replace this
 //! hand-rolled parser with one based on a library or parser generator.
 
+use crate::color;
+use crate::media::{Comparison, MediaCondition, MediaFeature, MediaQuery, MediaQueryList};
+use crate::media::{MediaType, MediaValue};
+use crate::properties;
+use crate::shorthands;
+use crate::values::DEFAULT_FONT_SIZE;
+use crate::variables;
+use std::sync::Arc;
+
 // Data structures:
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Stylesheet {
     pub rules: Vec<Rule>,
+    pub origin: Origin,
+    /// The full names of the cascade layers declared in this stylesheet, in order of first
+    /// appearance.  A layer's parent is always declared before it.  Anonymous layers are named
+    /// `<anonymous-0>`, `<anonymous-1>` and so on, which are only unique within the stylesheet.
+    pub layers: Vec<String>,
+    /// At-rules that don't contain style rules, in order of appearance.
+    pub at_rules: Vec<AtRule>,
 }
 
-#[derive(Debug)]
+/// Where a stylesheet comes from.  Normal declarations from later origins take precedence over
+/// earlier ones, and `!important` declarations reverse this order.
+///
+/// https://www.w3.org/TR/css-cascade-5/#cascading-origins
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Origin {
+    UserAgent,
+    User,
+    Author,
+}
+
+#[derive(Debug, PartialEq)]
 pub struct Rule {
     pub selectors: Vec<Selector>,
     pub declarations: Vec<Declaration>,
+    /// The full name of the cascade layer this rule is in, like `reset` or `framework.base`, or
+    /// `None` if it is not in a layer.
+    pub layer: Option<String>,
+    /// The media query lists of the `@media` rules this rule is inside.  The rule only applies
+    /// if they all match.
+    pub media: Vec<Arc<MediaQueryList>>,
 }
 
-#[derive(Debug)]
+/// An at-rule whose contents are kept for the parts of the engine that use them.
+///
+/// `@layer`, `@media`, and `@supports` are not included, since they only affect which style rules
+/// apply and in what order.
+#[derive(Debug, PartialEq)]
+pub enum AtRule {
+    /// The descriptors of a web font.
+    ///
+    /// https://www.w3.org/TR/css-fonts-4/#font-face-rule
+    FontFace(Vec<Declaration>),
+    /// The page selectors, like `:first`, and the declarations of a `@page` rule.
+    ///
+    /// https://www.w3.org/TR/css-page-3/#at-page-rule
+    Page(Vec<String>, Vec<Declaration>),
+    /// The name and keyframes of an animation.
+    ///
+    /// https://www.w3.org/TR/css-animations-1/#keyframes
+    Keyframes(String, Vec<Keyframe>),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Keyframe {
+    /// The positions of the keyframe in the animation, from 0.0 for `from` to 1.0 for `to`.
+    pub offsets: Vec<f32>,
+    pub declarations: Vec<Declaration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Selector {
     Simple(SimpleSelector),
+    /// A descendant combinator, like `ul li`: matches elements that match the simple selector
+    /// and have an ancestor that matches the boxed selector.
+    Descendant(Box<Selector>, SimpleSelector),
+    /// A child combinator, like `ul > li`: matches elements that match the simple selector and
+    /// whose parent matches the boxed selector.
+    Child(Box<Selector>, SimpleSelector),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct SimpleSelector {
     pub tag_name: Option<String>,
     pub id: Option<String>,
     pub class: Vec<String>,
+    pub pseudo_classes: Vec<PseudoClass>,
+    pub pseudo_element: Option<PseudoElement>,
 }
 
-#[derive(Debug)]
+/// https://www.w3.org/TR/selectors-4/#pseudo-classes
+#[derive(Debug, Clone, PartialEq)]
+pub enum PseudoClass {
+    /// `:is()` matches elements that match any of its selectors.  Nested rules use it to refer to
+    /// the selectors of their parent rule.
+    Is(Vec<Selector>),
+    /// `:where()` is like `:is()`, but adds nothing to the specificity.
+    Where(Vec<Selector>),
+    /// `:root` matches the root element.
+    Root,
+    /// `:hover`, `:focus`, and other states of user interaction, which never match, since
+    /// nothing interacts with a rendered page.
+    UserAction,
+}
+
+/// Pseudo-elements that generate boxes from the `content` property.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PseudoElement {
+    Before,
+    After,
+    Marker,
+}
+
+#[derive(Debug, PartialEq)]
 pub struct Declaration {
     pub name: String,
     pub value: Value,
+    pub important: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Keyword(String),
     Length(f32, Unit),
+    Number(f32),
     ColorValue(Color),
+    /// A quoted string, with escapes already processed.
+    Str(String),
+    /// A functional notation like `attr(title)` or `counter(item)`.
+    Function(String, Vec<Value>),
+    /// A space-separated list of component values, e.g. `"(" counter(item) ")"`.
+    List(Vec<Value>),
+    /// A math function: `calc()`, `min()`, `max()`, or `clamp()`.
+    Calc(Box<CalcNode>),
+    /// A comma-separated list, e.g. `Georgia, "Times New Roman", serif`.  Each item may be a
+    /// space-separated `List`.
+    CommaList(Vec<Value>),
+    /// A delimiter between component values, like the `/` in `font: 12px/1.5 serif`.
+    Delim(char),
+    /// The source text of a custom property, or of a value that contains `var()` references.
+    Unparsed(String),
+    /// The value of a longhand set by a shorthand that contains `var()` references: the name and
+    /// source text of the shorthand.  It is expanded once the variables are substituted.
+    PendingSubstitution(String, String),
 }
 
+/// A node in the expression tree of a math function.
+///
+/// https://www.w3.org/TR/css-values-4/#calc-internal
 #[derive(Debug, Clone, PartialEq)]
+pub enum CalcNode {
+    Number(f32),
+    Length(f32, Unit),
+    Sum(Vec<CalcNode>),
+    Product(Vec<CalcNode>),
+    /// Subtraction is represented as adding a negated value.
+    Negate(Box<CalcNode>),
+    /// Division is represented as multiplying by an inverted value.
+    Invert(Box<CalcNode>),
+    Min(Vec<CalcNode>),
+    Max(Vec<CalcNode>),
+    /// `clamp(min, value, max)`
+    Clamp(Box<CalcNode>, Box<CalcNode>, Box<CalcNode>),
+}
+
+/// The type of a math expression: what kind of value it evaluates to.
+///
+/// https://www.w3.org/TR/css-values-4/#calc-type-checking
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CalcType {
+    Number,
+    Length,
+    Percentage,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Unit {
+    // Absolute lengths:
     Px,
+    Pt,
+    Pc,
+    In,
+    Cm,
+    Mm,
+    Q,
+    // Font-relative lengths:
+    Em,
+    Rem,
+    Ex,
+    Ch,
+    // Viewport-relative lengths:
+    Vw,
+    Vh,
+    Vmin,
+    Vmax,
+    /// Percentages, relative to something that depends on the property.
+    Percent,
 }
 
 #[derive(Debug, Clone, PartialEq, Default)]
@@ -56,80 +215,1095 @@ pub struct Color {
 
 impl Copy for Color {}
 
+/// A component of a color function, before it is scaled to the right range.
+#[derive(Clone, Copy)]
+enum ColorComponent {
+    Number(f32),
+    Percentage(f32),
+}
+
+impl ColorComponent {
+    /// The value of this component, where 100% is equal to `percent_reference`.
+    fn resolve(self, percent_reference: f32) -> f32 {
+        match self {
+            ColorComponent::Number(n) => n,
+            ColorComponent::Percentage(p) => p / 100.0 * percent_reference,
+        }
+    }
+}
+
 pub type Specificity = (usize, usize, usize);
 
 impl Selector {
     pub fn specificity(&self) -> Specificity {
         // http://www.w3.org/TR/selectors/#specificity
-        let Selector::Simple(ref simple) = *self;
-        let a = simple.id.iter().count();
-        let b = simple.class.len();
-        let c = simple.tag_name.iter().count();
+        match *self {
+            Selector::Simple(ref simple) => simple.specificity(),
+            Selector::Descendant(ref ancestor, ref simple) |
+            Selector::Child(ref ancestor, ref simple) => {
+                let (a, b, c) = ancestor.specificity();
+                let (x, y, z) = simple.specificity();
+                (a + x, b + y, c + z)
+            }
+        }
+    }
+
+    /// The rightmost simple selector, which the element itself must match.
+    pub fn subject(&self) -> &SimpleSelector {
+        match *self {
+            Selector::Simple(ref simple) |
+            Selector::Descendant(_, ref simple) |
+            Selector::Child(_, ref simple) => simple,
+        }
+    }
+
+    /// Add `parent` to the start of this selector, with a descendant combinator, or a child
+    /// combinator if `child` is true.
+    fn prepend(self, parent: SimpleSelector, child: bool) -> Selector {
+        match self {
+            Selector::Simple(simple) if child => {
+                Selector::Child(Box::new(Selector::Simple(parent)), simple)
+            }
+            Selector::Simple(simple) => {
+                Selector::Descendant(Box::new(Selector::Simple(parent)), simple)
+            }
+            Selector::Descendant(ancestor, simple) => {
+                Selector::Descendant(Box::new(ancestor.prepend(parent, child)), simple)
+            }
+            Selector::Child(ancestor, simple) => {
+                Selector::Child(Box::new(ancestor.prepend(parent, child)), simple)
+            }
+        }
+    }
+}
+
+impl SimpleSelector {
+    fn specificity(&self) -> Specificity {
+        let mut a = self.id.iter().count();
+        let mut b = self.class.len();
+        let mut c = self.tag_name.iter().count() + self.pseudo_element.iter().count();
+        for pseudo_class in &self.pseudo_classes {
+            // `:is()` has the specificity of its most specific selector.
+            let (x, y, z) = match pseudo_class {
+                PseudoClass::Is(selectors) => {
+                    selectors.iter().map(Selector::specificity).max().unwrap_or_default()
+                }
+                PseudoClass::Where(_) => (0, 0, 0),
+                PseudoClass::Root | PseudoClass::UserAction => (0, 1, 0),
+            };
+            a += x;
+            b += y;
+            c += z;
+        }
         (a, b, c)
     }
+
+    /// Add a simple selector that matches the same elements as the `parent` selectors, for `&`
+    /// in a nested rule.
+    fn add_nesting_selector(&mut self, parent: &[Selector]) {
+        match parent {
+            // A single simple selector can be merged in, unless its type or id would conflict.
+            // This leaves a better key for the `SelectorMap`.
+            [Selector::Simple(simple)] if simple.pseudo_element.is_none()
+                && (self.tag_name.is_none() || simple.tag_name.is_none())
+                && (self.id.is_none() || simple.id.is_none()) => {
+                if simple.tag_name.is_some() {
+                    self.tag_name.clone_from(&simple.tag_name);
+                }
+                if simple.id.is_some() {
+                    self.id.clone_from(&simple.id);
+                }
+                self.class.extend(simple.class.iter().cloned());
+                self.pseudo_classes.extend(simple.pseudo_classes.iter().cloned());
+            }
+            _ => self.pseudo_classes.push(PseudoClass::Is(parent.to_vec())),
+        }
+    }
+}
+
+impl Unit {
+    /// The size of one of this unit in px, if it is an absolute length.
+    ///
+    /// https://www.w3.org/TR/css-values-4/#absolute-lengths
+    pub fn absolute_size(self) -> Option<f32> {
+        match self {
+            Unit::Px => Some(1.0),
+            Unit::In => Some(96.0),
+            Unit::Cm => Some(96.0 / 2.54),
+            Unit::Mm => Some(96.0 / 25.4),
+            Unit::Q => Some(96.0 / 101.6),
+            Unit::Pt => Some(96.0 / 72.0),
+            Unit::Pc => Some(16.0),
+            _ => None
+        }
+    }
 }
 
 impl Value {
-    /// Return the size of a length in px, or zero for non-lengths.
+    /// A list of space-separated values, or the value itself if there is only one.
+    pub fn from_list(mut values: Vec<Value>) -> Value {
+        if values.len() == 1 { values.remove(0) } else { Value::List(values) }
+    }
+
+    /// The items of a space-separated list, or just this value if it is not a list.
+    pub fn items(&self) -> &[Value] {
+        match self {
+            Value::List(items) => items,
+            value => std::slice::from_ref(value),
+        }
+    }
+
+    /// Return the size of an absolute length in px, or zero for anything else.
+    ///
+    /// Relative lengths must be resolved first, by `style_tree`.
     pub fn to_px(&self) -> f32 {
         match *self {
-            Value::Length(f, Unit::Px) => f,
+            Value::Length(f, unit) => unit.absolute_size().map_or(0.0, |size| f * size),
+            Value::Calc(ref node) => node.evaluate(0.0),
             _ => 0.0
         }
     }
 }
 
-/// Parse a whole CSS stylesheet.
+impl CalcNode {
+    /// Replace each length in the expression with the result of `f`.
+    pub fn map_lengths(self, f: &impl Fn(f32, Unit) -> CalcNode) -> CalcNode {
+        let map_all = |nodes: Vec<CalcNode>| nodes.into_iter().map(|n| n.map_lengths(f)).collect();
+        match self {
+            CalcNode::Length(value, unit) => f(value, unit),
+            CalcNode::Number(n) => CalcNode::Number(n),
+            CalcNode::Sum(nodes) => CalcNode::Sum(map_all(nodes)),
+            CalcNode::Product(nodes) => CalcNode::Product(map_all(nodes)),
+            CalcNode::Min(nodes) => CalcNode::Min(map_all(nodes)),
+            CalcNode::Max(nodes) => CalcNode::Max(map_all(nodes)),
+            CalcNode::Negate(node) => CalcNode::Negate(Box::new(node.map_lengths(f))),
+            CalcNode::Invert(node) => CalcNode::Invert(Box::new(node.map_lengths(f))),
+            CalcNode::Clamp(min, value, max) => CalcNode::Clamp(
+                Box::new(min.map_lengths(f)),
+                Box::new(value.map_lengths(f)),
+                Box::new(max.map_lengths(f))),
+        }
+    }
+
+    /// The type of the expression, or `None` if its operands' types don't fit together, like
+    /// `1px + 2` or `1px * 2px`.  A sum of lengths and percentages is a length, since the
+    /// percentages will resolve to lengths.
+    pub fn calc_type(&self) -> Option<CalcType> {
+        // Sums and comparisons need operands of the same type.
+        fn common_type<'a>(nodes: impl IntoIterator<Item = &'a CalcNode>) -> Option<CalcType> {
+            let mut types = nodes.into_iter().map(CalcNode::calc_type);
+            types.next()?.and_then(|first| types.try_fold(first, |a, b| match (a, b?) {
+                (a, b) if a == b => Some(a),
+                (CalcType::Length, CalcType::Percentage)
+                    | (CalcType::Percentage, CalcType::Length) => Some(CalcType::Length),
+                _ => None,
+            }))
+        }
+        match self {
+            CalcNode::Number(_) => Some(CalcType::Number),
+            CalcNode::Length(_, Unit::Percent) => Some(CalcType::Percentage),
+            CalcNode::Length(..) => Some(CalcType::Length),
+            CalcNode::Sum(nodes) | CalcNode::Min(nodes) | CalcNode::Max(nodes) => {
+                common_type(nodes)
+            }
+            CalcNode::Clamp(min, value, max) => common_type([&**min, &**value, &**max]),
+            // At most one factor of a product can have a unit.
+            CalcNode::Product(nodes) => nodes.iter().try_fold(CalcType::Number, |a, node| {
+                match (a, node.calc_type()?) {
+                    (a, CalcType::Number) => Some(a),
+                    (CalcType::Number, b) => Some(b),
+                    _ => None,
+                }
+            }),
+            CalcNode::Negate(node) => node.calc_type(),
+            // Only numbers can be divided by.
+            CalcNode::Invert(node) => node.calc_type().filter(|&t| t == CalcType::Number),
+        }
+    }
+
+    /// Does this expression contain any percentages?
+    pub fn has_percentages(&self) -> bool {
+        match self {
+            CalcNode::Length(_, unit) => *unit == Unit::Percent,
+            CalcNode::Number(_) => false,
+            CalcNode::Sum(nodes) | CalcNode::Product(nodes) |
+            CalcNode::Min(nodes) | CalcNode::Max(nodes) => {
+                nodes.iter().any(|n| n.has_percentages())
+            }
+            CalcNode::Negate(node) | CalcNode::Invert(node) => node.has_percentages(),
+            CalcNode::Clamp(min, value, max) => {
+                min.has_percentages() || value.has_percentages() || max.has_percentages()
+            }
+        }
+    }
+
+    /// Evaluate the expression in px, where 100% is `percent_reference`.
+    ///
+    /// Relative lengths other than percentages must already be converted to absolute lengths.
+    pub fn evaluate(&self, percent_reference: f32) -> f32 {
+        let eval = |node: &CalcNode| node.evaluate(percent_reference);
+        match self {
+            CalcNode::Number(n) => *n,
+            CalcNode::Length(f, Unit::Percent) => f / 100.0 * percent_reference,
+            CalcNode::Length(f, unit) => Value::Length(*f, *unit).to_px(),
+            CalcNode::Sum(nodes) => nodes.iter().map(eval).sum(),
+            CalcNode::Product(nodes) => nodes.iter().map(eval).product(),
+            CalcNode::Negate(node) => -eval(node),
+            CalcNode::Invert(node) => 1.0 / eval(node),
+            CalcNode::Min(nodes) => nodes.iter().map(eval).fold(f32::INFINITY, f32::min),
+            CalcNode::Max(nodes) => nodes.iter().map(eval).fold(f32::NEG_INFINITY, f32::max),
+            // If min is greater than max, min wins.
+            CalcNode::Clamp(min, value, max) => eval(value).min(eval(max)).max(eval(min)),
+        }
+    }
+}
+
+/// Parse a whole CSS stylesheet from the author of a document.
 pub fn parse(source: String) -> Stylesheet {
-    let mut parser = Parser { pos: 0, input: source };
-    Stylesheet { rules: parser.parse_rules() }
+    parse_with_origin(source, Origin::Author)
+}
+
+/// Parse a whole CSS stylesheet from the given origin.
+pub fn parse_with_origin(source: String, origin: Origin) -> Stylesheet {
+    let mut parser = Parser::new(source);
+    let rules = parser.parse_rules(&RuleContext::default());
+    Stylesheet { rules, origin, layers: parser.layers, at_rules: parser.at_rules }
+}
+
+/// Parse the declarations in a `style` attribute.
+pub fn parse_inline_style(source: &str) -> Vec<Declaration> {
+    let mut parser = Parser::new(source.to_string());
+    parser.parse_declaration_list()
+}
+
+/// Parse the value of a single declaration, like the initial value of a property.  Panics if the
+/// value is invalid.
+pub fn parse_value(source: &str) -> Value {
+    or_panic(Parser::new(source.to_string()).parse_value_list())
+}
+
+/// Parse the value of a single declaration, e.g. after substituting its variables.  Returns
+/// `None` if the value isn't valid CSS, or uses syntax this parser doesn't support.
+pub fn try_parse_value(source: &str) -> Option<Value> {
+    Parser::new(source.to_string()).parse_value_list().ok()
+}
+
+/// A syntax error, or syntax that this parser doesn't support.
+///
+/// Only parts of the parser recover from errors.  The rest panic, as `or_panic` does.
+#[derive(Debug)]
+struct ParseError(String);
+
+type ParseResult<T> = Result<T, ParseError>;
+
+/// Unwrap the result of parsing something that the parser doesn't recover from errors in.
+fn or_panic<T>(result: ParseResult<T>) -> T {
+    result.unwrap_or_else(|ParseError(message)| panic!("{}", message))
 }
 
 struct Parser {
     pos: usize,
     input: String,
+    /// The cascade layers declared so far.
+    layers: Vec<String>,
+    /// The number of anonymous layers so far, which is used to name the next one.
+    anonymous_layers: usize,
+    /// The at-rules parsed so far, other than those that contain style rules.
+    at_rules: Vec<AtRule>,
+}
+
+/// The at-rules and style rule that the rules being parsed are inside.
+#[derive(Clone, Default)]
+struct RuleContext {
+    /// The full name of the cascade layer.
+    layer: Option<String>,
+    /// The media query lists of the enclosing `@media` rules.
+    media: Vec<Arc<MediaQueryList>>,
+    /// Whether the rules are inside a block, which ends at a `}`.
+    nested: bool,
+    /// The selectors of the enclosing style rule, for nested rules.
+    parent: Option<Vec<Selector>>,
+}
+
+impl RuleContext {
+    /// A style rule in this context.
+    fn rule(&self, selectors: Vec<Selector>, declarations: Vec<Declaration>) -> Rule {
+        Rule {
+            selectors,
+            declarations,
+            layer: self.layer.clone(),
+            media: self.media.clone(),
+        }
+    }
 }
 
 impl Parser {
-    /// Parse a list of rule sets, separated by optional whitespace.
-    fn parse_rules(&mut self) -> Vec<Rule> {
+    fn new(input: String) -> Parser {
+        Parser { pos: 0, input, layers: Vec::new(), anonymous_layers: 0, at_rules: Vec::new() }
+    }
+
+    /// Parse a list of rules, up to the end of the input or the `}` that closes the block
+    /// containing them.
+    fn parse_rules(&mut self, context: &RuleContext) -> Vec<Rule> {
+        if let Some(ref parent) = context.parent {
+            // Inside a style rule, the block of an at-rule like `@media` can also contain
+            // declarations, which apply to the parent rule's selectors.
+            return self.parse_style_block(context, parent);
+        }
         let mut rules = Vec::new();
         loop {
             self.consume_whitespace();
-            if self.eof() { break }
-            rules.push(self.parse_rule());
+            if self.eof() || (context.nested && self.next_char() == '}') { break }
+            if self.next_char() == '@' {
+                rules.extend(self.parse_at_rule(context));
+            } else {
+                rules.extend(self.parse_rule(context));
+            }
         }
         rules
     }
 
-    /// Parse a rule set: `<selectors> { <declarations> }`.
-    fn parse_rule(&mut self) -> Rule {
-        Rule {
-            selectors: self.parse_selectors(),
-            declarations: self.parse_declarations(),
+    /// Parse a style rule: `<selectors> { <declarations> }`.  Returns the rule, followed by the
+    /// rules nested inside it.
+    ///
+    /// Nested rules are flattened, with `&` in their selectors (or at the start, if there is no
+    /// `&`) standing for `:is(<parent selectors>)`.  Each run of declarations between nested
+    /// rules becomes a separate rule with the parent's selectors, so the rules stay in source
+    /// order.
+    ///
+    /// https://www.w3.org/TR/css-nesting-1/
+    ///
+    /// If the selectors can't be parsed, the whole rule is skipped.
+    fn parse_rule(&mut self, context: &RuleContext) -> Vec<Rule> {
+        let start = self.pos;
+        let selectors = match self.parse_selectors(context.parent.as_deref()) {
+            Ok(selectors) if self.expect('{').is_ok() => selectors,
+            _ => {
+                self.pos = start;
+                self.skip_at_rule();
+                // Skip a stray `)` or `]`, which doesn't end the rule, so the rest of it is
+                // skipped next.
+                if self.pos == start {
+                    self.consume_char();
+                }
+                return Vec::new();
+            }
+        };
+        let nested = RuleContext { parent: Some(selectors.clone()), ..context.clone() };
+        let rules = self.parse_style_block(&nested, &selectors);
+        self.expect_char('}');
+        rules
+    }
+
+    /// Parse the declarations and nested rules in the block of a style rule, up to its `}`.
+    /// Returns the rules in source order, with a rule for each run of declarations.  Rules with
+    /// no declarations are left out.
+    fn parse_style_block(&mut self, context: &RuleContext, selectors: &[Selector]) -> Vec<Rule> {
+        let mut declarations = Vec::new();
+        let mut rules = Vec::new();
+        loop {
+            self.consume_whitespace();
+            if self.eof() || self.next_char() == '}' { break }
+            let nested_rules = match self.next_char() {
+                ';' => {
+                    self.consume_char();
+                    continue;
+                }
+                '@' => self.parse_at_rule(context),
+                _ if self.starts_nested_rule() => self.parse_rule(context),
+                _ => {
+                    if let Some(declaration) = self.parse_declaration_or_skip() {
+                        let longhands = shorthands::expand(declaration);
+                        declarations.extend(longhands.into_iter().filter(properties::is_valid));
+                    }
+                    continue;
+                }
+            };
+            if !declarations.is_empty() && !nested_rules.is_empty() {
+                let declarations = std::mem::take(&mut declarations);
+                rules.push(context.rule(selectors.to_vec(), declarations));
+            }
+            rules.extend(nested_rules);
+        }
+        if !declarations.is_empty() {
+            rules.push(context.rule(selectors.to_vec(), declarations));
+        }
+        rules
+    }
+
+    /// Does a nested style rule start here, rather than a declaration?  The selectors of a rule
+    /// are followed by a `{`, before any `;` or `}`.
+    fn starts_nested_rule(&self) -> bool {
+        let rest = &self.input[self.pos..];
+        if rest.starts_with("--") {
+            return false;
+        }
+        let mut quote = None;
+        for c in rest.chars() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '"' | '\'') => quote = Some(c),
+                (None, '{') => return true,
+                (None, ';' | '}') => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Parse an at-rule, returning the style rules inside it.  Other at-rules are added to
+    /// `self.at_rules`, and unknown ones are skipped, as are `@layer` and `@keyframes` rules
+    /// that can't be parsed.
+    fn parse_at_rule(&mut self, context: &RuleContext) -> Vec<Rule> {
+        self.expect_char('@');
+        let name = self.parse_identifier();
+        self.consume_whitespace();
+        let start = self.pos;
+        let at_rule = match &*name.to_ascii_lowercase() {
+            "layer" => match self.parse_layer_rule(context) {
+                Ok(rules) => return rules,
+                Err(_) => None,
+            },
+            "media" => return self.parse_media_rule(context),
+            "supports" => return self.parse_supports_rule(context),
+            "font-face" => Some(AtRule::FontFace(self.parse_descriptors(is_font_descriptor))),
+            "page" => Some(self.parse_page_rule()),
+            "keyframes" => self.parse_keyframes_rule().ok(),
+            _ => None,
+        };
+        match at_rule {
+            Some(at_rule) => self.at_rules.push(at_rule),
+            None => {
+                self.pos = start;
+                self.skip_at_rule();
+            }
+        }
+        Vec::new()
+    }
+
+    /// Skip the rest of an unknown at-rule, or a style rule with invalid selectors: up to the `;`
+    /// that ends a statement, or to the end of its block.
+    fn skip_at_rule(&mut self) {
+        let mut depth = 0;
+        while !self.eof() {
+            if self.consume_comment() {
+                continue;
+            }
+            match self.next_char() {
+                '"' | '\'' => {
+                    self.parse_string();
+                    continue;
+                }
+                ';' if depth == 0 => {
+                    self.consume_char();
+                    return;
+                }
+                '(' | '[' | '{' => depth += 1,
+                // The end of the block that contains this at-rule.
+                ')' | ']' | '}' if depth == 0 => return,
+                '}' if depth == 1 => {
+                    self.consume_char();
+                    return;
+                }
+                ')' | ']' | '}' => depth -= 1,
+                _ => {}
+            }
+            self.consume_char();
+        }
+    }
+
+    /// Parse the rest of an `@supports` block.  Its rules are only included if the engine
+    /// supports its condition.  A condition that can't be parsed makes the whole rule invalid.
+    ///
+    /// https://www.w3.org/TR/css-conditional-3/#at-supports
+    fn parse_supports_rule(&mut self, context: &RuleContext) -> Vec<Rule> {
+        let start = self.pos;
+        if !matches!(self.parse_supports_condition(), Ok(true)) {
+            self.pos = start;
+            self.skip_at_rule();
+            return Vec::new();
         }
+        let context = RuleContext { nested: true, ..context.clone() };
+        self.consume_whitespace();
+        self.expect_char('{');
+        let rules = self.parse_rules(&context);
+        self.expect_char('}');
+        rules
     }
 
-    /// Parse a comma-separated list of selectors.
-    fn parse_selectors(&mut self) -> Vec<Selector> {
+    /// Parse and evaluate `not <condition>`, or conditions joined by `and` or `or`.
+    fn parse_supports_condition(&mut self) -> ParseResult<bool> {
+        if self.consume_keyword("not") {
+            self.consume_whitespace();
+            return Ok(!self.parse_supports_in_parens()?);
+        }
+        let mut result = self.parse_supports_in_parens()?;
+        self.consume_whitespace();
+        let and = if self.consume_keyword("and") {
+            true
+        } else if self.consume_keyword("or") {
+            false
+        } else {
+            return Ok(result);
+        };
+        loop {
+            self.consume_whitespace();
+            let next = self.parse_supports_in_parens()?;
+            result = if and { result && next } else { result || next };
+            self.consume_whitespace();
+            if !self.consume_keyword(if and { "and" } else { "or" }) { break }
+        }
+        Ok(result)
+    }
+
+    /// Parse and evaluate a declaration or a nested condition in parentheses, or a `selector()`
+    /// function.  A declaration or selector that can't be parsed is false, and so are other
+    /// functions.
+    fn parse_supports_in_parens(&mut self) -> ParseResult<bool> {
+        if self.peek()? != '(' {
+            let name = self.parse_identifier();
+            if name.is_empty() {
+                let c = self.peek()?;
+                let message = format!("Unexpected character {} in @supports condition", c);
+                return Err(ParseError(message));
+            }
+            let arguments = self.consume_parenthesized()?;
+            return Ok(name.eq_ignore_ascii_case("selector") && supports_selector(&arguments));
+        }
+        let start = self.pos;
+        self.consume_char();
+        self.consume_whitespace();
+        if self.starts_condition() {
+            let result = self.parse_supports_condition()?;
+            self.consume_whitespace();
+            self.expect(')')?;
+            return Ok(result);
+        }
+        self.pos = start;
+        Ok(supports_declaration(&self.consume_parenthesized()?))
+    }
+
+    /// Consume a parenthesized block like `(a: b)`, returning the text inside the parentheses.
+    fn consume_parenthesized(&mut self) -> ParseResult<String> {
+        self.expect('(')?;
+        let start = self.pos;
+        let mut depth = 0;
+        while !self.eof() {
+            if self.consume_comment() {
+                continue;
+            }
+            match self.next_char() {
+                '"' | '\'' => {
+                    self.parse_string();
+                    continue;
+                }
+                '(' => depth += 1,
+                ')' if depth == 0 => break,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            self.consume_char();
+        }
+        let inner = self.input[start..self.pos].to_string();
+        self.expect(')')?;
+        Ok(inner)
+    }
+
+    /// Parse a block of descriptors, like the body of a `@font-face` rule.  Unlike properties,
+    /// descriptors aren't expanded or validated, but any that `is_descriptor` rejects are
+    /// dropped.
+    fn parse_descriptors(&mut self, is_descriptor: fn(&str) -> bool) -> Vec<Declaration> {
+        self.expect_char('{');
+        let mut descriptors = Vec::new();
+        loop {
+            self.consume_whitespace();
+            if self.eof() || self.next_char() == '}' { break }
+            if self.next_char() == ';' {
+                self.consume_char();
+                continue;
+            }
+            match self.parse_declaration_or_skip() {
+                Some(descriptor) if is_descriptor(&descriptor.name) => descriptors.push(descriptor),
+                _ => {}
+            }
+        }
+        self.expect_char('}');
+        descriptors
+    }
+
+    /// Parse the rest of a `@page` rule, skipping any margin rules like `@top-center` inside it.
+    ///
+    /// https://www.w3.org/TR/css-page-3/#at-page-rule
+    fn parse_page_rule(&mut self) -> AtRule {
         let mut selectors = Vec::new();
         loop {
-            selectors.push(Selector::Simple(self.parse_simple_selector()));
+            self.consume_whitespace();
+            let selector = self.consume_while(|c| valid_identifier_char(c) || c == ':');
+            if !selector.is_empty() {
+                selectors.push(selector);
+            }
             self.consume_whitespace();
             match self.next_char() {
-                ',' => { self.consume_char(); self.consume_whitespace(); }
+                ',' => { self.consume_char(); }
                 '{' => break,
-                c   => panic!("Unexpected character {} in selector list", c)
+                c => panic!("Unexpected character {} in @page selector", c),
+            }
+        }
+        self.expect_char('{');
+        let mut declarations = Vec::new();
+        loop {
+            self.consume_whitespace();
+            if self.eof() || self.next_char() == '}' { break }
+            match self.next_char() {
+                ';' => { self.consume_char(); }
+                '@' => {
+                    self.consume_char();
+                    self.parse_identifier();
+                    self.skip_at_rule();
+                }
+                _ => {
+                    if let Some(declaration) = self.parse_declaration_or_skip() {
+                        let longhands = shorthands::expand(declaration);
+                        declarations.extend(longhands.into_iter().filter(|declaration| {
+                            is_page_descriptor(&declaration.name)
+                                || properties::is_valid(declaration)
+                        }));
+                    }
+                }
+            }
+        }
+        self.expect_char('}');
+        AtRule::Page(selectors, declarations)
+    }
+
+    /// Parse the rest of a `@keyframes` rule.
+    ///
+    /// https://www.w3.org/TR/css-animations-1/#keyframes
+    fn parse_keyframes_rule(&mut self) -> ParseResult<AtRule> {
+        let name = match self.peek()? {
+            '"' | '\'' => self.parse_string(),
+            _ => self.parse_identifier(),
+        };
+        self.consume_whitespace();
+        self.expect('{')?;
+        let mut keyframes = Vec::new();
+        loop {
+            self.consume_whitespace();
+            if self.eof() || self.next_char() == '}' { break }
+            let mut offsets = Vec::new();
+            loop {
+                self.consume_whitespace();
+                offsets.push(match self.peek()? {
+                    '0'..='9' | '.' | '+' | '-' => match self.parse_length()? {
+                        Value::Length(f, Unit::Percent) if (0.0..=100.0).contains(&f) => f / 100.0,
+                        _ => {
+                            let message = format!("Expected a keyframe percentage from 0% to \
+                                                   100% at byte {}", self.pos);
+                            return Err(ParseError(message));
+                        }
+                    },
+                    _ => match &*self.parse_identifier().to_ascii_lowercase() {
+                        "from" => 0.0,
+                        "to" => 1.0,
+                        name => {
+                            let message = format!("Unexpected keyframe selector {:?}", name);
+                            return Err(ParseError(message));
+                        }
+                    }
+                });
+                self.consume_whitespace();
+                if self.peek()? != ',' { break }
+                self.consume_char();
+            }
+            // `!important` declarations are ignored in keyframes.
+            let mut declarations = self.parse_declarations();
+            declarations.retain(|declaration| !declaration.important);
+            keyframes.push(Keyframe { offsets, declarations });
+        }
+        self.expect('}')?;
+        Ok(AtRule::Keyframes(name, keyframes))
+    }
+
+    /// Parse the rest of a `@layer` statement (`@layer a, b;`) or block (`@layer a { ... }`),
+    /// declaring its layers inside the current layer.
+    ///
+    /// https://www.w3.org/TR/css-cascade-5/#layering
+    fn parse_layer_rule(&mut self, context: &RuleContext) -> ParseResult<Vec<Rule>> {
+        let mut names = Vec::new();
+        // A block can leave out the name, but a statement needs at least one.
+        let anonymous = !self.eof() && self.next_char() == '{';
+        if !anonymous {
+            loop {
+                let mut name = self.parse_identifier();
+                while !self.eof() && self.next_char() == '.' {
+                    self.consume_char();
+                    name.push('.');
+                    name.push_str(&self.parse_identifier());
+                }
+                if name.split('.').any(str::is_empty) {
+                    return Err(ParseError(format!("Invalid layer name at byte {}", self.pos)));
+                }
+                names.push(name);
+                self.consume_whitespace();
+                if self.eof() || matches!(self.next_char(), ';' | '{') { break }
+                self.expect(',')?;
+                self.consume_whitespace();
+            }
+        }
+        let full_name = |name: &str| match context.layer {
+            Some(ref parent) => format!("{}.{}", parent, name),
+            None => name.to_string(),
+        };
+
+        if self.eof() || self.next_char() == ';' {
+            // A statement just declares the order of its layers.
+            if !self.eof() {
+                self.consume_char();
+            }
+            for name in names {
+                self.declare_layer(&full_name(&name));
+            }
+            return Ok(Vec::new());
+        }
+
+        let layer = match &names[..] {
+            [] => {
+                self.anonymous_layers += 1;
+                full_name(&format!("<anonymous-{}>", self.anonymous_layers - 1))
+            }
+            [name] => full_name(name),
+            _ => return Err(ParseError("A @layer block must have at most one name".to_string())),
+        };
+        self.declare_layer(&layer);
+        let context = RuleContext { layer: Some(layer), nested: true, ..context.clone() };
+        self.expect_char('{');
+        let rules = self.parse_rules(&context);
+        self.expect_char('}');
+        Ok(rules)
+    }
+
+    /// Parse the rest of a `@media` block.  Its rules only apply when the media query list
+    /// matches the device.
+    ///
+    /// https://www.w3.org/TR/css-conditional-3/#at-media
+    fn parse_media_rule(&mut self, context: &RuleContext) -> Vec<Rule> {
+        let mut context = RuleContext { nested: true, ..context.clone() };
+        context.media.push(Arc::new(self.parse_media_query_list()));
+        self.expect_char('{');
+        let rules = self.parse_rules(&context);
+        self.expect_char('}');
+        rules
+    }
+
+    /// Parse a comma-separated list of media queries, up to the `{` that starts a block.  A query
+    /// that can't be parsed is replaced by `not all`, which never matches.
+    ///
+    /// https://www.w3.org/TR/mediaqueries-5/#mq-syntax
+    /// https://www.w3.org/TR/mediaqueries-5/#error-handling
+    fn parse_media_query_list(&mut self) -> MediaQueryList {
+        let mut queries = Vec::new();
+        loop {
+            self.consume_whitespace();
+            if self.eof() || self.next_char() == '{' { break }
+            let query = self.parse_media_query().and_then(|query| {
+                self.consume_whitespace();
+                match self.peek()? {
+                    ',' | '{' => Ok(query),
+                    c => Err(ParseError(format!("Unexpected character {} in media query", c))),
+                }
+            });
+            queries.push(query.unwrap_or_else(|_| {
+                self.skip_media_query();
+                MediaQuery { negated: true, media_type: MediaType::All, condition: None }
+            }));
+            if !self.eof() && self.next_char() == ',' {
+                self.consume_char();
+            }
+        }
+        MediaQueryList(queries)
+    }
+
+    /// Skip the rest of a media query that can't be parsed, up to the `,` or `{` after it.
+    fn skip_media_query(&mut self) {
+        let mut depth = 0;
+        while !self.eof() {
+            match self.next_char() {
+                ',' | '{' if depth == 0 => return,
+                '(' | '[' => depth += 1,
+                ')' | ']' if depth > 0 => depth -= 1,
+                _ => {}
+            }
+            self.consume_char();
+        }
+    }
+
+    /// Parse a media query: a condition like `(width > 600px)`, or a media type with an optional
+    /// condition like `not print and (color)`.
+    fn parse_media_query(&mut self) -> ParseResult<MediaQuery> {
+        if self.starts_condition() {
+            let condition = Some(self.parse_media_condition(true)?);
+            return Ok(MediaQuery { negated: false, media_type: MediaType::All, condition });
+        }
+        let negated = self.consume_keyword("not");
+        if !negated {
+            self.consume_keyword("only");
+        }
+        self.consume_whitespace();
+        let media_type = match &*self.parse_identifier().to_ascii_lowercase() {
+            "all" => MediaType::All,
+            "screen" => MediaType::Screen,
+            "print" => MediaType::Print,
+            "" => return Err(ParseError(format!("Expected a media type at byte {}", self.pos))),
+            other => MediaType::Unknown(other.to_string()),
+        };
+        self.consume_whitespace();
+        // After a media type, conditions can't be combined with `or`.
+        let condition = if self.consume_keyword("and") {
+            self.consume_whitespace();
+            Some(self.parse_media_condition(false)?)
+        } else {
+            None
+        };
+        Ok(MediaQuery { negated, media_type, condition })
+    }
+
+    /// Does a condition like `(...)` or `not (...)` start here?  In a media query, this means
+    /// there is no media type.
+    fn starts_condition(&mut self) -> bool {
+        let start = self.pos;
+        if self.consume_keyword("not") {
+            self.consume_whitespace();
+        }
+        let result = !self.eof() && self.next_char() == '(';
+        self.pos = start;
+        result
+    }
+
+    /// Parse `not <condition>`, or conditions joined by `and`, or by `or` if `allow_or` is true.
+    fn parse_media_condition(&mut self, allow_or: bool) -> ParseResult<MediaCondition> {
+        if self.consume_keyword("not") {
+            self.consume_whitespace();
+            return Ok(MediaCondition::Not(Box::new(self.parse_media_in_parens()?)));
+        }
+        let first = self.parse_media_in_parens()?;
+        self.consume_whitespace();
+        let combinator = if self.consume_keyword("and") {
+            "and"
+        } else if allow_or && self.consume_keyword("or") {
+            "or"
+        } else {
+            return Ok(first);
+        };
+        let mut conditions = vec![first];
+        loop {
+            self.consume_whitespace();
+            conditions.push(self.parse_media_in_parens()?);
+            self.consume_whitespace();
+            if !self.consume_keyword(combinator) { break }
+        }
+        Ok(match combinator {
+            "and" => MediaCondition::And(conditions),
+            _ => MediaCondition::Or(conditions),
+        })
+    }
+
+    /// Parse a media feature or a nested condition, in parentheses.
+    fn parse_media_in_parens(&mut self) -> ParseResult<MediaCondition> {
+        self.expect('(')?;
+        self.consume_whitespace();
+        let condition = if self.starts_condition() {
+            self.parse_media_condition(true)?
+        } else {
+            MediaCondition::Feature(self.parse_media_feature()?)
+        };
+        self.consume_whitespace();
+        self.expect(')')?;
+        Ok(condition)
+    }
+
+    /// Parse the inside of a media feature: `color`, `min-width: 40em`, `width > 600px`, or
+    /// `400px <= width < 700px`.
+    ///
+    /// https://www.w3.org/TR/mediaqueries-5/#mq-features
+    fn parse_media_feature(&mut self) -> ParseResult<MediaFeature> {
+        let mut comparisons = Vec::new();
+        if self.starts_media_number() {
+            let value = self.parse_media_value()?;
+            self.consume_whitespace();
+            // `value < name` is the same as `name > value`.
+            comparisons.push((self.parse_comparison()?.flip(), value));
+            self.consume_whitespace();
+        }
+        let name = self.parse_identifier().to_ascii_lowercase();
+        if name.is_empty() {
+            return Err(ParseError(format!("Expected a media feature at byte {}", self.pos)));
+        }
+        self.consume_whitespace();
+        if comparisons.is_empty() && self.peek()? == ':' {
+            self.consume_char();
+            self.consume_whitespace();
+            let value = self.parse_media_value()?;
+            let (name, comparison) = if let Some(name) = name.strip_prefix("min-") {
+                (name.to_string(), Comparison::Ge)
+            } else if let Some(name) = name.strip_prefix("max-") {
+                (name.to_string(), Comparison::Le)
+            } else {
+                (name, Comparison::Eq)
+            };
+            return Ok(MediaFeature { name, comparisons: vec![(comparison, value)] });
+        }
+        if matches!(self.peek()?, '<' | '>' | '=') {
+            let comparison = self.parse_comparison()?;
+            self.consume_whitespace();
+            comparisons.push((comparison, self.parse_media_value()?));
+        }
+        Ok(MediaFeature { name, comparisons })
+    }
+
+    /// Parse `<`, `<=`, `=`, `>=`, or `>`.
+    fn parse_comparison(&mut self) -> ParseResult<Comparison> {
+        let comparison = match self.peek()? {
+            '<' => Comparison::Lt,
+            '>' => Comparison::Gt,
+            '=' => {
+                self.consume_char();
+                return Ok(Comparison::Eq);
             }
+            c => return Err(ParseError(format!("Unexpected character {} in media feature", c))),
+        };
+        self.consume_char();
+        if !self.eof() && self.next_char() == '=' {
+            self.consume_char();
+            return Ok(match comparison {
+                Comparison::Lt => Comparison::Le,
+                _ => Comparison::Ge,
+            });
+        }
+        Ok(comparison)
+    }
+
+    /// Does a number start here?
+    fn starts_media_number(&self) -> bool {
+        match self.peek() {
+            Ok('0'..='9' | '.') => true,
+            Ok('+' | '-') => self.starts_with_number(),
+            _ => false,
+        }
+    }
+
+    /// Parse the value in a media feature: a keyword, number, length, or resolution.
+    fn parse_media_value(&mut self) -> ParseResult<MediaValue> {
+        if !self.starts_media_number() {
+            let ident = self.parse_identifier().to_ascii_lowercase();
+            if ident.is_empty() {
+                return Err(ParseError(format!("Expected a media value at byte {}", self.pos)));
+            }
+            return Ok(MediaValue::Ident(ident));
+        }
+        let value = self.parse_float()?;
+        let start = self.pos;
+        Ok(match &*self.parse_identifier().to_ascii_lowercase() {
+            "" if self.eof() || self.next_char() != '%' => MediaValue::Number(value),
+            "dppx" | "x" => MediaValue::Resolution(value),
+            "dpi" => MediaValue::Resolution(value / 96.0),
+            "dpcm" => MediaValue::Resolution(value * 2.54 / 96.0),
+            // Relative lengths in media queries are based on the initial value of `font-size`.
+            "em" | "rem" => MediaValue::Length(value * DEFAULT_FONT_SIZE),
+            _ => {
+                self.pos = start;
+                let unit = self.parse_unit()?;
+                match unit.absolute_size() {
+                    Some(size) => MediaValue::Length(value * size),
+                    None => {
+                        let message = format!("Unsupported unit {:?} in media query", unit);
+                        return Err(ParseError(message));
+                    }
+                }
+            }
+        })
+    }
+
+    /// If the identifier `keyword` (in any case) is next in the input, consume it and return true.
+    fn consume_keyword(&mut self, keyword: &str) -> bool {
+        let start = self.pos;
+        if self.parse_identifier().eq_ignore_ascii_case(keyword) {
+            return true;
+        }
+        self.pos = start;
+        false
+    }
+
+    /// Add a layer and any of its ancestors that are not yet declared.
+    fn declare_layer(&mut self, name: &str) {
+        let ends = name.match_indices('.').map(|(i, _)| i).chain(Some(name.len()));
+        for end in ends {
+            if !self.layers.iter().any(|layer| *layer == name[..end]) {
+                self.layers.push(name[..end].to_string());
+            }
+        }
+    }
+
+    /// Parse a comma-separated list of selectors, up to the `{` or `)` after it.  In a nested
+    /// rule, `parent` holds the selectors of the parent rule.
+    fn parse_selectors(&mut self, parent: Option<&[Selector]>) -> ParseResult<Vec<Selector>> {
+        let mut selectors = Vec::new();
+        loop {
+            selectors.push(self.parse_selector(parent)?);
+            if self.eof() || self.next_char() != ',' { break }
+            self.consume_char();
+            self.consume_whitespace();
         }
         // Return selectors with highest specificity first, for use in matching.
         selectors.sort_by_key(|s| s.specificity());
-        selectors
+        Ok(selectors)
+    }
+
+    /// Parse a sequence of simple selectors separated by combinators, e.g.: `ul > li .item`
+    fn parse_selector(&mut self, parent: Option<&[Selector]>) -> ParseResult<Selector> {
+        let mut uses_parent = false;
+        // In a nested rule, a selector can start with a combinator, relative to the parent.
+        let relative_child = parent.is_some() && self.peek()? == '>';
+        if relative_child {
+            self.consume_char();
+            self.consume_whitespace();
+        }
+        let mut selector = Selector::Simple(self.parse_simple_selector(parent, &mut uses_parent)?);
+        loop {
+            self.consume_whitespace();
+            if self.eof() { break }
+            match self.next_char() {
+                ',' | '{' | ')' => break,
+                '>' => {
+                    self.consume_char();
+                    self.consume_whitespace();
+                    let simple = self.parse_simple_selector(parent, &mut uses_parent)?;
+                    selector = Selector::Child(Box::new(selector), simple);
+                }
+                c if valid_identifier_char(c) || matches!(c, '#' | '.' | '*' | ':' | '&') => {
+                    let simple = self.parse_simple_selector(parent, &mut uses_parent)?;
+                    selector = Selector::Descendant(Box::new(selector), simple);
+                }
+                c => return Err(ParseError(format!("Unexpected character {} in selector", c))),
+            }
+        }
+        Ok(match parent {
+            // A nested selector without `&` is relative to its parent, as if it started with `&`.
+            Some(parent) if !uses_parent => {
+                let mut nesting = SimpleSelector::default();
+                nesting.add_nesting_selector(parent);
+                selector.prepend(nesting, relative_child)
+            }
+            _ => selector,
+        })
     }
 
-    /// Parse one simple selector, e.g.: `type#id.class1.class2.class3`
-    fn parse_simple_selector(&mut self) -> SimpleSelector {
-        let mut selector = SimpleSelector { tag_name: None, id: None, class: Vec::new() };
+    /// Parse one simple selector, e.g.: `type#id.class1.class2.class3`.  If it contains the
+    /// nesting selector `&`, sets `uses_parent` to true.
+    fn parse_simple_selector(&mut self, parent: Option<&[Selector]>,
+                             uses_parent: &mut bool) -> ParseResult<SimpleSelector> {
+        let start = self.pos;
+        let mut selector = SimpleSelector::default();
         while !self.eof() {
             match self.next_char() {
                 '#' => {
@@ -144,82 +1318,563 @@ impl Parser {
                     // universal selector
                     self.consume_char();
                 }
+                '&' => {
+                    self.consume_char();
+                    match parent {
+                        Some(parent) => selector.add_nesting_selector(parent),
+                        // Outside a nested rule, `&` is the same as `:scope`, which is the root
+                        // element.
+                        None => selector.pseudo_classes.push(PseudoClass::Root),
+                    }
+                    *uses_parent = true;
+                }
+                ':' => self.parse_pseudo(&mut selector)?,
                 c if valid_identifier_char(c) => {
                     selector.tag_name = Some(self.parse_identifier());
                 }
                 _ => break
             }
         }
-        selector
+        // Something like `a > > b`, or a selector list with an empty item.
+        if self.pos == start {
+            return Err(ParseError(format!("Expected a selector at byte {}", self.pos)));
+        }
+        Ok(selector)
+    }
+
+    /// Parse a pseudo-class, or one of the pseudo-elements `::before`, `::after`, or `::marker`.
+    /// The legacy single-colon syntax for pseudo-elements is also accepted.
+    fn parse_pseudo(&mut self, selector: &mut SimpleSelector) -> ParseResult<()> {
+        self.expect(':')?;
+        let double_colon = self.peek()? == ':';
+        if double_colon {
+            self.consume_char();
+        }
+        let name = self.parse_identifier().to_ascii_lowercase();
+        selector.pseudo_element = Some(match &*name {
+            "before" => PseudoElement::Before,
+            "after" => PseudoElement::After,
+            "marker" if double_colon => PseudoElement::Marker,
+            _ if double_colon => {
+                return Err(ParseError(format!("Unsupported pseudo-element {}", name)));
+            }
+            _ => {
+                selector.pseudo_classes.push(self.parse_pseudo_class(&name)?);
+                return Ok(());
+            }
+        });
+        Ok(())
+    }
+
+    /// Parse the rest of a pseudo-class, after its name.
+    ///
+    /// https://www.w3.org/TR/selectors-4/#pseudo-classes
+    fn parse_pseudo_class(&mut self, name: &str) -> ParseResult<PseudoClass> {
+        let is_function = !self.eof() && self.next_char() == '(';
+        Ok(match name {
+            "is" | "where" if is_function => {
+                self.consume_char();
+                self.consume_whitespace();
+                let selectors = self.parse_selectors(None)?;
+                self.expect(')')?;
+                match name {
+                    "is" => PseudoClass::Is(selectors),
+                    _ => PseudoClass::Where(selectors),
+                }
+            }
+            "root" => PseudoClass::Root,
+            "hover" | "active" | "focus" | "focus-visible" | "focus-within" | "visited" |
+            "target" => PseudoClass::UserAction,
+            _ => return Err(ParseError(format!("Unsupported pseudo-class {}", name))),
+        })
     }
 
     /// Parse a list of declarations enclosed in `{ ... }`.
     fn parse_declarations(&mut self) -> Vec<Declaration> {
         self.expect_char('{');
+        let declarations = self.parse_declaration_list();
+        self.expect_char('}');
+        declarations
+    }
+
+    /// Parse declarations up to the end of the input or a closing `}`.
+    fn parse_declaration_list(&mut self) -> Vec<Declaration> {
         let mut declarations = Vec::new();
         loop {
             self.consume_whitespace();
-            if self.next_char() == '}' {
+            if self.eof() || self.next_char() == '}' { break }
+            if self.next_char() == ';' {
                 self.consume_char();
-                break;
+                continue;
+            }
+            if let Some(declaration) = self.parse_declaration_or_skip() {
+                let longhands = shorthands::expand(declaration);
+                declarations.extend(longhands.into_iter().filter(properties::is_valid));
             }
-            declarations.push(self.parse_declaration());
         }
         declarations
     }
 
-    /// Parse one `<property>: <value>;` declaration.
-    fn parse_declaration(&mut self) -> Declaration {
+    /// Parse a declaration, or skip to its end and return `None` if it can't be parsed, like
+    /// `color: #12` or `width: 10deg`.  Invalid declarations are ignored, like those for unknown
+    /// properties.
+    ///
+    /// https://www.w3.org/TR/css-syntax-3/#consume-list-of-declarations
+    fn parse_declaration_or_skip(&mut self) -> Option<Declaration> {
+        match self.parse_declaration() {
+            Ok(declaration) => Some(declaration),
+            Err(_) => {
+                self.consume_raw_value();
+                if !self.eof() && self.next_char() == ';' {
+                    self.consume_char();
+                }
+                None
+            }
+        }
+    }
+
+    /// Parse one `<property>: <value>;` declaration.  The semicolon is optional for the last
+    /// declaration in a block.
+    ///
+    /// Custom properties, and any declarations that use `var()`, are kept as unparsed text until
+    /// their variables are substituted at computed-value time.
+    fn parse_declaration(&mut self) -> ParseResult<Declaration> {
         let name = self.parse_identifier();
         self.consume_whitespace();
-        self.expect_char(':');
-        self.consume_whitespace();
-        let value = self.parse_value();
+        self.expect(':')?;
         self.consume_whitespace();
-        self.expect_char(';');
 
-        Declaration { name, value }
+        let source = self.consume_raw_value();
+        let (source, important) = strip_important(&source);
+        // `unicode-range` values, like `U+0-7F`, aren't made of tokens this parser understands.
+        let unparsed = name.starts_with("--") || name.eq_ignore_ascii_case("unicode-range");
+        let value = if unparsed || variables::contains_var(source) {
+            Value::Unparsed(source.to_string())
+        } else {
+            Parser::new(source.to_string()).parse_value_list()?
+        };
+        if !self.eof() && self.next_char() == ';' {
+            self.consume_char();
+        }
+
+        Ok(Declaration { name, value, important })
+    }
+
+    /// Consume the source text of a declaration value, up to the `;` or `}` that ends it.
+    fn consume_raw_value(&mut self) -> String {
+        let start = self.pos;
+        let mut depth = 0;
+        while !self.eof() {
+            if self.consume_comment() {
+                continue;
+            }
+            match self.next_char() {
+                '"' | '\'' => {
+                    self.parse_string();
+                    continue;
+                }
+                '(' | '[' | '{' => depth += 1,
+                ';' | '}' if depth == 0 => break,
+                ')' | ']' | '}' => depth -= 1,
+                _ => {}
+            }
+            self.consume_char();
+        }
+        self.input[start..self.pos].trim().to_string()
     }
 
     // Methods for parsing values:
 
-    fn parse_value(&mut self) -> Value {
-        match self.next_char() {
-            '0'..='9' => self.parse_length(),
-            '#' => self.parse_color(),
-            _ => Value::Keyword(self.parse_identifier())
+    /// Parse the values up to the end of a declaration.  Values are separated by spaces, and
+    /// optionally grouped by commas.
+    fn parse_value_list(&mut self) -> ParseResult<Value> {
+        let mut groups = Vec::new();
+        let mut values = Vec::new();
+        self.consume_whitespace();
+        while !self.eof() && !matches!(self.next_char(), ';' | '}') {
+            if self.next_char() == ',' {
+                self.consume_char();
+                groups.push(Value::from_list(std::mem::take(&mut values)));
+            } else {
+                values.push(self.parse_value()?);
+            }
+            self.consume_whitespace();
         }
+        let last = Value::from_list(values);
+        if groups.is_empty() {
+            return Ok(last);
+        }
+        groups.push(last);
+        Ok(Value::CommaList(groups))
     }
 
-    fn parse_length(&mut self) -> Value {
-        Value::Length(self.parse_float(), self.parse_unit())
+    fn parse_value(&mut self) -> ParseResult<Value> {
+        Ok(match self.peek()? {
+            '0'..='9' | '.' => self.parse_length()?,
+            '-' | '+' if self.starts_with_number() => self.parse_length()?,
+            '#' => self.parse_color()?,
+            '"' | '\'' => Value::Str(self.parse_string()),
+            '/' => {
+                self.consume_char();
+                Value::Delim('/')
+            }
+            c => {
+                let name = self.parse_identifier();
+                if name.is_empty() {
+                    return Err(ParseError(format!("Unexpected character {} in value", c)));
+                }
+                let is_function = !self.eof() && self.next_char() == '(';
+                match &*name.to_ascii_lowercase() {
+                    "url" if is_function => self.parse_url()?,
+                    "rgb" | "rgba" | "hsl" | "hsla" | "hwb" | "lab" | "lch" | "oklab" | "oklch"
+                        if is_function => self.parse_color_function(&name.to_ascii_lowercase())?,
+                    "calc" | "min" | "max" | "clamp" if is_function => {
+                        let name = name.to_ascii_lowercase();
+                        Value::Calc(Box::new(self.parse_math_function(&name)?))
+                    }
+                    _ if is_function => self.parse_function(name)?,
+                    "currentcolor" => Value::Keyword("currentcolor".to_string()),
+                    lower => match color::named_color(lower) {
+                        Some(color) => Value::ColorValue(color),
+                        None => Value::Keyword(name)
+                    }
+                }
+            }
+        })
     }
 
-    fn parse_float(&mut self) -> f32 {
-        self.consume_while(|c| matches!(c, '0'..='9' | '.')).parse().unwrap()
+    /// Parse a length, or a plain number if there is no unit.
+    fn parse_length(&mut self) -> ParseResult<Value> {
+        let value = self.parse_float()?;
+        if self.eof() || !(valid_identifier_char(self.next_char()) || self.next_char() == '%') {
+            return Ok(Value::Number(value));
+        }
+        Ok(Value::Length(value, self.parse_unit()?))
     }
 
-    fn parse_unit(&mut self) -> Unit {
-        match &*self.parse_identifier().to_ascii_lowercase() {
-            "px" => Unit::Px,
-            _ => panic!("unrecognized unit")
+    fn parse_float(&mut self) -> ParseResult<f32> {
+        let sign = match self.peek()? {
+            '-' => { self.consume_char(); -1.0 }
+            '+' => { self.consume_char(); 1.0 }
+            _ => 1.0
+        };
+        let mut digits = self.consume_while(|c| matches!(c, '0'..='9' | '.'));
+        // An exponent, like `1e3`, but not the start of a unit like `1em`.
+        let rest = &self.input.as_bytes()[self.pos..];
+        let exponent = match rest {
+            [b'e' | b'E', b'+' | b'-', b'0'..=b'9', ..] => 2,
+            [b'e' | b'E', b'0'..=b'9', ..] => 1,
+            _ => 0,
+        };
+        if exponent > 0 {
+            digits.push('e');
+            self.pos += 1;
+            if exponent == 2 {
+                digits.push(self.consume_char());
+            }
+            digits.push_str(&self.consume_while(|c| c.is_ascii_digit()));
+        }
+        match digits.parse::<f32>() {
+            Ok(value) => Ok(sign * value),
+            Err(_) => Err(ParseError(format!("Invalid number {:?}", digits))),
+        }
+    }
+
+    /// Does the input start with a signed number like `-1` or `+.5`?
+    fn starts_with_number(&self) -> bool {
+        let mut chars = self.input[self.pos..].chars().skip(1);
+        matches!(chars.next(), Some('0'..='9' | '.'))
+    }
+
+    /// Parse the arguments of `calc()`, `min()`, `max()`, or `clamp()`.
+    ///
+    /// https://www.w3.org/TR/css-values-4/#calc-syntax
+    fn parse_math_function(&mut self, name: &str) -> ParseResult<CalcNode> {
+        self.expect('(')?;
+        let mut args = Vec::new();
+        loop {
+            self.consume_whitespace();
+            args.push(self.parse_calc_sum()?);
+            self.consume_whitespace();
+            match self.peek()? {
+                ',' => {}
+                ')' => break,
+                c => return Err(ParseError(format!("Unexpected character {} in {}()", c, name))),
+            }
+            self.consume_char();
+        }
+        self.consume_char();
+        let node = match name {
+            "calc" if args.len() == 1 => args.remove(0),
+            "min" => CalcNode::Min(args),
+            "max" => CalcNode::Max(args),
+            "clamp" if args.len() == 3 => {
+                let max = args.pop().unwrap();
+                let value = args.pop().unwrap();
+                let min = args.pop().unwrap();
+                CalcNode::Clamp(Box::new(min), Box::new(value), Box::new(max))
+            }
+            _ => return Err(ParseError(format!("Wrong number of arguments to {}()", name)))
+        };
+        match node.calc_type() {
+            Some(_) => Ok(node),
+            None => Err(ParseError(format!("Mismatched types in {}()", name))),
+        }
+    }
+
+    /// Parse `<calc-product> [ [ '+' | '-' ] <calc-product> ]*`
+    fn parse_calc_sum(&mut self) -> ParseResult<CalcNode> {
+        let mut terms = vec![self.parse_calc_product()?];
+        loop {
+            self.consume_whitespace();
+            match self.peek()? {
+                '+' => {
+                    self.consume_char();
+                    self.consume_whitespace();
+                    terms.push(self.parse_calc_product()?);
+                }
+                '-' => {
+                    self.consume_char();
+                    self.consume_whitespace();
+                    terms.push(CalcNode::Negate(Box::new(self.parse_calc_product()?)));
+                }
+                _ => break
+            }
+        }
+        Ok(if terms.len() == 1 { terms.remove(0) } else { CalcNode::Sum(terms) })
+    }
+
+    /// Parse `<calc-value> [ [ '*' | '/' ] <calc-value> ]*`
+    fn parse_calc_product(&mut self) -> ParseResult<CalcNode> {
+        let mut factors = vec![self.parse_calc_value()?];
+        loop {
+            self.consume_whitespace();
+            match self.peek()? {
+                '*' => {
+                    self.consume_char();
+                    self.consume_whitespace();
+                    factors.push(self.parse_calc_value()?);
+                }
+                '/' => {
+                    self.consume_char();
+                    self.consume_whitespace();
+                    factors.push(CalcNode::Invert(Box::new(self.parse_calc_value()?)));
+                }
+                _ => break
+            }
+        }
+        Ok(if factors.len() == 1 { factors.remove(0) } else { CalcNode::Product(factors) })
+    }
+
+    /// Parse a number, a length, a parenthesized sum, or a nested math function.
+    fn parse_calc_value(&mut self) -> ParseResult<CalcNode> {
+        match self.peek()? {
+            '(' => {
+                self.consume_char();
+                self.consume_whitespace();
+                let sum = self.parse_calc_sum()?;
+                self.consume_whitespace();
+                self.expect(')')?;
+                Ok(sum)
+            }
+            '0'..='9' | '.' => self.parse_calc_number(),
+            '-' | '+' if self.starts_with_number() => self.parse_calc_number(),
+            _ => {
+                let name = self.parse_identifier().to_ascii_lowercase();
+                match &*name {
+                    "calc" | "min" | "max" | "clamp" => self.parse_math_function(&name),
+                    _ => Err(ParseError(format!("Unexpected {:?} in math function", name)))
+                }
+            }
         }
     }
 
-    fn parse_color(&mut self) -> Value {
-        self.expect_char('#');
-        Value::ColorValue(Color {
-            r: self.parse_hex_pair(),
-            g: self.parse_hex_pair(),
-            b: self.parse_hex_pair(),
-            a: 255 })
+    fn parse_calc_number(&mut self) -> ParseResult<CalcNode> {
+        Ok(match self.parse_length()? {
+            Value::Number(n) => CalcNode::Number(n),
+            Value::Length(f, unit) => CalcNode::Length(f, unit),
+            _ => unreachable!()
+        })
+    }
+
+    /// Parse the argument of `url()`, which may be quoted or unquoted.
+    fn parse_url(&mut self) -> ParseResult<Value> {
+        self.expect('(')?;
+        self.consume_whitespace();
+        let url = match self.peek()? {
+            '"' | '\'' => self.parse_string(),
+            _ => self.consume_while(|c| c != ')' && !c.is_whitespace()),
+        };
+        self.consume_whitespace();
+        self.expect(')')?;
+        Ok(Value::Function("url".to_string(), vec![Value::Str(url)]))
+    }
+
+    /// Parse the comma-separated arguments of a function, e.g. `counter(item, decimal)`.  An
+    /// argument can be a space-separated list, like the font name in `local(DejaVu Sans)`.
+    fn parse_function(&mut self, name: String) -> ParseResult<Value> {
+        self.expect('(')?;
+        let mut args = Vec::new();
+        loop {
+            self.consume_whitespace();
+            if self.peek()? == ')' {
+                self.consume_char();
+                break;
+            }
+            let mut values = Vec::new();
+            while !matches!(self.peek()?, ',' | ')') {
+                values.push(self.parse_value()?);
+                self.consume_whitespace();
+            }
+            args.push(Value::from_list(values));
+            if self.consume_char() == ')' {
+                break;
+            }
+        }
+        Ok(Value::Function(name.to_ascii_lowercase(), args))
+    }
+
+    /// Parse a single- or double-quoted string, processing backslash escapes.  A string that
+    /// isn't closed ends at the end of the input.
+    fn parse_string(&mut self) -> String {
+        let quote = self.consume_char();
+        let mut result = String::new();
+        while !self.eof() {
+            match self.consume_char() {
+                c if c == quote => break,
+                '\\' => {
+                    let hex = self.consume_while_max(6, |c| c.is_ascii_hexdigit());
+                    if !hex.is_empty() {
+                        let code = u32::from_str_radix(&hex, 16).unwrap();
+                        result.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+                        // A single whitespace character after a hex escape is part of the escape.
+                        if !self.eof() && self.next_char().is_whitespace() {
+                            self.consume_char();
+                        }
+                    } else if !self.eof() {
+                        result.push(self.consume_char());
+                    }
+                }
+                c => result.push(c)
+            }
+        }
+        result
+    }
+
+    fn parse_unit(&mut self) -> ParseResult<Unit> {
+        if self.next_char() == '%' {
+            self.consume_char();
+            return Ok(Unit::Percent);
+        }
+        Ok(match &*self.parse_identifier().to_ascii_lowercase() {
+            "px" => Unit::Px,
+            "pt" => Unit::Pt,
+            "pc" => Unit::Pc,
+            "in" => Unit::In,
+            "cm" => Unit::Cm,
+            "mm" => Unit::Mm,
+            "q" => Unit::Q,
+            "em" => Unit::Em,
+            "rem" => Unit::Rem,
+            "ex" => Unit::Ex,
+            "ch" => Unit::Ch,
+            "vw" => Unit::Vw,
+            "vh" => Unit::Vh,
+            "vmin" => Unit::Vmin,
+            "vmax" => Unit::Vmax,
+            unit => return Err(ParseError(format!("unrecognized unit {}", unit)))
+        })
+    }
+
+    /// Parse a hex color: `#rgb`, `#rgba`, `#rrggbb`, or `#rrggbbaa`.
+    fn parse_color(&mut self) -> ParseResult<Value> {
+        self.expect('#')?;
+        let hex = self.consume_while(|c| c.is_ascii_hexdigit());
+        let digit = |i: usize| u8::from_str_radix(&hex[i..i + 1], 16).unwrap();
+        let pair = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Ok(Value::ColorValue(match hex.len() {
+            3 => Color { r: digit(0) * 17, g: digit(1) * 17, b: digit(2) * 17, a: 255 },
+            4 => Color { r: digit(0) * 17, g: digit(1) * 17, b: digit(2) * 17, a: digit(3) * 17 },
+            6 => Color { r: pair(0), g: pair(2), b: pair(4), a: 255 },
+            8 => Color { r: pair(0), g: pair(2), b: pair(4), a: pair(6) },
+            _ => return Err(ParseError(format!("Invalid hex color #{}", hex)))
+        }))
+    }
+
+    /// Parse the arguments of a color function like `rgb()`, `hsl()` or `oklch()`, in either the
+    /// legacy comma-separated syntax or the modern space-separated syntax with an optional
+    /// `/ alpha`.
+    ///
+    /// https://www.w3.org/TR/css-color-4/#color-syntax
+    fn parse_color_function(&mut self, name: &str) -> ParseResult<Value> {
+        self.expect('(')?;
+        let mut components = Vec::new();
+        let mut alpha = None;
+        loop {
+            self.consume_whitespace();
+            match self.peek()? {
+                ')' => { self.consume_char(); break; }
+                ',' => { self.consume_char(); }
+                '/' => {
+                    self.consume_char();
+                    self.consume_whitespace();
+                    alpha = Some(self.parse_color_component()?);
+                }
+                _ => components.push(self.parse_color_component()?)
+            }
+        }
+        // In the legacy syntax, alpha is just the fourth component.
+        if components.len() == 4 && alpha.is_none() {
+            alpha = components.pop();
+        }
+        let [x, y, z] = components[..] else {
+            return Err(ParseError(format!("Expected three components in {}()", name)))
+        };
+        let alpha = alpha.map_or(1.0, |a| a.resolve(1.0));
+
+        let (r, g, b) = match name {
+            "rgb" | "rgba" => (x.resolve(255.0) / 255.0, y.resolve(255.0) / 255.0,
+                               z.resolve(255.0) / 255.0),
+            "hsl" | "hsla" => color::hsl_to_rgb(x.resolve(0.0), y.resolve(100.0) / 100.0,
+                                                z.resolve(100.0) / 100.0),
+            "hwb" => color::hwb_to_rgb(x.resolve(0.0), y.resolve(100.0) / 100.0,
+                                       z.resolve(100.0) / 100.0),
+            "lab" => color::lab_to_rgb(x.resolve(100.0), y.resolve(125.0), z.resolve(125.0)),
+            "lch" => {
+                let (a, b) = color::polar_to_ab(y.resolve(150.0), z.resolve(0.0));
+                color::lab_to_rgb(x.resolve(100.0), a, b)
+            }
+            "oklab" => color::oklab_to_rgb(x.resolve(1.0), y.resolve(0.4), z.resolve(0.4)),
+            "oklch" => {
+                let (a, b) = color::polar_to_ab(y.resolve(0.4), z.resolve(0.0));
+                color::oklab_to_rgb(x.resolve(1.0), a, b)
+            }
+            _ => unreachable!()
+        };
+        Ok(Value::ColorValue(color::srgb(r, g, b, alpha)))
     }
 
-    /// Parse two hexadecimal digits.
-    fn parse_hex_pair(&mut self) -> u8 {
-        let s = &self.input[self.pos .. self.pos + 2];
-        self.pos += 2;
-        u8::from_str_radix(s, 16).unwrap()
+    /// Parse a number, percentage, angle, or `none` inside a color function.
+    fn parse_color_component(&mut self) -> ParseResult<ColorComponent> {
+        if !self.starts_with_number() && !matches!(self.next_char(), '0'..='9' | '.') {
+            return match &*self.parse_identifier().to_ascii_lowercase() {
+                "none" => Ok(ColorComponent::Number(0.0)),
+                other => Err(ParseError(format!("Unexpected {:?} in color function", other)))
+            };
+        }
+        let value = self.parse_float()?;
+        if !self.eof() && self.next_char() == '%' {
+            self.consume_char();
+            return Ok(ColorComponent::Percentage(value));
+        }
+        // Angles are converted to degrees.
+        Ok(ColorComponent::Number(match &*self.parse_identifier().to_ascii_lowercase() {
+            "" | "deg" => value,
+            "rad" => value.to_degrees(),
+            "grad" => value * 0.9,
+            "turn" => value * 360.0,
+            unit => return Err(ParseError(format!("Unexpected unit {} in color function", unit)))
+        }))
     }
 
     /// Parse a property name or keyword.
@@ -227,9 +1882,25 @@ impl Parser {
         self.consume_while(valid_identifier_char)
     }
 
-    /// Consume and discard zero or more whitespace characters.
+    /// Consume and discard zero or more whitespace characters and comments.
     fn consume_whitespace(&mut self) {
-        self.consume_while(char::is_whitespace);
+        loop {
+            self.consume_while(char::is_whitespace);
+            if !self.consume_comment() { break }
+        }
+    }
+
+    /// If a `/* comment */` starts at the current position, consume it and return true.
+    fn consume_comment(&mut self) -> bool {
+        if !self.input[self.pos..].starts_with("/*") {
+            return false;
+        }
+        self.pos = match self.input[self.pos + 2..].find("*/") {
+            Some(end) => self.pos + 2 + end + 2,
+            // An unclosed comment runs to the end of the input.
+            None => self.input.len(),
+        };
+        true
     }
 
     /// Consume characters until `test` returns false.
@@ -241,6 +1912,15 @@ impl Parser {
         result
     }
 
+    /// Consume at most `max` characters while `test` returns true.
+    fn consume_while_max(&mut self, max: usize, test: impl Fn(char) -> bool) -> String {
+        let mut result = String::new();
+        while result.len() < max && !self.eof() && test(self.next_char()) {
+            result.push(self.consume_char());
+        }
+        result
+    }
+
     /// Return the current character, and advance self.pos to the next character.
     fn consume_char(&mut self) -> char {
         let c = self.next_char();
@@ -251,9 +1931,17 @@ impl Parser {
     /// If the exact string `s` is found at the current position, consume it.
     /// Otherwise, panic.
     fn expect_char(&mut self, c: char) {
-        if self.consume_char() != c {
-            panic!("Expected {:?} at byte {} but it was not found", c, self.pos);
+        or_panic(self.expect(c))
+    }
+
+    /// If the character `c` is next, consume it.  Otherwise, return an error.
+    fn expect(&mut self, c: char) -> ParseResult<()> {
+        if self.peek()? != c {
+            return Err(ParseError(format!("Expected {:?} at byte {} but it was not found", c,
+                                          self.pos)));
         }
+        self.consume_char();
+        Ok(())
     }
 
     /// Read the current character without consuming it.
@@ -261,13 +1949,245 @@ impl Parser {
         self.input[self.pos..].chars().next().unwrap()
     }
 
+    /// Read the current character without consuming it, or return an error at the end of the
+    /// input.
+    fn peek(&self) -> ParseResult<char> {
+        match self.input[self.pos..].chars().next() {
+            Some(c) => Ok(c),
+            None => Err(ParseError("Unexpected end of input".to_string())),
+        }
+    }
+
     /// Return true if all input is consumed.
     fn eof(&self) -> bool {
         self.pos >= self.input.len()
     }
 }
 
+/// Does the engine support this `property: value` declaration, from an `@supports` condition?
+fn supports_declaration(source: &str) -> bool {
+    let mut parser = Parser::new(source.to_string());
+    let declaration = match parser.parse_declaration() {
+        Ok(declaration) if parser.eof() => declaration,
+        _ => return false,
+    };
+    let longhands = shorthands::expand(declaration);
+    !longhands.is_empty() && longhands.iter().all(|declaration| {
+        let known = declaration.name.starts_with("--")
+            || properties::lookup(&declaration.name).is_some();
+        known && properties::is_valid(declaration)
+    })
+}
+
+/// Does the engine support this selector, from a `selector()` function in an `@supports`
+/// condition?
+///
+/// https://www.w3.org/TR/css-conditional-4/#support-definition-ext
+fn supports_selector(source: &str) -> bool {
+    let mut parser = Parser::new(source.trim().to_string());
+    match parser.parse_selector(None) {
+        Ok(_) => parser.eof(),
+        Err(_) => false,
+    }
+}
+
+/// Is this the generated name of an anonymous layer, or of one of its sub-layers?
+pub fn is_anonymous_layer(layer: &str) -> bool {
+    layer.starts_with('<') || layer.contains(".<")
+}
+
+/// https://www.w3.org/TR/css-fonts-4/#font-face-rule
+fn is_font_descriptor(name: &str) -> bool {
+    matches!(name, "font-family" | "src" | "font-style" | "font-weight" | "font-stretch" |
+                   "font-display" | "unicode-range" | "font-feature-settings" |
+                   "font-variation-settings" | "ascent-override" | "descent-override" |
+                   "line-gap-override" | "size-adjust")
+}
+
+/// Descriptors of `@page` rules that aren't also properties.
+///
+/// https://www.w3.org/TR/css-page-3/#page-properties
+fn is_page_descriptor(name: &str) -> bool {
+    matches!(name, "size" | "page-orientation" | "marks" | "bleed")
+}
+
+/// Remove a trailing `!important` from the source text of a value.
+///
+/// https://www.w3.org/TR/css-cascade-5/#importance
+fn strip_important(source: &str) -> (&str, bool) {
+    let len = source.len();
+    if len >= "important".len() && source.is_char_boundary(len - "important".len()) {
+        let (rest, keyword) = source.split_at(len - "important".len());
+        if keyword.eq_ignore_ascii_case("important") {
+            if let Some(rest) = rest.trim_end().strip_suffix('!') {
+                return (rest.trim_end(), true);
+            }
+        }
+    }
+    (source, false)
+}
+
 fn valid_identifier_char(c: char) -> bool {
     // TODO: Include U+00A0 and higher.
     matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_')
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::values::{Context, LineHeight, PropertyType};
+
+    /// The media query lists of the rules in a stylesheet.
+    fn media(source: &str) -> Vec<Vec<Arc<MediaQueryList>>> {
+        parse(source.to_string()).rules.into_iter().map(|rule| rule.media).collect()
+    }
+
+    #[test]
+    fn invalid_media_queries_are_not_all() {
+        let not_all = MediaQuery { negated: true, media_type: MediaType::All, condition: None };
+        let media = media("@media (min-aspect-ratio: 16/9), print { a { color: red } } \
+                           @media (min-width: 10vw) { b { color: red } } \
+                           @media screen junk, (width >) { c { color: red } } \
+                           d { color: red }");
+        assert_eq!(media.len(), 4);
+        let MediaQueryList(queries) = &*media[0][0];
+        assert_eq!(queries[0], not_all);
+        assert_eq!(queries[1].media_type, MediaType::Print);
+        assert_eq!(*media[1][0], MediaQueryList(vec![not_all]));
+        assert_eq!(media[2][0].0.len(), 2);
+        assert!(media[2][0].0.iter().all(|query| query.negated && query.condition.is_none()));
+        assert!(media[3].is_empty());
+    }
+
+    #[test]
+    fn supports_conditions() {
+        let sheet = parse("@supports (transform: rotate(45deg)) { a { color: red } } \
+                           @supports (color: #12345) or (color: red) { b { color: red } } \
+                           @supports not (color: #12345) { c { color: red } } \
+                           @supports (display: grid) and (color: red junk) { d { color: red } } \
+                           @supports (color: red) and [junk] { e { color: red } } \
+                           @supports selector(a > .b) { f { color: red } } \
+                           @supports selector(a:first-child) or selector(a, b) { \
+                               h { color: red } \
+                           } \
+                           @supports not selector(a::foo) { i { color: red } } \
+                           g { color: red }".to_string());
+        let names: Vec<_> = sheet.rules.iter().map(|rule| rule.selectors[0].to_string()).collect();
+        assert_eq!(names, ["b", "c", "f", "i", "g"]);
+    }
+
+    #[test]
+    fn invalid_at_rules_are_dropped() {
+        let sheet = parse("@keyframes a { middle { color: red } } \
+                           @keyframes b { 150% { color: red } } \
+                           @keyframes c { -10% { color: red } } \
+                           @keyframes d { from { color: red } 50%, to { color: blue } } \
+                           @layer e f { p { color: red } } \
+                           @layer g, ; \
+                           @layer h.; \
+                           @layer i, j.k; \
+                           a { color: red }".to_string());
+        let keyframes: Vec<_> = sheet.at_rules.iter().map(|at_rule| match at_rule {
+            AtRule::Keyframes(name, keyframes) => {
+                (&**name, keyframes.iter().map(|k| k.offsets.clone()).collect::<Vec<_>>())
+            }
+            _ => panic!("unexpected at-rule {:?}", at_rule),
+        }).collect();
+        assert_eq!(keyframes, [("d", vec![vec![0.0], vec![0.5, 1.0]])]);
+        assert_eq!(sheet.layers, ["i", "j", "j.k"]);
+        let names: Vec<_> = sheet.rules.iter().map(|rule| rule.selectors[0].to_string()).collect();
+        assert_eq!(names, ["a"]);
+    }
+
+    #[test]
+    fn invalid_selectors_are_dropped() {
+        let sheet = parse("a:first-child { color: red } \
+                           b:nth-child(2) { color: red } \
+                           c:not(.x) { color: red } \
+                           d > > e { color: red } \
+                           f, , g { color: red } \
+                           h::foo { color: red } \
+                           i) { color: red } \
+                           j { color: red; k:hover { color: red } l:bogus { color: red } } \
+                           { color: red } \
+                           m { color: red }".to_string());
+        let names: Vec<_> = sheet.rules.iter().map(|rule| rule.selectors[0].to_string()).collect();
+        assert_eq!(names, ["j", "j k:hover", "m"]);
+    }
+
+    #[test]
+    fn invalid_colors_are_dropped() {
+        let source = "a { color: #12; background-color: #0f08; color: rgb(1 2); \
+                          border-top-color: rgb(0, 128, 0, 50%) } \
+                      b { color: hsl(120 100% 25% / 0.5); color: oklch(0.5 0.1) }";
+        let sheet = parse(source.to_string());
+        let declarations: Vec<Vec<_>> = sheet.rules.iter()
+            .map(|rule| rule.declarations.iter()
+                .map(|declaration| (&*declaration.name, &declaration.value))
+                .collect())
+            .collect();
+        let color = |r, g, b, a| Value::ColorValue(Color { r, g, b, a });
+        assert_eq!(declarations, [
+            vec![("background-color", &color(0, 255, 0, 136)),
+                 ("border-top-color", &color(0, 128, 0, 128))],
+            vec![("color", &color(0, 128, 0, 128))],
+        ]);
+    }
+
+    #[test]
+    fn invalid_units_are_dropped() {
+        let sheet = parse("a { width: 10deg; height: 1e3px; margin-top: 2E-1em; \
+                               foo: rotate(45deg); padding-top: 10px }".to_string());
+        let declarations: Vec<_> = sheet.rules[0].declarations.iter()
+            .map(|declaration| (&*declaration.name, &declaration.value))
+            .collect();
+        assert_eq!(declarations, [
+            ("height", &Value::Length(1000.0, Unit::Px)),
+            ("margin-top", &Value::Length(0.2, Unit::Em)),
+            ("padding-top", &Value::Length(10.0, Unit::Px)),
+        ]);
+    }
+
+    #[test]
+    fn invalid_shorthands_are_dropped() {
+        let sheet = parse("a { padding: 1px -2px; border: -1px solid red; border-width: 1px -1px; \
+                               padding-left: -1%; border-top-width: -3px; margin: 1px -2px; \
+                               padding-top: calc(-1px) } \
+                           @page { margin: 1in red; padding: -1px; size: a4 }".to_string());
+        let names = |declarations: &[Declaration]| -> Vec<String> {
+            declarations.iter().map(|declaration| declaration.name.clone()).collect()
+        };
+        assert_eq!(names(&sheet.rules[0].declarations),
+                   ["margin-top", "margin-right", "margin-bottom", "margin-left", "padding-top"]);
+        let AtRule::Page(_, ref declarations) = sheet.at_rules[0] else { panic!() };
+        assert_eq!(names(declarations), ["size"]);
+    }
+
+    #[test]
+    fn calc_types() {
+        let sheet = parse("a { width: calc(2); width: calc(10px * 10px); width: calc(1px + 2); \
+                               width: calc(1px / 1px); height: min(1px, 2); \
+                               width: calc(100% - 2 * (1em + 3px)); \
+                               line-height: calc(3 / 2) }".to_string());
+        let declarations: Vec<_> = sheet.rules[0].declarations.iter()
+            .map(|declaration| format!("{}: {}", declaration.name, declaration.value))
+            .collect();
+        assert_eq!(declarations, ["width: calc(100% - 2 * (1em + 3px))",
+                                  "line-height: calc(3 / 2)"]);
+        // A calculation that results in a number is a number, not a length.
+        let line_height = &sheet.rules[0].declarations[1].value;
+        assert_eq!(LineHeight::compute(line_height.clone(), &Context::initial()),
+                   Some(LineHeight::Number(1.5)));
+    }
+
+    #[test]
+    fn nested_declarations_stay_in_order() {
+        let sheet = parse(".a { color: red; & { color: blue } color: green; \
+                                @media screen { color: white; .b { color: black } } } \
+                           .a, #b { .c { color: red } } \
+                           .d {}".to_string());
+        assert_eq!(format!("{:#}", sheet), ".a{color:#f00}.a{color:#00f}.a{color:#008000}\
+                                           @media screen{.a{color:#fff}.a .b{color:#000}}\
+                                           :is(.a,#b) .c{color:#f00}");
+    }
+}
@@ -0,0 +1,1497 @@
+//! Fonts: reading TrueType and OpenType files, mapping text to glyphs, and measuring them.
+//!
+//! Only the tables needed to lay out and draw horizontal text are read: `head`, `hhea`, `maxp`,
+//! `cmap` and `hmtx` for metrics, `glyf` and `loca` or `CFF ` for outlines, and pair
+//! adjustments from `GPOS`, or the older `kern` table, for kerning.  Shaping is one glyph per
+//! character, with kerning between neighbours.  A font that is missing a required table, or is
+//! truncated, fails to parse; damage elsewhere just leaves glyphs without outlines or kerning.
+//!
+//! https://learn.microsoft.com/en-us/typography/opentype/spec/
+//! https://developer.apple.com/fonts/TrueType-Reference-Manual/
+
+use crate::values::FontStyle;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::sync::{Arc, OnceLock};
+
+/// A parsed font file.  Metrics are in font units, which `size / units_per_em` scales to px.
+pub struct Font {
+    data: Cow<'static, [u8]>,
+    /// The names of the family the font belongs to, like "DejaVu Sans", from the `name` table.
+    /// A font can have both a family name and a "typographic" one that groups more styles.
+    pub family_names: Vec<String>,
+    /// Names that identify this font alone, like "DejaVu Sans Bold", for `local()` sources.
+    pub full_names: Vec<String>,
+    /// The weight, style and stretch of the font, from the `OS/2` table, in the same terms as the
+    /// `font-weight`, `font-style` and `font-stretch` properties.
+    pub weight: f32,
+    pub style: FontStyle,
+    pub stretch: f32,
+    pub units_per_em: f32,
+    /// The distance from the baseline to the top of the line, from the `hhea` table.
+    pub ascender: f32,
+    /// The distance from the baseline to the bottom of the line.  Negative, below the baseline.
+    pub descender: f32,
+    /// Extra space recommended between lines.
+    pub line_gap: f32,
+    /// The height of lowercase letters, from the `OS/2` table or else the height of "x".
+    pub x_height: Option<f32>,
+    num_glyphs: u16,
+    /// The `cmap` subtable used to map characters to glyphs.
+    cmap: Option<usize>,
+    hmtx: Range<usize>,
+    num_h_metrics: u16,
+    outlines: Outlines,
+    kerning: Kerning,
+}
+
+/// A glyph, positioned relative to the start of a run of text.  `y` is the baseline.
+#[derive(Clone, Copy, Debug)]
+pub struct PositionedGlyph {
+    pub id: u16,
+    pub x: f32,
+    pub y: f32,
+}
+
+/// Glyphs from one font.
+#[derive(Clone, Debug)]
+pub struct GlyphRun {
+    pub font: Arc<Font>,
+    pub glyphs: Vec<PositionedGlyph>,
+}
+
+/// Text mapped to glyphs, which come from more than one font if the first choice is missing some
+/// characters.  Glyphs are positioned relative to the top left of the text's content area, which
+/// is as tall as the ascent and descent of the first font.
+#[derive(Clone, Debug, Default)]
+pub struct ShapedText {
+    pub text: String,
+    pub runs: Vec<GlyphRun>,
+    /// The advance width of the text in px.
+    pub width: f32,
+}
+
+impl ShapedText {
+    /// Add text to the end of this text.  No kerning is applied between them.
+    pub fn push(&mut self, other: ShapedText) {
+        self.text.push_str(&other.text);
+        for mut run in other.runs {
+            for glyph in &mut run.glyphs {
+                glyph.x += self.width;
+            }
+            match self.runs.last_mut() {
+                Some(last) if Arc::ptr_eq(&last.font, &run.font) => last.glyphs.extend(run.glyphs),
+                _ => self.runs.push(run),
+            }
+        }
+        self.width += other.width;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+/// A piece of a glyph outline.  Coordinates are in font units, with y pointing up.  Each contour
+/// starts with a `MoveTo` and ends with a `Close`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathSegment {
+    MoveTo(Point),
+    LineTo(Point),
+    /// A quadratic Bézier curve, with a control point, as used by TrueType outlines.
+    QuadTo(Point, Point),
+    /// A cubic Bézier curve, with two control points, as used by CFF outlines.
+    CurveTo(Point, Point, Point),
+    Close,
+}
+
+enum Outlines {
+    TrueType { glyf: Range<usize>, loca: Range<usize>, long_offsets: bool },
+    Cff(Cff),
+    None,
+}
+
+enum Kerning {
+    /// The pair adjustment subtables of each lookup used by the `kern` feature.
+    Gpos(Vec<Vec<usize>>),
+    /// Horizontal kerning subtables in format 0.
+    Kern(Vec<usize>),
+    None,
+}
+
+/// The font used when no other font is available: DejaVu Sans, which is included in the binary.
+pub fn default_font() -> &'static Arc<Font> {
+    static DEFAULT: OnceLock<Arc<Font>> = OnceLock::new();
+    DEFAULT.get_or_init(|| {
+        let data: &'static [u8] = include_bytes!("../fonts/DejaVuSans.ttf");
+        Arc::new(Font::parse(data).expect("invalid built-in font"))
+    })
+}
+
+impl Font {
+    /// Parse a TrueType or OpenType font file.  For a collection, this reads the first font.
+    /// Returns `None` if the data isn't a supported font.
+    pub fn parse(data: impl Into<Cow<'static, [u8]>>) -> Option<Font> {
+        let data = data.into();
+        let tables = table_directory(&data)?;
+        let table = |tag: &[u8; 4]| tables.get(tag).cloned();
+
+        let head = table(b"head")?.start;
+        let name = |ids: &[u16]| table(b"name").map_or(Vec::new(), |name| {
+            let mut names = Vec::new();
+            for &id in ids {
+                match read_name(&data, name.start, id) {
+                    Some(n) if !names.contains(&n) => names.push(n),
+                    _ => {}
+                }
+            }
+            names
+        });
+        // The typographic family name, if any, and then the legacy one.
+        let family_names = name(&[16, 1]);
+        // The full name and PostScript name.
+        let full_names = name(&[4, 6]);
+        // `sxHeight` was added in version 2 of the `OS/2` table.
+        let x_height = table(b"OS/2")
+            .filter(|os2| read_u16(&data, os2.start).is_some_and(|version| version >= 2))
+            .and_then(|os2| read_i16(&data, os2.start + 86))
+            .filter(|&x_height| x_height > 0)
+            .map(f32::from);
+        let (weight, style, stretch) = match table(b"OS/2") {
+            Some(os2) => {
+                const ITALIC: u16 = 0x1;
+                const OBLIQUE: u16 = 0x200;
+                let selection = read_u16(&data, os2.start + 62).unwrap_or(0);
+                let style = if selection & OBLIQUE != 0 {
+                    FontStyle::Oblique
+                } else if selection & ITALIC != 0 {
+                    FontStyle::Italic
+                } else {
+                    FontStyle::Normal
+                };
+                let width_class = read_u16(&data, os2.start + 6).unwrap_or(5);
+                let stretch = [50.0, 62.5, 75.0, 87.5, 100.0, 112.5, 125.0, 150.0, 200.0]
+                    .get(width_class.saturating_sub(1) as usize).copied().unwrap_or(100.0);
+                let weight = read_u16(&data, os2.start + 4).unwrap_or(400).clamp(1, 1000);
+                (weight as f32, style, stretch)
+            }
+            None => {
+                const MAC_BOLD: u16 = 0x1;
+                const MAC_ITALIC: u16 = 0x2;
+                let mac_style = read_u16(&data, head + 44)?;
+                let weight = if mac_style & MAC_BOLD != 0 { 700.0 } else { 400.0 };
+                let italic = mac_style & MAC_ITALIC != 0;
+                (weight, if italic { FontStyle::Italic } else { FontStyle::Normal }, 100.0)
+            }
+        };
+        let hhea = table(b"hhea")?.start;
+        let maxp = table(b"maxp")?.start;
+        let long_offsets = read_i16(&data, head + 50)? != 0;
+        let outlines = match (table(b"glyf"), table(b"loca"), table(b"CFF ")) {
+            (Some(glyf), Some(loca), _) => Outlines::TrueType { glyf, loca, long_offsets },
+            (_, _, Some(cff)) => Cff::parse(&data, cff).map_or(Outlines::None, Outlines::Cff),
+            _ => Outlines::None,
+        };
+        let gpos = table(b"GPOS").map_or(Vec::new(), |gpos| gpos_kerning(&data, gpos.start));
+        let kerning = match table(b"kern") {
+            _ if !gpos.is_empty() => Kerning::Gpos(gpos),
+            Some(kern) => Kerning::Kern(kern_subtables(&data, kern.start)),
+            None => Kerning::None,
+        };
+        let mut font = Font {
+            family_names,
+            full_names,
+            weight,
+            style,
+            stretch,
+            units_per_em: read_u16(&data, head + 18)?.max(1) as f32,
+            ascender: read_i16(&data, hhea + 4)? as f32,
+            descender: read_i16(&data, hhea + 6)? as f32,
+            line_gap: read_i16(&data, hhea + 8)? as f32,
+            x_height,
+            num_h_metrics: read_u16(&data, hhea + 34)?.max(1),
+            num_glyphs: read_u16(&data, maxp + 4)?,
+            cmap: choose_cmap(&data, table(b"cmap")?.start),
+            hmtx: table(b"hmtx")?,
+            outlines,
+            kerning,
+            data,
+        };
+        // Fonts without `sxHeight` still have an "x" to measure.
+        if font.x_height.is_none() {
+            font.x_height = font.glyph_top('x');
+        }
+        Some(font)
+    }
+
+    /// The height above the baseline of the top of a character's glyph, in font units.
+    fn glyph_top(&self, c: char) -> Option<f32> {
+        let glyph = self.glyph_index(c);
+        if glyph == 0 {
+            return None;
+        }
+        self.outline(glyph).iter().filter_map(|segment| match *segment {
+            PathSegment::MoveTo(p) | PathSegment::LineTo(p) => Some(p.y),
+            PathSegment::QuadTo(c, p) => Some(c.y.max(p.y)),
+            PathSegment::CurveTo(c1, c2, p) => Some(c1.y.max(c2.y).max(p.y)),
+            PathSegment::Close => None,
+        }).reduce(f32::max).filter(|&top| top > 0.0)
+    }
+
+    /// The number of px per font unit, at a font size in px.
+    pub fn scale(&self, size: f32) -> f32 {
+        size / self.units_per_em
+    }
+
+    /// The ascent of the font at this size, in px.
+    pub fn ascent(&self, size: f32) -> f32 {
+        self.ascender * self.scale(size)
+    }
+
+    /// The descent of the font at this size, in px, as a positive number.
+    pub fn descent(&self, size: f32) -> f32 {
+        -self.descender * self.scale(size)
+    }
+
+    /// The x-height of the font at this size, in px, for the `ex` unit.  If the font neither
+    /// gives its x-height nor has an "x", it is assumed to be half the size.
+    ///
+    /// https://www.w3.org/TR/css-values-4/#ex
+    pub fn x_height(&self, size: f32) -> f32 {
+        self.x_height.map_or(size / 2.0, |x_height| x_height * self.scale(size))
+    }
+
+    /// The advance width of "0" at this size, in px, for the `ch` unit.  If the font has no
+    /// glyph for "0", it is assumed to be half the size.
+    ///
+    /// https://www.w3.org/TR/css-values-4/#ch
+    pub fn zero_advance(&self, size: f32) -> f32 {
+        match self.glyph_index('0') {
+            0 => size / 2.0,
+            glyph => self.advance(glyph) * self.scale(size),
+        }
+    }
+
+    /// The used value of `line-height: normal` at this size, in px.
+    ///
+    /// https://www.w3.org/TR/CSS2/visudet.html#propdef-line-height
+    pub fn normal_line_height(&self, size: f32) -> f32 {
+        (self.ascender - self.descender + self.line_gap) * self.scale(size)
+    }
+
+    /// The glyph for a character, or 0 (the `.notdef` glyph) if the font has none.
+    ///
+    /// https://learn.microsoft.com/en-us/typography/opentype/spec/cmap
+    pub fn glyph_index(&self, c: char) -> u16 {
+        let glyph = self.cmap.and_then(|cmap| cmap_lookup(&self.data, cmap, c as u32));
+        glyph.filter(|&glyph| glyph < self.num_glyphs).unwrap_or(0)
+    }
+
+    /// Does the font have a glyph for this character?
+    pub fn has_glyph(&self, c: char) -> bool {
+        self.glyph_index(c) != 0
+    }
+
+    /// The advance width of a glyph, in font units.
+    ///
+    /// https://learn.microsoft.com/en-us/typography/opentype/spec/hmtx
+    pub fn advance(&self, glyph: u16) -> f32 {
+        // Glyphs after the last long metric have the same advance as it.
+        let index = glyph.min(self.num_h_metrics - 1) as usize;
+        read_u16(&self.data, self.hmtx.start + 4 * index).unwrap_or(0) as f32
+    }
+
+    /// The adjustment to the advance of glyph `left` when it is followed by glyph `right`, in
+    /// font units.
+    pub fn kerning(&self, left: u16, right: u16) -> f32 {
+        let data = &self.data;
+        match self.kerning {
+            // Each lookup uses its first subtable that applies to the pair.
+            Kerning::Gpos(ref lookups) => lookups.iter().map(|subtables| {
+                subtables.iter()
+                    .find_map(|&subtable| pair_adjustment(data, subtable, left, right))
+                    .unwrap_or(0)
+            }).sum::<i32>() as f32,
+            Kerning::Kern(ref subtables) => subtables.iter()
+                .filter_map(|&subtable| kern_pair(data, subtable, left, right))
+                .sum::<i32>() as f32,
+            Kerning::None => 0.0,
+        }
+    }
+
+    /// Map text to glyphs and position them along the baseline, at a font size in px.  Returns
+    /// the glyphs and the total advance width in px.
+    pub fn shape(&self, text: &str, size: f32) -> (Vec<PositionedGlyph>, f32) {
+        let scale = self.scale(size);
+        let mut glyphs: Vec<PositionedGlyph> = Vec::new();
+        let mut x = 0.0;
+        let mut previous = None;
+        for c in text.chars() {
+            let id = self.glyph_index(c);
+            if let Some(previous) = previous {
+                x += self.kerning(previous, id) * scale;
+            }
+            glyphs.push(PositionedGlyph { id, x, y: 0.0 });
+            x += self.advance(id) * scale;
+            previous = Some(id);
+        }
+        (glyphs, x)
+    }
+
+    /// The advance width of text at a font size in px.
+    pub fn text_width(&self, text: &str, size: f32) -> f32 {
+        self.shape(text, size).1
+    }
+
+    /// The outline of a glyph, in font units.  Empty for glyphs like the space, and for glyphs
+    /// whose outlines can't be read.
+    pub fn outline(&self, glyph: u16) -> Vec<PathSegment> {
+        let mut path = Vec::new();
+        match self.outlines {
+            Outlines::TrueType { .. } => {
+                self.glyf_outline(glyph, IDENTITY, 0, &mut path);
+            }
+            Outlines::Cff(ref cff) => {
+                if cff.outline(&self.data, glyph, &mut path).is_none() {
+                    path.clear();
+                }
+            }
+            Outlines::None => {}
+        }
+        path
+    }
+}
+
+impl fmt::Debug for Font {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Font")
+            .field("family_names", &self.family_names)
+            .field("weight", &self.weight)
+            .field("style", &self.style)
+            .field("stretch", &self.stretch)
+            .finish_non_exhaustive()
+    }
+}
+
+// Reading big-endian values.  These return `None` past the end of the data, so damaged fonts
+// fail cleanly.
+
+fn read_u8(data: &[u8], offset: usize) -> Option<u8> {
+    data.get(offset).copied()
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_i16(data: &[u8], offset: usize) -> Option<i16> {
+    read_u16(data, offset).map(|n| n as i16)
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// Find the tables in a font file.
+///
+/// https://learn.microsoft.com/en-us/typography/opentype/spec/otff#organization-of-an-opentype-font
+fn table_directory(data: &[u8]) -> Option<HashMap<[u8; 4], Range<usize>>> {
+    let start = match data.get(0..4)? {
+        b"ttcf" => read_u32(data, 12)? as usize,
+        _ => 0,
+    };
+    match data.get(start..start + 4)? {
+        [0, 1, 0, 0] | b"OTTO" | b"true" => {}
+        _ => return None,
+    }
+    let num_tables = read_u16(data, start + 4)? as usize;
+    let mut tables = HashMap::new();
+    for i in 0..num_tables {
+        let record = start + 12 + 16 * i;
+        let tag = data.get(record..record + 4)?.try_into().ok()?;
+        let offset = read_u32(data, record + 8)? as usize;
+        let length = read_u32(data, record + 12)? as usize;
+        if offset + length <= data.len() {
+            tables.insert(tag, offset..offset + length);
+        }
+    }
+    Some(tables)
+}
+
+/// Read a name from the `name` table, preferring US English in a Unicode encoding.
+///
+/// https://learn.microsoft.com/en-us/typography/opentype/spec/name
+fn read_name(data: &[u8], name: usize, id: u16) -> Option<String> {
+    let count = read_u16(data, name + 2)? as usize;
+    let strings = name + read_u16(data, name + 4)? as usize;
+    let mut best = None;
+    for i in 0..count {
+        let record = name + 6 + 12 * i;
+        if read_u16(data, record + 6)? != id {
+            continue;
+        }
+        let platform = read_u16(data, record)?;
+        let encoding = read_u16(data, record + 2)?;
+        let language = read_u16(data, record + 4)?;
+        let priority = match (platform, encoding, language) {
+            (3, 1, 0x409) => 3,
+            (0, _, _) | (3, 1, _) => 2,
+            (1, 0, 0) => 1,
+            _ => continue,
+        };
+        if best.as_ref().is_some_and(|&(best_priority, _)| best_priority >= priority) {
+            continue;
+        }
+        let start = strings + read_u16(data, record + 10)? as usize;
+        let bytes = data.get(start..start + read_u16(data, record + 8)? as usize)?;
+        let string = if platform == 1 {
+            // Mac Roman, which matches ASCII for the characters that names usually use.
+            bytes.iter().map(|&b| b as char).collect()
+        } else {
+            let units: Vec<u16> = bytes.chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            String::from_utf16_lossy(&units)
+        };
+        best = Some((priority, string));
+    }
+    best.map(|(_, string)| string)
+}
+
+// Character to glyph mapping.
+
+/// Choose the `cmap` subtable with the widest Unicode coverage, in a format we can read.
+fn choose_cmap(data: &[u8], cmap: usize) -> Option<usize> {
+    let num_tables = read_u16(data, cmap + 2)? as usize;
+    (0..num_tables).filter_map(|i| {
+        let record = cmap + 4 + 8 * i;
+        let platform = read_u16(data, record)?;
+        let encoding = read_u16(data, record + 2)?;
+        let subtable = cmap + read_u32(data, record + 4)? as usize;
+        let format = read_u16(data, subtable)?;
+        let priority = match (platform, encoding, format) {
+            (_, _, f) if ![0, 4, 6, 12].contains(&f) => return None,
+            // Full Unicode.
+            (0, 4 | 6, _) | (3, 10, _) => 3,
+            // The Basic Multilingual Plane.
+            (0, 0..=3, _) | (3, 1, _) => 2,
+            _ => return None,
+        };
+        Some((priority, subtable))
+    }).max_by_key(|&(priority, subtable)| (priority, std::cmp::Reverse(subtable)))
+        .map(|(_, subtable)| subtable)
+}
+
+fn cmap_lookup(data: &[u8], subtable: usize, c: u32) -> Option<u16> {
+    match read_u16(data, subtable)? {
+        0 if c < 256 => read_u8(data, subtable + 6 + c as usize).map(u16::from),
+        4 if c <= 0xFFFF => {
+            let c = c as u16;
+            let seg_count = read_u16(data, subtable + 6)? as usize / 2;
+            let end_codes = subtable + 14;
+            let start_codes = end_codes + 2 * seg_count + 2;
+            let deltas = start_codes + 2 * seg_count;
+            let range_offsets = deltas + 2 * seg_count;
+            // Segments are sorted by end code.
+            let (mut low, mut high) = (0, seg_count);
+            while low < high {
+                let mid = (low + high) / 2;
+                if read_u16(data, end_codes + 2 * mid)? < c {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            let segment = low;
+            if segment == seg_count || read_u16(data, start_codes + 2 * segment)? > c {
+                return None;
+            }
+            let start = read_u16(data, start_codes + 2 * segment)?;
+            let delta = read_u16(data, deltas + 2 * segment)?;
+            let range_offset = read_u16(data, range_offsets + 2 * segment)? as usize;
+            if range_offset == 0 {
+                return Some(c.wrapping_add(delta));
+            }
+            let address = range_offsets + 2 * segment + range_offset + 2 * (c - start) as usize;
+            match read_u16(data, address)? {
+                0 => None,
+                glyph => Some(glyph.wrapping_add(delta)),
+            }
+        }
+        6 => {
+            let first = read_u16(data, subtable + 6)? as u32;
+            let count = read_u16(data, subtable + 8)? as u32;
+            match c.checked_sub(first) {
+                Some(i) if i < count => read_u16(data, subtable + 10 + 2 * i as usize),
+                _ => None,
+            }
+        }
+        12 => {
+            let num_groups = read_u32(data, subtable + 12)? as usize;
+            let group = |i: usize| subtable + 16 + 12 * i;
+            let (mut low, mut high) = (0, num_groups);
+            while low < high {
+                let mid = (low + high) / 2;
+                if read_u32(data, group(mid) + 4)? < c {
+                    low = mid + 1;
+                } else {
+                    high = mid;
+                }
+            }
+            let start = read_u32(data, group(low))?;
+            if low == num_groups || start > c {
+                return None;
+            }
+            let glyph = read_u32(data, group(low) + 8)? + (c - start);
+            u16::try_from(glyph).ok()
+        }
+        _ => None,
+    }
+}
+
+// Kerning.
+
+/// Find the pair adjustment subtables of the lookups used by the `kern` feature, for any script.
+///
+/// https://learn.microsoft.com/en-us/typography/opentype/spec/gpos
+fn gpos_kerning(data: &[u8], gpos: usize) -> Vec<Vec<usize>> {
+    let read = || -> Option<Vec<Vec<usize>>> {
+        let feature_list = gpos + read_u16(data, gpos + 6)? as usize;
+        let lookup_list = gpos + read_u16(data, gpos + 8)? as usize;
+        let mut lookup_indices = Vec::new();
+        for i in 0..read_u16(data, feature_list)? as usize {
+            let record = feature_list + 2 + 6 * i;
+            if data.get(record..record + 4)? != b"kern" {
+                continue;
+            }
+            let feature = feature_list + read_u16(data, record + 4)? as usize;
+            for j in 0..read_u16(data, feature + 2)? as usize {
+                lookup_indices.push(read_u16(data, feature + 4 + 2 * j)?);
+            }
+        }
+        lookup_indices.sort_unstable();
+        lookup_indices.dedup();
+
+        let mut lookups = Vec::new();
+        for index in lookup_indices {
+            let record = lookup_list + 2 + 2 * index as usize;
+            let lookup = lookup_list + read_u16(data, record)? as usize;
+            let lookup_type = read_u16(data, lookup)?;
+            let mut subtables = Vec::new();
+            for i in 0..read_u16(data, lookup + 4)? as usize {
+                let mut subtable = lookup + read_u16(data, lookup + 6 + 2 * i)? as usize;
+                let mut subtable_type = lookup_type;
+                // Extension subtables point to the real subtable with a 32-bit offset.
+                if lookup_type == 9 {
+                    subtable_type = read_u16(data, subtable + 2)?;
+                    subtable += read_u32(data, subtable + 4)? as usize;
+                }
+                if subtable_type == 2 {
+                    subtables.push(subtable);
+                }
+            }
+            if !subtables.is_empty() {
+                lookups.push(subtables);
+            }
+        }
+        Some(lookups)
+    };
+    read().unwrap_or_default()
+}
+
+/// The x advance adjustment of a pair in a pair adjustment subtable, or `None` if the subtable
+/// doesn't cover the pair.
+fn pair_adjustment(data: &[u8], subtable: usize, left: u16, right: u16) -> Option<i32> {
+    let format = read_u16(data, subtable)?;
+    let coverage = subtable + read_u16(data, subtable + 2)? as usize;
+    let coverage_index = coverage_index(data, coverage, left)?;
+    let value_format_1 = read_u16(data, subtable + 4)?;
+    let value_format_2 = read_u16(data, subtable + 6)?;
+    // Each value in a value record, except device table offsets, is 16 bits.
+    let record_size = 2 * (value_format_1.count_ones() + value_format_2.count_ones()) as usize;
+    let record = match format {
+        1 => {
+            let pair_set = subtable + read_u16(data, subtable + 10 + 2 * coverage_index)? as usize;
+            let count = read_u16(data, pair_set)? as usize;
+            let pair = |i: usize| pair_set + 2 + (2 + record_size) * i;
+            let (mut low, mut high) = (0, count);
+            while low < high {
+                let mid = (low + high) / 2;
+                match read_u16(data, pair(mid))?.cmp(&right) {
+                    std::cmp::Ordering::Less => low = mid + 1,
+                    std::cmp::Ordering::Greater => high = mid,
+                    std::cmp::Ordering::Equal => {
+                        return x_advance(data, pair(mid) + 2, value_format_1);
+                    }
+                }
+            }
+            return None;
+        }
+        2 => {
+            let class_def_1 = subtable + read_u16(data, subtable + 8)? as usize;
+            let class_def_2 = subtable + read_u16(data, subtable + 10)? as usize;
+            let class_1_count = read_u16(data, subtable + 12)? as usize;
+            let class_2_count = read_u16(data, subtable + 14)? as usize;
+            let class_1 = glyph_class(data, class_def_1, left)? as usize;
+            let class_2 = glyph_class(data, class_def_2, right)? as usize;
+            if class_1 >= class_1_count || class_2 >= class_2_count {
+                return None;
+            }
+            subtable + 16 + record_size * (class_1 * class_2_count + class_2)
+        }
+        _ => return None,
+    };
+    x_advance(data, record, value_format_1)
+}
+
+/// The `XAdvance` field of a value record.
+///
+/// https://learn.microsoft.com/en-us/typography/opentype/spec/gpos#value-record
+fn x_advance(data: &[u8], record: usize, value_format: u16) -> Option<i32> {
+    const X_ADVANCE: u16 = 0x4;
+    if value_format & X_ADVANCE == 0 {
+        return Some(0);
+    }
+    // Skip the placement fields before it.
+    let offset = 2 * (value_format & (X_ADVANCE - 1)).count_ones() as usize;
+    read_i16(data, record + offset).map(i32::from)
+}
+
+/// The index of a glyph in a coverage table, if it's covered.
+///
+/// https://learn.microsoft.com/en-us/typography/opentype/spec/chapter2#coverage-table
+fn coverage_index(data: &[u8], coverage: usize, glyph: u16) -> Option<usize> {
+    let count = read_u16(data, coverage + 2)? as usize;
+    match read_u16(data, coverage)? {
+        1 => {
+            let (mut low, mut high) = (0, count);
+            while low < high {
+                let mid = (low + high) / 2;
+                match read_u16(data, coverage + 4 + 2 * mid)?.cmp(&glyph) {
+                    std::cmp::Ordering::Less => low = mid + 1,
+                    std::cmp::Ordering::Greater => high = mid,
+                    std::cmp::Ordering::Equal => return Some(mid),
+                }
+            }
+            None
+        }
+        2 => {
+            let (start, range) = find_range(data, coverage + 4, count, glyph)?;
+            let start_index = read_u16(data, range + 4)? as usize;
+            Some(start_index + (glyph - start) as usize)
+        }
+        _ => None,
+    }
+}
+
+/// The class of a glyph in a class definition table.
+///
+/// https://learn.microsoft.com/en-us/typography/opentype/spec/chapter2#class-definition-table
+fn glyph_class(data: &[u8], class_def: usize, glyph: u16) -> Option<u16> {
+    match read_u16(data, class_def)? {
+        1 => {
+            let start = read_u16(data, class_def + 2)?;
+            let count = read_u16(data, class_def + 4)?;
+            match glyph.checked_sub(start) {
+                Some(i) if i < count => read_u16(data, class_def + 6 + 2 * i as usize),
+                _ => Some(0),
+            }
+        }
+        2 => {
+            let count = read_u16(data, class_def + 2)? as usize;
+            match find_range(data, class_def + 4, count, glyph) {
+                Some((_, range)) => read_u16(data, range + 4),
+                None => Some(0),
+            }
+        }
+        _ => Some(0),
+    }
+}
+
+/// Find the range record containing a glyph, in a sorted array of records that each start with
+/// the first and last glyph ids of a range.  Returns the first glyph id and the record.
+fn find_range(data: &[u8], records: usize, count: usize, glyph: u16) -> Option<(u16, usize)> {
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = (low + high) / 2;
+        let record = records + 6 * mid;
+        if read_u16(data, record + 2)? < glyph {
+            low = mid + 1;
+        } else if read_u16(data, record)? > glyph {
+            high = mid;
+        } else {
+            return Some((read_u16(data, record)?, record));
+        }
+    }
+    None
+}
+
+/// Find the horizontal kerning subtables of a `kern` table that are in format 0.
+///
+/// https://learn.microsoft.com/en-us/typography/opentype/spec/kern
+fn kern_subtables(data: &[u8], kern: usize) -> Vec<usize> {
+    let read = || -> Option<Vec<usize>> {
+        // Apple's version 1 tables have a different layout.
+        if read_u16(data, kern)? != 0 {
+            return Some(Vec::new());
+        }
+        let mut subtables = Vec::new();
+        let mut subtable = kern + 4;
+        for _ in 0..read_u16(data, kern + 2)? {
+            const HORIZONTAL: u16 = 0x1;
+            const MINIMUM: u16 = 0x2;
+            const CROSS_STREAM: u16 = 0x4;
+            let coverage = read_u16(data, subtable + 4)?;
+            let flags = coverage & (HORIZONTAL | MINIMUM | CROSS_STREAM);
+            if coverage >> 8 == 0 && flags == HORIZONTAL {
+                subtables.push(subtable);
+            }
+            subtable += read_u16(data, subtable + 2)? as usize;
+        }
+        Some(subtables)
+    };
+    read().unwrap_or_default()
+}
+
+fn kern_pair(data: &[u8], subtable: usize, left: u16, right: u16) -> Option<i32> {
+    let count = read_u16(data, subtable + 6)? as usize;
+    let key = (left as u32) << 16 | right as u32;
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let mid = (low + high) / 2;
+        let pair = subtable + 14 + 6 * mid;
+        match read_u32(data, pair)?.cmp(&key) {
+            std::cmp::Ordering::Less => low = mid + 1,
+            std::cmp::Ordering::Greater => high = mid,
+            std::cmp::Ordering::Equal => return read_i16(data, pair + 4).map(i32::from),
+        }
+    }
+    None
+}
+
+// TrueType outlines.
+
+/// An affine transform `[a, b, c, d, e, f]`, mapping (x, y) to (ax + cy + e, bx + dy + f).
+type Transform = [f32; 6];
+
+const IDENTITY: Transform = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+fn transform_point(t: &Transform, x: f32, y: f32) -> Point {
+    Point { x: t[0] * x + t[2] * y + t[4], y: t[1] * x + t[3] * y + t[5] }
+}
+
+/// Apply `inner`, then `outer`.
+fn compose(outer: &Transform, inner: &Transform) -> Transform {
+    [
+        outer[0] * inner[0] + outer[2] * inner[1],
+        outer[1] * inner[0] + outer[3] * inner[1],
+        outer[0] * inner[2] + outer[2] * inner[3],
+        outer[1] * inner[2] + outer[3] * inner[3],
+        outer[0] * inner[4] + outer[2] * inner[5] + outer[4],
+        outer[1] * inner[4] + outer[3] * inner[5] + outer[5],
+    ]
+}
+
+/// Composite glyphs can nest, but not this deep in any reasonable font.
+const MAX_COMPONENT_DEPTH: u32 = 8;
+
+impl Font {
+    /// Add the outline of a glyph from the `glyf` table to `path`.  Returns `None` if the glyph
+    /// data is damaged, leaving whatever was read.
+    ///
+    /// https://learn.microsoft.com/en-us/typography/opentype/spec/glyf
+    fn glyf_outline(&self, glyph: u16, transform: Transform, depth: u32,
+                    path: &mut Vec<PathSegment>) -> Option<()> {
+        let Outlines::TrueType { ref glyf, ref loca, long_offsets } = self.outlines else {
+            return None;
+        };
+        let data = &self.data;
+        let index = glyph as usize;
+        let (start, end) = if long_offsets {
+            (read_u32(data, loca.start + 4 * index)? as usize,
+             read_u32(data, loca.start + 4 * index + 4)? as usize)
+        } else {
+            (2 * read_u16(data, loca.start + 2 * index)? as usize,
+             2 * read_u16(data, loca.start + 2 * index + 2)? as usize)
+        };
+        if start >= end {
+            return Some(());
+        }
+        let glyph_data = data.get(glyf.start + start..glyf.start + end.min(glyf.len()))?;
+        let num_contours = read_i16(glyph_data, 0)?;
+        if num_contours >= 0 {
+            simple_glyph(glyph_data, num_contours as usize, &transform, path)
+        } else if depth < MAX_COMPONENT_DEPTH {
+            self.composite_glyph(glyph_data, &transform, depth, path)
+        } else {
+            None
+        }
+    }
+
+    fn composite_glyph(&self, glyph_data: &[u8], transform: &Transform, depth: u32,
+                       path: &mut Vec<PathSegment>) -> Option<()> {
+        const ARG_1_AND_2_ARE_WORDS: u16 = 0x1;
+        const ARGS_ARE_XY_VALUES: u16 = 0x2;
+        const WE_HAVE_A_SCALE: u16 = 0x8;
+        const MORE_COMPONENTS: u16 = 0x20;
+        const WE_HAVE_AN_X_AND_Y_SCALE: u16 = 0x40;
+        const WE_HAVE_A_TWO_BY_TWO: u16 = 0x80;
+        let f2dot14 = |offset| read_i16(glyph_data, offset).map(|n| n as f32 / 16384.0);
+
+        let mut offset = 10;
+        loop {
+            let flags = read_u16(glyph_data, offset)?;
+            let component = read_u16(glyph_data, offset + 2)?;
+            offset += 4;
+            let (arg1, arg2) = if flags & ARG_1_AND_2_ARE_WORDS != 0 {
+                offset += 4;
+                (read_i16(glyph_data, offset - 4)? as f32, read_i16(glyph_data, offset - 2)? as f32)
+            } else {
+                offset += 2;
+                (read_u8(glyph_data, offset - 2)? as i8 as f32,
+                 read_u8(glyph_data, offset - 1)? as i8 as f32)
+            };
+            let mut inner = IDENTITY;
+            if flags & WE_HAVE_A_SCALE != 0 {
+                inner[0] = f2dot14(offset)?;
+                inner[3] = inner[0];
+                offset += 2;
+            } else if flags & WE_HAVE_AN_X_AND_Y_SCALE != 0 {
+                inner[0] = f2dot14(offset)?;
+                inner[3] = f2dot14(offset + 2)?;
+                offset += 4;
+            } else if flags & WE_HAVE_A_TWO_BY_TWO != 0 {
+                for (i, value) in inner[..4].iter_mut().enumerate() {
+                    *value = f2dot14(offset + 2 * i)?;
+                }
+                offset += 8;
+            }
+            // Components positioned by matching points aren't supported, and are left in place.
+            if flags & ARGS_ARE_XY_VALUES != 0 {
+                inner[4] = arg1;
+                inner[5] = arg2;
+            }
+            self.glyf_outline(component, compose(transform, &inner), depth + 1, path)?;
+            if flags & MORE_COMPONENTS == 0 {
+                return Some(());
+            }
+        }
+    }
+}
+
+fn simple_glyph(glyph_data: &[u8], num_contours: usize, transform: &Transform,
+                path: &mut Vec<PathSegment>) -> Option<()> {
+    const ON_CURVE: u8 = 0x1;
+    const X_SHORT: u8 = 0x2;
+    const Y_SHORT: u8 = 0x4;
+    const REPEAT: u8 = 0x8;
+    const X_SAME_OR_POSITIVE: u8 = 0x10;
+    const Y_SAME_OR_POSITIVE: u8 = 0x20;
+
+    let end_points: Vec<usize> = (0..num_contours)
+        .map(|i| read_u16(glyph_data, 10 + 2 * i).map(usize::from))
+        .collect::<Option<_>>()?;
+    let num_points = end_points.last().map_or(0, |&last| last + 1);
+    let instructions_length = read_u16(glyph_data, 10 + 2 * num_contours)? as usize;
+    let mut offset = 12 + 2 * num_contours + instructions_length;
+
+    let mut flags = Vec::with_capacity(num_points);
+    while flags.len() < num_points {
+        let flag = read_u8(glyph_data, offset)?;
+        offset += 1;
+        let repeat = if flag & REPEAT != 0 {
+            offset += 1;
+            read_u8(glyph_data, offset - 1)? as usize
+        } else {
+            0
+        };
+        flags.extend(std::iter::repeat_n(flag, repeat + 1));
+    }
+    flags.truncate(num_points);
+
+    // Coordinates are deltas from the previous point, in one of three encodings.
+    let mut read_coordinates = |short: u8, same_or_positive: u8| -> Option<Vec<f32>> {
+        let mut value = 0i32;
+        flags.iter().map(|&flag| {
+            if flag & short != 0 {
+                let delta = read_u8(glyph_data, offset)? as i32;
+                offset += 1;
+                value += if flag & same_or_positive != 0 { delta } else { -delta };
+            } else if flag & same_or_positive == 0 {
+                value += read_i16(glyph_data, offset)? as i32;
+                offset += 2;
+            }
+            Some(value as f32)
+        }).collect()
+    };
+    let xs = read_coordinates(X_SHORT, X_SAME_OR_POSITIVE)?;
+    let ys = read_coordinates(Y_SHORT, Y_SAME_OR_POSITIVE)?;
+
+    let mut start = 0;
+    for &end in &end_points {
+        if end < start || end >= num_points {
+            return None;
+        }
+        let points: Vec<(Point, bool)> = (start..=end)
+            .map(|i| (transform_point(transform, xs[i], ys[i]), flags[i] & ON_CURVE != 0))
+            .collect();
+        quadratic_contour(&points, path);
+        start = end + 1;
+    }
+    Some(())
+}
+
+/// Convert a TrueType contour to path segments.  Between two consecutive off-curve points there
+/// is an implied on-curve point halfway between them.
+fn quadratic_contour(points: &[(Point, bool)], path: &mut Vec<PathSegment>) {
+    let (Some(&(first, first_on)), Some(&(last, last_on))) = (points.first(), points.last()) else {
+        return;
+    };
+    let midpoint = |a: Point, b: Point| Point { x: (a.x + b.x) / 2.0, y: (a.y + b.y) / 2.0 };
+    // Start at an on-curve point.
+    let (start, rest) = if first_on {
+        (first, &points[1..])
+    } else if last_on {
+        (last, &points[..points.len() - 1])
+    } else {
+        (midpoint(first, last), points)
+    };
+    path.push(PathSegment::MoveTo(start));
+    let mut control: Option<Point> = None;
+    for &(point, on_curve) in rest {
+        match (on_curve, control) {
+            (true, Some(c)) => path.push(PathSegment::QuadTo(c, point)),
+            (true, None) => path.push(PathSegment::LineTo(point)),
+            (false, Some(c)) => path.push(PathSegment::QuadTo(c, midpoint(c, point))),
+            (false, None) => {}
+        }
+        control = if on_curve { None } else { Some(point) };
+    }
+    if let Some(c) = control {
+        path.push(PathSegment::QuadTo(c, start));
+    }
+    path.push(PathSegment::Close);
+}
+
+// CFF outlines.
+
+/// The parts of a Compact Font Format table needed to draw glyphs.
+///
+/// https://adobe-type-tools.github.io/font-tech-notes/pdfs/5176.CFF.pdf
+struct Cff {
+    char_strings: Index,
+    global_subrs: Index,
+    local_subrs: LocalSubrs,
+}
+
+enum LocalSubrs {
+    Single(Option<Index>),
+    /// A CID-keyed font has a private dictionary, with its own subroutines, for each group of
+    /// glyphs in its `FDSelect` table.
+    Cid { fd_select: usize, subrs: Vec<Option<Index>> },
+}
+
+/// An array of variable-sized objects.
+#[derive(Clone, Copy)]
+struct Index {
+    count: usize,
+    offset_size: usize,
+    /// The position of the array of offsets.
+    offsets: usize,
+    /// The position before the first object, which offsets are relative to.
+    base: usize,
+}
+
+impl Index {
+    /// Read an INDEX, returning it and the position just after it.
+    fn parse(data: &[u8], offset: usize) -> Option<(Index, usize)> {
+        let count = read_u16(data, offset)? as usize;
+        if count == 0 {
+            let index = Index { count, offset_size: 1, offsets: offset, base: offset };
+            return Some((index, offset + 2));
+        }
+        let offset_size = read_u8(data, offset + 2)? as usize;
+        if !(1..=4).contains(&offset_size) {
+            return None;
+        }
+        let offsets = offset + 3;
+        let base = offsets + (count + 1) * offset_size - 1;
+        let index = Index { count, offset_size, offsets, base };
+        let end = base + index.offset(data, count)?;
+        Some((index, end))
+    }
+
+    fn offset(&self, data: &[u8], i: usize) -> Option<usize> {
+        let bytes = data.get(self.offsets + i * self.offset_size..)?.get(..self.offset_size)?;
+        Some(bytes.iter().fold(0, |n, &byte| n << 8 | byte as usize))
+    }
+
+    fn get(&self, data: &[u8], i: usize) -> Option<Range<usize>> {
+        if i >= self.count {
+            return None;
+        }
+        let range = self.base + self.offset(data, i)?..self.base + self.offset(data, i + 1)?;
+        if range.start <= range.end && range.end <= data.len() { Some(range) } else { None }
+    }
+
+    /// The number added to subroutine numbers, which are stored offset to make them smaller.
+    fn bias(&self) -> i32 {
+        match self.count {
+            0..=1239 => 107,
+            1240..=33899 => 1131,
+            _ => 32768,
+        }
+    }
+}
+
+/// Read a DICT of operators and their operands.  Two-byte operators are numbered 1200 and up.
+fn parse_dict(data: &[u8], range: Range<usize>) -> Option<HashMap<u16, Vec<f64>>> {
+    let mut dict = HashMap::new();
+    let mut operands = Vec::new();
+    let mut i = range.start;
+    while i < range.end {
+        let b0 = read_u8(data, i)?;
+        i += 1;
+        match b0 {
+            0..=21 => {
+                let operator = if b0 == 12 {
+                    i += 1;
+                    1200 + read_u8(data, i - 1)? as u16
+                } else {
+                    b0 as u16
+                };
+                dict.insert(operator, std::mem::take(&mut operands));
+            }
+            28 => {
+                operands.push(read_i16(data, i)? as f64);
+                i += 2;
+            }
+            29 => {
+                operands.push(read_u32(data, i)? as i32 as f64);
+                i += 4;
+            }
+            30 => {
+                // A real number, as a string of BCD nibbles.
+                let mut text = String::new();
+                'nibbles: loop {
+                    let byte = read_u8(data, i)?;
+                    i += 1;
+                    for nibble in [byte >> 4, byte & 0xf] {
+                        match nibble {
+                            0..=9 => text.push((b'0' + nibble) as char),
+                            0xa => text.push('.'),
+                            0xb => text.push('E'),
+                            0xc => text.push_str("E-"),
+                            0xe => text.push('-'),
+                            0xf => break 'nibbles,
+                            _ => {}
+                        }
+                    }
+                }
+                operands.push(text.parse().unwrap_or(0.0));
+            }
+            32..=246 => operands.push(b0 as f64 - 139.0),
+            247..=250 => {
+                operands.push((b0 as f64 - 247.0) * 256.0 + read_u8(data, i)? as f64 + 108.0);
+                i += 1;
+            }
+            251..=254 => {
+                operands.push(-(b0 as f64 - 251.0) * 256.0 - read_u8(data, i)? as f64 - 108.0);
+                i += 1;
+            }
+            _ => return None,
+        }
+    }
+    Some(dict)
+}
+
+impl Cff {
+    fn parse(data: &[u8], table: Range<usize>) -> Option<Cff> {
+        let start = table.start;
+        let header_size = read_u8(data, start + 2)? as usize;
+        let (_names, end) = Index::parse(data, start + header_size)?;
+        let (top_dicts, end) = Index::parse(data, end)?;
+        let (_strings, end) = Index::parse(data, end)?;
+        let (global_subrs, _) = Index::parse(data, end)?;
+        let top_dict = parse_dict(data, top_dicts.get(data, 0)?)?;
+        let offset = |operator| -> Option<usize> {
+            Some(start + *top_dict.get(&operator)?.first()? as usize)
+        };
+
+        const CHAR_STRINGS: u16 = 17;
+        const FD_ARRAY: u16 = 1236;
+        const FD_SELECT: u16 = 1237;
+        let (char_strings, _) = Index::parse(data, offset(CHAR_STRINGS)?)?;
+        let local_subrs = match (offset(FD_ARRAY), offset(FD_SELECT)) {
+            (Some(fd_array), Some(fd_select)) => {
+                let (font_dicts, _) = Index::parse(data, fd_array)?;
+                let subrs = (0..font_dicts.count).map(|i| {
+                    let font_dict = parse_dict(data, font_dicts.get(data, i)?)?;
+                    private_subrs(data, start, &font_dict)
+                }).collect();
+                LocalSubrs::Cid { fd_select, subrs }
+            }
+            _ => LocalSubrs::Single(private_subrs(data, start, &top_dict)),
+        };
+        Some(Cff { char_strings, global_subrs, local_subrs })
+    }
+
+    /// The local subroutines for a glyph.
+    fn local_subrs(&self, data: &[u8], glyph: u16) -> Option<Index> {
+        match self.local_subrs {
+            LocalSubrs::Single(subrs) => subrs,
+            LocalSubrs::Cid { fd_select, ref subrs } => {
+                let fd = match read_u8(data, fd_select)? {
+                    0 => read_u8(data, fd_select + 1 + glyph as usize)?,
+                    3 => {
+                        // Ranges of glyphs, each starting with its first glyph id.
+                        let count = read_u16(data, fd_select + 1)? as usize;
+                        let range = |i: usize| fd_select + 3 + 3 * i;
+                        let first = |i| read_u16(data, range(i));
+                        let i = (0..count).rev()
+                            .find(|&i| first(i).is_some_and(|first| first <= glyph))?;
+                        read_u8(data, range(i) + 2)?
+                    }
+                    _ => return None,
+                };
+                *subrs.get(fd as usize)?
+            }
+        }
+    }
+
+    fn outline(&self, data: &[u8], glyph: u16, path: &mut Vec<PathSegment>) -> Option<()> {
+        let char_string = self.char_strings.get(data, glyph as usize)?;
+        let mut interpreter = CharStringInterpreter {
+            data,
+            global_subrs: self.global_subrs,
+            local_subrs: self.local_subrs(data, glyph),
+            stack: Vec::new(),
+            num_stems: 0,
+            seen_width: false,
+            point: Point { x: 0.0, y: 0.0 },
+            path,
+        };
+        interpreter.run(char_string, 0)?;
+        if interpreter.path.last().is_some_and(|&segment| segment != PathSegment::Close) {
+            interpreter.path.push(PathSegment::Close);
+        }
+        Some(())
+    }
+}
+
+/// The subroutines in the private DICT referenced by a top or font DICT.
+fn private_subrs(data: &[u8], start: usize, dict: &HashMap<u16, Vec<f64>>) -> Option<Index> {
+    const PRIVATE: u16 = 18;
+    const SUBRS: u16 = 19;
+    let &[size, offset] = &dict.get(&PRIVATE)?[..] else { return None };
+    let private = start + offset as usize;
+    let private_dict = parse_dict(data, private..private + size as usize)?;
+    let subrs = private + *private_dict.get(&SUBRS)?.first()? as usize;
+    Index::parse(data, subrs).map(|(index, _)| index)
+}
+
+/// Subroutines can call each other, but only this deep.
+const MAX_SUBR_DEPTH: u32 = 10;
+
+/// Runs a Type 2 charstring, the program that draws a CFF glyph.
+///
+/// https://adobe-type-tools.github.io/font-tech-notes/pdfs/5177.Type2.pdf
+struct CharStringInterpreter<'a, 'p> {
+    data: &'a [u8],
+    global_subrs: Index,
+    local_subrs: Option<Index>,
+    stack: Vec<f32>,
+    num_stems: usize,
+    /// The first stack-clearing operator can have an extra operand, the glyph's width, which
+    /// isn't needed because widths come from the `hmtx` table.
+    seen_width: bool,
+    point: Point,
+    path: &'p mut Vec<PathSegment>,
+}
+
+impl CharStringInterpreter<'_, '_> {
+    /// Run a charstring or subroutine.  Returns true if it ended the glyph.
+    fn run(&mut self, range: Range<usize>, depth: u32) -> Option<bool> {
+        let data = self.data;
+        let mut i = range.start;
+        while i < range.end {
+            let b0 = read_u8(data, i)?;
+            i += 1;
+            match b0 {
+                // hstem, vstem, hstemhm, vstemhm
+                1 | 3 | 18 | 23 => self.stems(),
+                // hintmask, cntrmask: a stem hint mask follows, with a bit for each stem.
+                19 | 20 => {
+                    self.stems();
+                    i += self.num_stems.div_ceil(8);
+                }
+                // rmoveto, hmoveto, vmoveto
+                21 => {
+                    self.width(2);
+                    let (dx, dy) = (self.arg(0)?, self.arg(1)?);
+                    self.move_to(dx, dy);
+                }
+                22 => {
+                    self.width(1);
+                    self.move_to(self.arg(0)?, 0.0);
+                }
+                4 => {
+                    self.width(1);
+                    self.move_to(0.0, self.arg(0)?);
+                }
+                // rlineto
+                5 => {
+                    for pair in self.stack.chunks_exact(2) {
+                        line_to(self.path, &mut self.point, pair[0], pair[1]);
+                    }
+                }
+                // hlineto, vlineto: alternating horizontal and vertical lines.
+                6 | 7 => {
+                    for (j, &d) in self.stack.iter().enumerate() {
+                        let horizontal = (j % 2 == 0) == (b0 == 6);
+                        let (dx, dy) = if horizontal { (d, 0.0) } else { (0.0, d) };
+                        line_to(self.path, &mut self.point, dx, dy);
+                    }
+                }
+                // rrcurveto
+                8 => {
+                    for args in self.stack.chunks_exact(6) {
+                        curve_to(self.path, &mut self.point, args);
+                    }
+                }
+                // rcurveline: curves, then a line.
+                24 => {
+                    let curves = self.stack.len().saturating_sub(2) / 6;
+                    for args in self.stack[..6 * curves].chunks_exact(6) {
+                        curve_to(self.path, &mut self.point, args);
+                    }
+                    if let [dx, dy] = self.stack[6 * curves..] {
+                        line_to(self.path, &mut self.point, dx, dy);
+                    }
+                }
+                // rlinecurve: lines, then a curve.
+                25 => {
+                    let lines = self.stack.len().saturating_sub(6) / 2;
+                    for pair in self.stack[..2 * lines].chunks_exact(2) {
+                        line_to(self.path, &mut self.point, pair[0], pair[1]);
+                    }
+                    if let Some(args) = self.stack.get(2 * lines..2 * lines + 6) {
+                        curve_to(self.path, &mut self.point, args);
+                    }
+                }
+                // vvcurveto, hhcurveto: curves that start and end vertical or horizontal, with
+                // an optional first delta in the other direction.
+                26 | 27 => {
+                    let (mut first, args) = match self.stack.len() % 4 {
+                        1 => (self.stack[0], &self.stack[1..]),
+                        _ => (0.0, &self.stack[..]),
+                    };
+                    for a in args.chunks_exact(4) {
+                        let curve = if b0 == 26 {
+                            [first, a[0], a[1], a[2], 0.0, a[3]]
+                        } else {
+                            [a[0], first, a[1], a[2], a[3], 0.0]
+                        };
+                        curve_to(self.path, &mut self.point, &curve);
+                        first = 0.0;
+                    }
+                }
+                // vhcurveto, hvcurveto: curves alternating between starting vertical and
+                // horizontal.  The last one can have a final delta in the other direction.
+                30 | 31 => {
+                    let mut horizontal = b0 == 31;
+                    let count = self.stack.len() / 4;
+                    for (j, a) in self.stack.chunks_exact(4).enumerate() {
+                        let last = match self.stack.get(4 * count) {
+                            Some(&d) if j + 1 == count => d,
+                            _ => 0.0,
+                        };
+                        let curve = if horizontal {
+                            [a[0], 0.0, a[1], a[2], last, a[3]]
+                        } else {
+                            [0.0, a[0], a[1], a[2], a[3], last]
+                        };
+                        curve_to(self.path, &mut self.point, &curve);
+                        horizontal = !horizontal;
+                    }
+                }
+                // callsubr, callgsubr
+                10 | 29 => {
+                    let subrs = if b0 == 10 { self.local_subrs? } else { self.global_subrs };
+                    let number = self.stack.pop()? as i32 + subrs.bias();
+                    let subr = subrs.get(data, usize::try_from(number).ok()?)?;
+                    if depth >= MAX_SUBR_DEPTH {
+                        return None;
+                    }
+                    if self.run(subr, depth + 1)? {
+                        return Some(true);
+                    }
+                    continue;
+                }
+                // return
+                11 => return Some(false),
+                // endchar
+                14 => {
+                    self.width(0);
+                    if self.path.last().is_some_and(|&segment| segment != PathSegment::Close) {
+                        self.path.push(PathSegment::Close);
+                    }
+                    return Some(true);
+                }
+                12 => {
+                    let b1 = read_u8(data, i)?;
+                    i += 1;
+                    self.flex(b1);
+                }
+                28 => {
+                    self.stack.push(read_i16(data, i)? as f32);
+                    i += 2;
+                    continue;
+                }
+                32..=246 => {
+                    self.stack.push(b0 as f32 - 139.0);
+                    continue;
+                }
+                247..=250 => {
+                    let b1 = read_u8(data, i)? as f32;
+                    self.stack.push((b0 as f32 - 247.0) * 256.0 + b1 + 108.0);
+                    i += 1;
+                    continue;
+                }
+                251..=254 => {
+                    let b1 = read_u8(data, i)? as f32;
+                    self.stack.push(-(b0 as f32 - 251.0) * 256.0 - b1 - 108.0);
+                    i += 1;
+                    continue;
+                }
+                // A 16.16 fixed-point number.
+                255 => {
+                    self.stack.push(read_u32(data, i)? as i32 as f32 / 65536.0);
+                    i += 4;
+                    continue;
+                }
+                _ => {}
+            }
+            // Every operator except subroutine calls clears the stack.
+            self.stack.clear();
+        }
+        Some(false)
+    }
+
+    fn arg(&self, i: usize) -> Option<f32> {
+        self.stack.get(i).copied()
+    }
+
+    /// Remove the width operand, if this is the first stack-clearing operator and it has one
+    /// more operand than `expected`.
+    fn width(&mut self, expected: usize) {
+        if !self.seen_width {
+            self.seen_width = true;
+            if self.stack.len() > expected {
+                self.stack.remove(0);
+            }
+        }
+    }
+
+    /// Count stem hints, which are pairs of operands.
+    fn stems(&mut self) {
+        if !self.seen_width {
+            self.seen_width = true;
+            if self.stack.len() % 2 == 1 {
+                self.stack.remove(0);
+            }
+        }
+        self.num_stems += self.stack.len() / 2;
+    }
+
+    fn move_to(&mut self, dx: f32, dy: f32) {
+        if self.path.last().is_some_and(|&segment| segment != PathSegment::Close) {
+            self.path.push(PathSegment::Close);
+        }
+        self.point = Point { x: self.point.x + dx, y: self.point.y + dy };
+        self.path.push(PathSegment::MoveTo(self.point));
+    }
+
+    /// The flex operators, which draw two curves that can be flattened at small sizes.
+    fn flex(&mut self, operator: u8) {
+        let s = &self.stack;
+        let curves = match (operator, s.len()) {
+            // flex
+            (35, 13) => [s[0], s[1], s[2], s[3], s[4], s[5], s[6], s[7], s[8], s[9], s[10], s[11]],
+            // hflex
+            (34, 7) => [s[0], 0.0, s[1], s[2], s[3], 0.0, s[4], 0.0, s[5], -s[2], s[6], 0.0],
+            // hflex1
+            (36, 9) => {
+                let dy = -(s[1] + s[3] + s[7]);
+                [s[0], s[1], s[2], s[3], s[4], 0.0, s[5], 0.0, s[6], s[7], s[8], dy]
+            }
+            // flex1: the last point is on the axis in which the curves move the most.
+            (37, 11) => {
+                let dx: f32 = s[0..10].iter().step_by(2).sum();
+                let dy: f32 = s[1..10].iter().step_by(2).sum();
+                let (dx6, dy6) = if dx.abs() > dy.abs() { (s[10], -dy) } else { (-dx, s[10]) };
+                [s[0], s[1], s[2], s[3], s[4], s[5], s[6], s[7], s[8], s[9], dx6, dy6]
+            }
+            // The arithmetic and storage operators are deprecated and unsupported.
+            _ => return,
+        };
+        curve_to(self.path, &mut self.point, &curves[..6]);
+        curve_to(self.path, &mut self.point, &curves[6..]);
+    }
+}
+
+fn line_to(path: &mut Vec<PathSegment>, point: &mut Point, dx: f32, dy: f32) {
+    *point = Point { x: point.x + dx, y: point.y + dy };
+    path.push(PathSegment::LineTo(*point));
+}
+
+/// Add a cubic curve given by the deltas from the current point to the first control point, from
+/// there to the second, and from there to the end point.
+fn curve_to(path: &mut Vec<PathSegment>, point: &mut Point, d: &[f32]) {
+    let c1 = Point { x: point.x + d[0], y: point.y + d[1] };
+    let c2 = Point { x: c1.x + d[2], y: c1.y + d[3] };
+    *point = Point { x: c2.x + d[4], y: c2.y + d[5] };
+    path.push(PathSegment::CurveTo(c1, c2, *point));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_font() {
+        let font = default_font();
+        assert_eq!(font.family_names, ["DejaVu Sans"]);
+        assert_eq!((font.weight, font.style), (400.0, FontStyle::Normal));
+        assert!(font.ascent(16.0) > 0.0 && font.descent(16.0) > 0.0);
+        assert!(font.has_glyph('a') && font.has_glyph('é'));
+        assert!(!font.has_glyph('\u{E000}'));
+        assert!(!font.outline(font.glyph_index('a')).is_empty());
+        assert!(font.outline(font.glyph_index(' ')).is_empty());
+    }
+
+    #[test]
+    fn shaping_and_kerning() {
+        let font = default_font();
+        let (a, v) = (font.glyph_index('A'), font.glyph_index('V'));
+        assert!(font.kerning(a, v) < 0.0);
+
+        let scale = font.scale(20.0);
+        let (glyphs, width) = font.shape("AVA", 20.0);
+        let ids: Vec<_> = glyphs.iter().map(|glyph| glyph.id).collect();
+        assert_eq!(ids, [a, v, a]);
+        // Each glyph follows the advance of the one before, adjusted by their kerning.
+        let kerned = (font.advance(a) + font.kerning(a, v)) * scale;
+        assert_eq!(glyphs[0].x, 0.0);
+        assert!((glyphs[1].x - kerned).abs() < 0.001);
+        let unkerned = (2.0 * font.advance(a) + font.advance(v)) * scale;
+        assert!(width < unkerned);
+        assert_eq!(font.text_width("AVA", 20.0), width);
+        // Characters the font doesn't have get the `.notdef` glyph.
+        assert_eq!(font.shape("\u{E000}", 20.0).0[0].id, 0);
+    }
+}
@@ -0,0 +1,356 @@
+//! The fonts available to a document, and font matching: choosing a font for each character of
+//! text from the `font-family`, `font-weight`, `font-style` and `font-stretch` properties.
+//!
+//! Fonts come from the bundled default font, from font files in directories, and from
+//! `@font-face` rules whose sources are local files.  Each family in `font-family` is tried in
+//! order, and within a family the face whose stretch, then style, then weight is closest to the
+//! requested one is used.  Characters that none of those fonts have are looked up in every other
+//! family, so text in scripts that the chosen fonts don't cover is still drawn.
+//!
+//! https://www.w3.org/TR/css-fonts-4/#font-matching-algorithm
+
+use crate::css::{AtRule, Stylesheet, Value};
+use crate::font::{default_font, Font, GlyphRun, ShapedText};
+use crate::properties::ComputedStyle;
+use crate::values::{family_name, Context, FamilyName, FontStretch, FontStyle, FontWeight};
+use crate::values::{GenericFamily, PropertyType};
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+pub struct FontDatabase {
+    /// The faces in each family, by family name in lowercase.
+    families: HashMap<String, Vec<Face>>,
+    /// The lowercase family names in the order they were added, which is the order they are
+    /// searched in for characters that the requested fonts don't have.
+    family_order: Vec<String>,
+}
+
+/// A font in a family, and the weights, style and stretches it is used for.  A font file covers
+/// a single weight and stretch, but a `@font-face` rule can give ranges.
+struct Face {
+    font: Arc<Font>,
+    weight: (f32, f32),
+    style: FontStyle,
+    stretch: (f32, f32),
+}
+
+impl Default for FontDatabase {
+    fn default() -> FontDatabase {
+        FontDatabase::new()
+    }
+}
+
+impl FontDatabase {
+    /// A database with just the bundled default font.
+    pub fn new() -> FontDatabase {
+        let mut fonts = FontDatabase { families: HashMap::new(), family_order: Vec::new() };
+        fonts.add_font(default_font().clone());
+        fonts
+    }
+
+    /// Add a font to each family it names.
+    pub fn add_font(&mut self, font: Arc<Font>) {
+        for family in &font.family_names {
+            self.add_face(family, Face {
+                font: font.clone(),
+                weight: (font.weight, font.weight),
+                style: font.style,
+                stretch: (font.stretch, font.stretch),
+            });
+        }
+    }
+
+    fn add_face(&mut self, family: &str, face: Face) {
+        let family = family.to_lowercase();
+        if !self.families.contains_key(&family) {
+            self.family_order.push(family.clone());
+        }
+        self.families.entry(family).or_default().push(face);
+    }
+
+    /// Add the TrueType and OpenType fonts in a directory and its subdirectories, in order of
+    /// their paths.  Symbolic links to directories aren't followed, so they can't form a loop.
+    /// Only an error reading `directory` itself is returned: files and subdirectories that can't
+    /// be read or parsed are skipped.
+    pub fn load_directory(&mut self, directory: &Path) -> io::Result<()> {
+        let mut entries = fs::read_dir(directory)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| Some((entry.path(), entry.file_type().ok()?)))
+            .collect::<Vec<_>>();
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (path, file_type) in entries {
+            if file_type.is_dir() {
+                self.load_directory(&path).ok();
+                continue;
+            }
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+            if !matches!(&*extension.to_ascii_lowercase(), "ttf" | "otf" | "ttc" | "otc") {
+                continue;
+            }
+            if let Some(font) = fs::read(&path).ok().and_then(Font::parse) {
+                self.add_font(Arc::new(font));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the fonts of a stylesheet's `@font-face` rules.  Relative URLs are resolved against
+    /// `base`, the directory that contains the stylesheet.  Rules without a `font-family` or
+    /// without a source that loads are ignored.
+    ///
+    /// https://www.w3.org/TR/css-fonts-4/#font-face-rule
+    pub fn load_font_faces(&mut self, stylesheet: &Stylesheet, base: &Path) {
+        for rule in &stylesheet.at_rules {
+            let AtRule::FontFace(descriptors) = rule else { continue };
+            let descriptor = |name: &str| {
+                descriptors.iter().rev().find(|d| d.name == name).map(|d| &d.value)
+            };
+            let Some(family) = descriptor("font-family").and_then(family_name) else { continue };
+            let Some(font) = descriptor("src").and_then(|src| self.load_source(src, base)) else {
+                continue
+            };
+            let face = Face {
+                weight: descriptor("font-weight").and_then(range::<FontWeight>)
+                    .unwrap_or((font.weight, font.weight)),
+                style: descriptor("font-style")
+                    .and_then(|style| FontStyle::compute(style.clone(), &Context::initial()))
+                    .unwrap_or(font.style),
+                stretch: descriptor("font-stretch").and_then(range::<FontStretch>)
+                    .unwrap_or((font.stretch, font.stretch)),
+                font,
+            };
+            self.add_face(&family, face);
+        }
+    }
+
+    /// Load the first source in a `src` descriptor that is a font: a `local()` font that is
+    /// already in the database, or a `url()` of a file.  Network URLs are not supported.
+    ///
+    /// https://www.w3.org/TR/css-fonts-4/#src-desc
+    fn load_source(&self, src: &Value, base: &Path) -> Option<Arc<Font>> {
+        let sources = match src {
+            Value::CommaList(sources) => &sources[..],
+            source => std::slice::from_ref(source),
+        };
+        // Each source can be followed by hints like `format()`, which are ignored.
+        sources.iter().find_map(|source| match source.items().first()? {
+            Value::Function(name, args) if name == "url" => {
+                let Some(Value::Str(url)) = args.first() else { return None };
+                let path = url.strip_prefix("file://").unwrap_or(url);
+                Font::parse(fs::read(base.join(path)).ok()?).map(Arc::new)
+            }
+            Value::Function(name, args) if name == "local" => {
+                let name = family_name(args.first()?)?;
+                let is_named = |face: &&Face| {
+                    face.font.full_names.iter().any(|n| n.eq_ignore_ascii_case(&name))
+                };
+                self.families.values().flatten().find(is_named).map(|face| face.font.clone())
+            }
+            _ => None,
+        })
+    }
+
+    /// The face in a family that best matches a style, or `None` if there is no such family.
+    ///
+    /// https://www.w3.org/TR/css-fonts-4/#font-style-matching
+    fn match_family(&self, family: &str, style: &ComputedStyle) -> Option<&Arc<Font>> {
+        let key = |face: &Face| {
+            (distance(face.stretch, style.font_stretch, style.font_stretch <= 100.0),
+             style_rank(face.style, style.font_style),
+             weight_distance(face.weight, style.font_weight))
+        };
+        let faces = self.families.get(&family.to_lowercase())?;
+        // The first of several equally good faces is used.
+        faces.iter()
+            .min_by(|a, b| key(a).partial_cmp(&key(b)).unwrap_or(Ordering::Equal))
+            .map(|face| &face.font)
+    }
+
+    /// The fonts for each family in a style's `font-family` list that is available, followed by
+    /// the default font.
+    fn fonts<'a>(&'a self, style: &'a ComputedStyle) -> impl Iterator<Item = &'a Arc<Font>> {
+        style.font_family.iter().filter_map(move |family| match family {
+            FamilyName::Named(name) => self.match_family(name, style),
+            FamilyName::Generic(generic) => generic_families(*generic).iter()
+                .find_map(|name| self.match_family(name, style)),
+        }).chain(std::iter::once(default_font()))
+    }
+
+    /// The first available font for a style, which sets the ascent, descent and normal line
+    /// height of its boxes.
+    ///
+    /// https://www.w3.org/TR/css-fonts-4/#first-available-font
+    pub fn primary_font<'a>(&'a self, style: &'a ComputedStyle) -> &'a Arc<Font> {
+        self.fonts(style).next().unwrap()
+    }
+
+    /// Map text to glyphs with a style's fonts, using the first font in the list that has each
+    /// character, or else the best match in any other family that has it.  The style's
+    /// `letter-spacing` is added after each character, and its `word-spacing` after each word
+    /// separator.
+    ///
+    /// https://www.w3.org/TR/css-fonts-4/#cluster-matching
+    /// https://www.w3.org/TR/css-text-3/#spacing
+    pub fn shape(&self, text: &str, style: &ComputedStyle) -> ShapedText {
+        let mut fonts: Vec<&Arc<Font>> = Vec::new();
+        for font in self.fonts(style) {
+            if !fonts.iter().any(|f| Arc::ptr_eq(f, font)) {
+                fonts.push(font);
+            }
+        }
+        let primary = fonts[0];
+        let font_for = |c: char| {
+            fonts.iter().copied().find(|font| font.has_glyph(c)).or_else(|| {
+                self.family_order.iter()
+                    .filter_map(|family| self.match_family(family, style))
+                    .find(|font| font.has_glyph(c))
+            }).unwrap_or(primary)
+        };
+
+        let size = style.font_size;
+        let ascent = primary.ascent(size);
+        let shape_run = |font: &Arc<Font>, text: &str| {
+            let (mut glyphs, mut width) = font.shape(text, size);
+            let mut spacing = 0.0;
+            for (glyph, c) in glyphs.iter_mut().zip(text.chars()) {
+                glyph.x += spacing;
+                glyph.y = ascent;
+                spacing += style.letter_spacing;
+                if is_word_separator(c) {
+                    spacing += style.word_spacing;
+                }
+            }
+            width += spacing;
+            let runs = vec![GlyphRun { font: font.clone(), glyphs }];
+            ShapedText { text: text.to_string(), runs, width }
+        };
+
+        // Split the text into runs of characters that use the same font.
+        let mut shaped = ShapedText::default();
+        let mut start = 0;
+        let mut current: Option<&Arc<Font>> = None;
+        for (i, c) in text.char_indices() {
+            let font = font_for(c);
+            if let Some(previous) = current.filter(|&previous| !Arc::ptr_eq(previous, font)) {
+                shaped.push(shape_run(previous, &text[start..i]));
+                start = i;
+            }
+            current = Some(font);
+        }
+        if let Some(font) = current {
+            shaped.push(shape_run(font, &text[start..]));
+        }
+        shaped
+    }
+}
+
+/// Is this a character that `word-spacing` applies to?
+///
+/// https://www.w3.org/TR/css-text-3/#word-separator
+fn is_word_separator(c: char) -> bool {
+    matches!(c, ' ' | '\u{A0}' | '\u{1361}' | '\u{10100}' | '\u{10101}' | '\u{1039F}'
+                | '\u{1091F}')
+}
+
+/// The families tried for a generic family, in order.
+fn generic_families(generic: GenericFamily) -> &'static [&'static str] {
+    match generic {
+        GenericFamily::Serif | GenericFamily::UiSerif => {
+            &["DejaVu Serif", "Times New Roman", "Liberation Serif", "Noto Serif", "Times"]
+        }
+        GenericFamily::SansSerif | GenericFamily::SystemUi | GenericFamily::UiSansSerif
+            | GenericFamily::UiRounded => {
+            &["DejaVu Sans", "Arial", "Liberation Sans", "Noto Sans", "Helvetica"]
+        }
+        GenericFamily::Monospace | GenericFamily::UiMonospace => {
+            &["DejaVu Sans Mono", "Courier New", "Liberation Mono", "Noto Sans Mono", "Courier"]
+        }
+        GenericFamily::Cursive => &["Comic Sans MS", "URW Chancery L", "Apple Chancery"],
+        GenericFamily::Fantasy => &["Impact", "Papyrus"],
+        GenericFamily::Math => &["DejaVu Math TeX Gyre", "STIX Two Math", "Cambria Math"],
+        GenericFamily::Emoji => &["Noto Color Emoji", "Apple Color Emoji", "Segoe UI Emoji"],
+        GenericFamily::Fangsong => &["FangSong", "STFangsong"],
+    }
+}
+
+/// Parse a descriptor that is a single value or a range of two, like `font-weight: 100 900`.
+fn range<T: PropertyType<Computed = f32>>(value: &Value) -> Option<(f32, f32)> {
+    let compute = |value: &Value| T::compute(value.clone(), &Context::initial());
+    match value.items() {
+        [value] => compute(value).map(|v| (v, v)),
+        [min, max] => {
+            let (min, max) = (compute(min)?, compute(max)?);
+            Some((min.min(max), min.max(max)))
+        }
+        _ => None,
+    }
+}
+
+/// How far a face's range of values is from the desired value, for sorting.  Values in the
+/// range come first, then those on the preferred side from nearest to farthest, then those on
+/// the other side.
+fn distance((min, max): (f32, f32), desired: f32, prefer_lower: bool) -> (u8, f32) {
+    if desired < min {
+        (if prefer_lower { 2 } else { 1 }, min - desired)
+    } else if desired > max {
+        (if prefer_lower { 1 } else { 2 }, desired - max)
+    } else {
+        (0, 0.0)
+    }
+}
+
+/// Like `distance`, for `font-weight`.  Desired weights from 400 to 500 prefer heavier weights
+/// up to 500, then lighter ones, then weights over 500.
+fn weight_distance(weight: (f32, f32), desired: f32) -> (u8, f32) {
+    if (400.0..=500.0).contains(&desired) {
+        match distance(weight, desired, false) {
+            (1, d) if desired + d > 500.0 => (3, d),
+            key => key,
+        }
+    } else {
+        distance(weight, desired, desired < 400.0)
+    }
+}
+
+/// The order of preference of a face's style for a desired style.
+fn style_rank(style: FontStyle, desired: FontStyle) -> usize {
+    let order = match desired {
+        FontStyle::Italic => [FontStyle::Italic, FontStyle::Oblique, FontStyle::Normal],
+        FontStyle::Oblique => [FontStyle::Oblique, FontStyle::Italic, FontStyle::Normal],
+        FontStyle::Normal => [FontStyle::Normal, FontStyle::Oblique, FontStyle::Italic],
+    };
+    order.iter().position(|&s| s == style).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn load_directory_skips_bad_files_and_links() {
+        let directory = std::env::temp_dir().join(format!("robinson-fonts-{}", std::process::id()));
+        let nested = directory.join("nested");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(nested.join("DejaVuSans.ttf"), include_bytes!("../fonts/DejaVuSans.ttf"))
+            .unwrap();
+        fs::write(directory.join("broken.ttf"), b"not a font").unwrap();
+        // A link back to the directory itself, and one to a file that doesn't exist.
+        std::os::unix::fs::symlink(&directory, nested.join("loop")).unwrap();
+        std::os::unix::fs::symlink(directory.join("missing.ttf"), directory.join("dangling.ttf"))
+            .unwrap();
+
+        let mut fonts = FontDatabase::new();
+        let result = fonts.load_directory(&directory);
+        let missing = fonts.load_directory(&directory.join("missing"));
+        fs::remove_dir_all(&directory).unwrap();
+        result.unwrap();
+        assert!(missing.is_err());
+        // The built-in font, and the copy of it.
+        assert_eq!(fonts.families["dejavu sans"].len(), 2);
+    }
+}
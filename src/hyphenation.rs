@@ -0,0 +1,103 @@
+//! Automatic hyphenation with Liang's algorithm, for `hyphens: auto`.
+//!
+//! A dictionary of patterns gives a level to some of the places between letters, depending on
+//! the letters around them.  Each place takes the highest level of all the patterns that match
+//! there, and a word can be hyphenated where the level is odd.
+//!
+//! Documents don't have a language yet, so all text is hyphenated with the US English patterns.
+//!
+//! https://tug.org/docs/liang/
+//! https://www.w3.org/TR/css-text-3/#hyphenation
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// The hyphenation patterns for one language.
+pub struct Hyphenator {
+    /// The levels between the letters of each pattern, including before the first and after the
+    /// last, keyed by the pattern's letters.
+    patterns: HashMap<String, Vec<u8>>,
+    /// The length of the longest pattern, in characters.
+    max_length: usize,
+    /// The fewest characters that can go before a hyphen.
+    left_min: usize,
+    /// The fewest characters that can go after a hyphen.
+    right_min: usize,
+}
+
+/// The hyphenator for US English, which is used for all text.
+pub fn default_hyphenator() -> &'static Hyphenator {
+    static HYPHENATOR: OnceLock<Hyphenator> = OnceLock::new();
+    HYPHENATOR.get_or_init(|| Hyphenator::parse(include_str!("../data/hyph-en-us.pat.txt")))
+}
+
+impl Hyphenator {
+    /// Parse patterns in the format used by TeX, one per line, like `.ach4` or `n2at`, with
+    /// comments starting with `%`.
+    pub fn parse(source: &str) -> Hyphenator {
+        let mut patterns = HashMap::new();
+        let mut max_length = 0;
+        let lines = source.lines().map(|line| line.split('%').next().unwrap());
+        for pattern in lines.flat_map(str::split_whitespace) {
+            let mut letters = String::new();
+            let mut levels = vec![0];
+            for c in pattern.chars() {
+                match c.to_digit(10) {
+                    Some(level) => *levels.last_mut().unwrap() = level as u8,
+                    None => {
+                        letters.push(c);
+                        levels.push(0);
+                    }
+                }
+            }
+            max_length = max_length.max(levels.len() - 1);
+            patterns.insert(letters, levels);
+        }
+        Hyphenator { patterns, max_length, left_min: 2, right_min: 3 }
+    }
+
+    /// Find the places where the words in `text` can be hyphenated, as byte indices of the
+    /// characters that would start the next line.  Words are runs of letters.
+    pub fn hyphenate(&self, text: &str) -> Vec<usize> {
+        let mut points = Vec::new();
+        let mut word: Vec<(usize, char)> = Vec::new();
+        for (i, c) in text.char_indices().chain([(text.len(), ' ')]) {
+            if c.is_alphabetic() {
+                word.push((i, c));
+            } else if !word.is_empty() {
+                points.extend(self.hyphenate_word(&word));
+                word.clear();
+            }
+        }
+        points
+    }
+
+    fn hyphenate_word(&self, word: &[(usize, char)]) -> Vec<usize> {
+        if word.len() < self.left_min + self.right_min {
+            return Vec::new();
+        }
+        // Patterns with `.` only match at the start or end of a word.
+        let letters: Vec<char> = std::iter::once('.')
+            .chain(word.iter().map(|&(_, c)| c.to_lowercase().next().unwrap()))
+            .chain(std::iter::once('.'))
+            .collect();
+        let mut levels = vec![0; letters.len() + 1];
+        let mut key = String::new();
+        for start in 0..letters.len() {
+            key.clear();
+            for end in start + 1..=letters.len().min(start + self.max_length) {
+                key.push(letters[end - 1]);
+                if let Some(pattern) = self.patterns.get(&key) {
+                    for (level, &pattern_level) in levels[start..].iter_mut().zip(pattern) {
+                        *level = (*level).max(pattern_level);
+                    }
+                }
+            }
+        }
+        // The level before the letter at `i` in the word is at `i + 1`, after the leading `.`.
+        (self.left_min..=word.len() - self.right_min)
+            .filter(|&i| levels[i + 1] % 2 == 1)
+            .map(|i| word[i].0)
+            .collect()
+    }
+}
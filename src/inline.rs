@@ -0,0 +1,983 @@
+//! Inline layout: breaking text and inline boxes into line boxes.
+//!
+//! The inline-level children of an anonymous block are flattened into a list of items: the start
+//! and end of each inline box, words, spaces, and other places where a line can break, which are
+//! found with the Unicode line breaking algorithm and hyphenation.  Lines are filled greedily,
+//! breaking at the last opportunity before the next word that doesn't fit.  A word that doesn't
+//! fit on a line by itself can be split anywhere if `overflow-wrap` allows it.  Breaks are only
+//! found within each text node, so text in different inline boxes only breaks at spaces between
+//! them.
+//!
+//! Before lines are broken, the bidirectional algorithm finds the embedding level of each item, so
+//! that the pieces of each line can be put in display order.  Then each line gets a fragment of
+//! every inline box that has content on it, so a box that is split across lines is painted once
+//! per line, with the margin, border and padding at its start on its first fragment and those at
+//! its end on its last.  A box that is split by reordering gets a fragment for each piece.
+//! Finally, the line's content is moved into place according to `text-align`, and justified
+//! lines have their spaces stretched.
+//!
+//! http://www.w3.org/TR/CSS2/visuren.html#inline-formatting
+//! https://www.w3.org/TR/css-inline-3/
+//! https://www.w3.org/TR/css-writing-modes-3/#text-direction
+
+use crate::bidi::{self, Level};
+use crate::font::ShapedText;
+use crate::font_database::FontDatabase;
+use crate::hyphenation::default_hyphenator;
+use crate::layout::{Dimensions, LayoutBox};
+use crate::layout::{AnonymousBlock, BlockNode, InlineNode, LineBox, TextRun};
+use crate::line_break::line_breaks;
+use crate::properties::ComputedStyle;
+use crate::style::StyledNode;
+use crate::values::{Direction, Hyphens, LineBreak, LineHeight, OverflowWrap, TextAlign};
+use crate::values::{TextAlignLast, TextTransform, UnicodeBidi, WhiteSpace, WordBreak};
+use std::collections::HashMap;
+
+/// A piece of inline content, in document order.
+struct Item<'a> {
+    kind: ItemKind<'a>,
+    /// The horizontal space the item takes up on a line.
+    width: f32,
+    /// The item's embedding level, from `resolve_bidi`.
+    level: Level,
+}
+
+enum ItemKind<'a> {
+    /// The start of an inline box.  Its width is the margin, border and padding at the box's
+    /// start, which is its left side unless it is right to left.
+    Open(&'a StyledNode<'a>),
+    /// The end of an inline box.  Its width is the margin, border and padding at the box's end.
+    Close(&'a StyledNode<'a>),
+    /// Text that can't be broken across lines.  Right-to-left text is shaped in display order.
+    Text(&'a StyledNode<'a>, ShapedText),
+    /// One or more spaces.  A line can break after them, if their `white-space` allows wrapping.
+    Space(&'a StyledNode<'a>, ShapedText),
+    /// A place between words where a line can break, with the hyphen that ends the line if it
+    /// does break here.  It takes up no space unless the line breaks.
+    BreakOpportunity(Option<(&'a StyledNode<'a>, ShapedText)>),
+    /// A preserved newline, which ends the line.
+    ForcedBreak,
+    /// A block-level box inside an inline box, which goes between the lines before and after it.
+    Block(LayoutBox<'a>),
+}
+
+/// How the `white-space` property treats spaces and newlines.
+///
+/// https://www.w3.org/TR/css-text-3/#white-space-property
+struct WhiteSpaceRules {
+    /// Whether runs of spaces, tabs and newlines collapse into a single space, which is removed
+    /// at the start and end of a line.
+    collapse: bool,
+    /// Whether newlines are forced line breaks.
+    preserve_newlines: bool,
+    /// Whether lines can break at spaces.
+    wrap: bool,
+}
+
+fn white_space_rules(style: &ComputedStyle) -> WhiteSpaceRules {
+    let (collapse, preserve_newlines, wrap) = match style.white_space {
+        WhiteSpace::Normal => (true, false, true),
+        WhiteSpace::Nowrap => (true, false, false),
+        WhiteSpace::Pre => (false, true, false),
+        WhiteSpace::PreWrap => (false, true, true),
+        WhiteSpace::PreLine => (true, true, true),
+    };
+    WhiteSpaceRules { collapse, preserve_newlines, wrap }
+}
+
+/// Lay out the inline-level children of an anonymous block in line boxes, stacked down from the
+/// top of `block`'s content area.  `strut` is the style of the block container, which sets the
+/// minimum height of each line and how lines are aligned, and `fonts` has the fonts that text is
+/// drawn with.  If the anonymous block is the first thing in the block container, `first` is
+/// true, and its first line is indented by `text-indent`.
+///
+/// Returns the line boxes, along with any block-level boxes that were inside inline boxes, and
+/// adds their height to `block.content.height`.  Lines with nothing visible on them, like those
+/// that only contain collapsed spaces, are left out.
+pub fn layout_lines<'a>(boxes: Vec<LayoutBox<'a>>, block: &mut Dimensions,
+                        strut: &ComputedStyle, first: bool,
+                        fonts: &FontDatabase) -> Vec<LayoutBox<'a>> {
+    let mut items = Vec::new();
+    let mut collapse_next = true;
+    for layout_box in boxes {
+        flatten(layout_box, block.content.width, fonts, &mut items, &mut collapse_next);
+    }
+    let items = resolve_bidi(items, strut, fonts);
+
+    let indent = if first { strut.text_indent.resolve(block.content.width) } else { 0.0 };
+
+    let mut children = Vec::new();
+    // The inline boxes that are still open at the end of the previous line.
+    let mut open = Vec::new();
+    let mut lines = break_lines(items, block.content.width, indent, fonts).into_iter().peekable();
+    let mut first_line = true;
+    while let Some(mut line) = lines.next() {
+        let top = block.content.y + block.content.height;
+        if let [Item { kind: ItemKind::Block(_), .. }] = line[..] {
+            let Some(ItemKind::Block(mut block_box)) = line.pop().map(|item| item.kind) else {
+                unreachable!()
+            };
+            block_box.layout(*block, strut.direction, fonts);
+            block.content.height += block_box.dimensions.margin_box().height;
+            children.push(block_box);
+            first_line = false;
+            continue;
+        }
+        // The last line of a paragraph is the one before a forced break or a block, or at the end.
+        let last = matches!(line.last(), Some(Item { kind: ItemKind::ForcedBreak, .. }))
+            || match lines.peek() {
+                Some(next) => matches!(next[..], [Item { kind: ItemKind::Block(_), .. }]),
+                None => true,
+            };
+        let indent = if std::mem::take(&mut first_line) { indent } else { 0.0 };
+        let was_open = open.clone();
+        let has_content = line.iter().any(|item| match item.kind {
+            ItemKind::Text(..) => true,
+            ItemKind::Space(node, _) => !white_space_rules(&node.style).collapse,
+            ItemKind::Open(_) | ItemKind::Close(_) => item.width != 0.0,
+            _ => false,
+        });
+        let offset = align_line(&mut line, block.content.width, indent, strut, last);
+        let mut line_box = build_line(line, &mut open, block, strut);
+        for_each_fragment(&mut line_box, &mut |fragment| {
+            fragment.dimensions.content.x += offset;
+        });
+        // A line with no content takes up no space, and isn't kept.
+        if has_content || was_open.iter().any(|node| has_vertical_edges(node, block)) {
+            place_line(&mut line_box, top, strut, block.content.width, fonts);
+            block.content.height += line_box.dimensions.content.height;
+            children.push(line_box);
+        }
+    }
+    children
+}
+
+/// Lay out the marker of a list item with `list-style-position: outside` on a line of its own,
+/// outside the start edge of `block`, the list item's content area.  The line's top is at `top`,
+/// which is the top of the list item's first line.  `strut` is the list item's style.
+///
+/// https://www.w3.org/TR/css-lists-3/#list-style-position-property
+pub fn layout_outside_marker<'a>(marker: LayoutBox<'a>, block: &Dimensions, top: f32,
+                                 strut: &ComputedStyle, fonts: &FontDatabase) -> LayoutBox<'a> {
+    let mut items = Vec::new();
+    flatten(marker, block.content.width, fonts, &mut items, &mut true);
+    // The marker is never broken across lines.
+    let line: Vec<Item> = resolve_bidi(items, strut, fonts).into_iter()
+        .filter(|item| !matches!(item.kind, ItemKind::BreakOpportunity(_) | ItemKind::ForcedBreak))
+        .collect();
+    let width: f32 = line.iter().map(|item| item.width).sum();
+    let mut line_box = build_line(line, &mut Vec::new(), block, strut);
+    let offset = match strut.direction {
+        Direction::Ltr => -width,
+        Direction::Rtl => block.content.width,
+    };
+    line_box.dimensions.content.x += offset;
+    line_box.dimensions.content.width = width;
+    for_each_fragment(&mut line_box, &mut |fragment| {
+        fragment.dimensions.content.x += offset;
+    });
+    place_line(&mut line_box, top, strut, block.content.width, fonts);
+    line_box
+}
+
+/// Does this inline box have any vertical padding or borders, which make a line it is on
+/// visible?
+fn has_vertical_edges(node: &StyledNode, block: &Dimensions) -> bool {
+    let style = &node.style;
+    let width = block.content.width;
+    style.padding_top.resolve(width) + style.padding_bottom.resolve(width)
+        + style.border_top_width + style.border_bottom_width != 0.0
+}
+
+/// Add the items for an inline-level box and its descendants.
+///
+/// `collapse_next` is true if a collapsible space at this point would be removed, because it
+/// follows another one or is at the start of the block.
+fn flatten<'a>(layout_box: LayoutBox<'a>, containing_width: f32, fonts: &FontDatabase,
+               items: &mut Vec<Item<'a>>, collapse_next: &mut bool) {
+    let node = match layout_box.box_type {
+        InlineNode(node) => node,
+        BlockNode(_) => {
+            items.push(Item { kind: ItemKind::Block(layout_box), width: 0.0, level: 0 });
+            *collapse_next = true;
+            return;
+        }
+        AnonymousBlock | LineBox | TextRun(..) => unreachable!("not an inline-level box"),
+    };
+    if let Some(text) = node.text() {
+        flatten_text(node, text, fonts, items, collapse_next);
+        return;
+    }
+    let (left, right) = (left_edges(node, containing_width), right_edges(node, containing_width));
+    let (start, end) = match node.style.direction {
+        Direction::Ltr => (left, right),
+        Direction::Rtl => (right, left),
+    };
+    items.push(Item { kind: ItemKind::Open(node), width: start, level: 0 });
+    for child in layout_box.children {
+        flatten(child, containing_width, fonts, items, collapse_next);
+    }
+    items.push(Item { kind: ItemKind::Close(node), width: end, level: 0 });
+}
+
+/// The total width of an inline box's left margin, border and padding.
+fn left_edges(node: &StyledNode, containing_width: f32) -> f32 {
+    let style = &node.style;
+    style.margin_left.resolve(containing_width).unwrap_or(0.0)
+        + style.border_left_width + style.padding_left.resolve(containing_width)
+}
+
+/// The total width of an inline box's right margin, border and padding.
+fn right_edges(node: &StyledNode, containing_width: f32) -> f32 {
+    let style = &node.style;
+    style.margin_right.resolve(containing_width).unwrap_or(0.0)
+        + style.border_right_width + style.padding_right.resolve(containing_width)
+}
+
+/// Split text into words, spaces, forced breaks and break opportunities, processing white space
+/// according to the `white-space` property, and changing case according to `text-transform`.
+///
+/// https://www.w3.org/TR/css-text-3/#white-space-processing
+fn flatten_text<'a>(node: &'a StyledNode<'a>, text: &str, fonts: &FontDatabase,
+                    items: &mut Vec<Item<'a>>, collapse_next: &mut bool) {
+    let rules = white_space_rules(&node.style);
+    let text = transform(text, node.style.text_transform);
+    // The text between preserved newlines, with its white space processed.
+    let mut lines = vec![String::new()];
+    for c in text.chars() {
+        let line = lines.last_mut().unwrap();
+        match c {
+            '\n' if rules.preserve_newlines => {
+                lines.push(String::new());
+                *collapse_next = true;
+            }
+            ' ' | '\t' | '\n' | '\r' => {
+                if !rules.collapse {
+                    line.push(' ');
+                    *collapse_next = false;
+                } else if !*collapse_next {
+                    line.push(' ');
+                    *collapse_next = true;
+                }
+            }
+            c => {
+                line.push(c);
+                *collapse_next = false;
+            }
+        }
+    }
+    for (i, line) in lines.iter().enumerate() {
+        if i > 0 {
+            items.push(Item { kind: ItemKind::ForcedBreak, width: 0.0, level: 0 });
+        }
+        flatten_line(node, line, rules.wrap, fonts, items);
+    }
+}
+
+/// Change the case of text for `text-transform`, with Unicode's full case mappings, in which some
+/// characters map to more than one, like `ß` to `SS`.  Text doesn't have a language yet, so
+/// there's no special casing for languages like Turkish.
+///
+/// https://www.w3.org/TR/css-text-3/#text-transform-property
+fn transform(text: &str, transform: TextTransform) -> String {
+    match transform {
+        TextTransform::None => text.to_string(),
+        TextTransform::Uppercase => text.to_uppercase(),
+        TextTransform::Lowercase => text.to_lowercase(),
+        TextTransform::Capitalize => {
+            // A word starts at a letter or number that doesn't follow another one, or an
+            // apostrophe inside a word.
+            let mut in_word = false;
+            let mut capitalized = String::with_capacity(text.len());
+            for c in text.chars() {
+                if c.is_alphanumeric() && !in_word {
+                    capitalized.extend(titlecase(c));
+                } else {
+                    capitalized.push(c);
+                }
+                in_word = c.is_alphanumeric() || in_word && matches!(c, '\'' | '\u{2019}');
+            }
+            capitalized
+        }
+    }
+}
+
+/// The titlecase mapping of a character, which capitalizes just the first letter of those that
+/// uppercase to more than one, like `ß` to `Ss`, and has special forms for digraphs like `ǆ` and
+/// Greek letters with an iota subscript.
+///
+/// https://www.unicode.org/Public/UCD/latest/ucd/SpecialCasing.txt
+fn titlecase(c: char) -> Vec<char> {
+    let title = match c {
+        '\u{1C4}'..='\u{1C6}' => '\u{1C5}',
+        '\u{1C7}'..='\u{1C9}' => '\u{1C8}',
+        '\u{1CA}'..='\u{1CC}' => '\u{1CB}',
+        '\u{1F1}'..='\u{1F3}' => '\u{1F2}',
+        // ᾀ to ᾯ come in groups of eight lowercase letters followed by their titlecase forms.
+        '\u{1F80}'..='\u{1FAF}' => char::from_u32(c as u32 | 8).unwrap(),
+        '\u{1FB3}' | '\u{1FBC}' => '\u{1FBC}',
+        '\u{1FC3}' | '\u{1FCC}' => '\u{1FCC}',
+        '\u{1FF3}' | '\u{1FFC}' => '\u{1FFC}',
+        c => {
+            let mut upper = c.to_uppercase();
+            let first = upper.next().unwrap();
+            return std::iter::once(first).chain(upper.flat_map(char::to_lowercase)).collect();
+        }
+    };
+    vec![title]
+}
+
+/// Split text without newlines at its line break opportunities.  Only mandatory breaks, like
+/// those after a line separator, are kept if the text doesn't wrap.
+///
+/// https://www.w3.org/TR/css-text-3/#line-breaking
+/// https://www.w3.org/TR/css-text-3/#hyphenation
+fn flatten_line<'a>(node: &'a StyledNode<'a>, line: &str, wrap: bool, fonts: &FontDatabase,
+                    items: &mut Vec<Item<'a>>) {
+    let style = &node.style;
+    let breaks = line_breaks(line, style.word_break, style.line_break).into_iter()
+        .filter(|b| b.mandatory || wrap)
+        // A soft hyphen is only a break opportunity if hyphenation isn't turned off.
+        .filter(|b| style.hyphens != Hyphens::None || !line[..b.index].ends_with(SOFT_HYPHEN))
+        .map(|b| (b.index, Some(b.mandatory)));
+    let mut start = 0;
+    for (end, mandatory) in breaks.chain([(line.len(), None)]) {
+        let segment = &line[start..end];
+        // Spaces at the start of the text can be broken after, like those between words.
+        let trimmed = if start == 0 { segment.trim_start_matches(' ') } else { segment };
+        let leading = segment.len() - trimmed.len();
+        let word = segment[leading..].trim_end_matches(is_line_separator).trim_end_matches(' ');
+        let spaces = &segment[leading + word.len()..];
+        push_spaces(node, &segment[..leading], fonts, items);
+        flatten_word(node, word, wrap, fonts, items);
+        push_spaces(node, spaces, fonts, items);
+        match mandatory {
+            Some(true) => items.push(Item { kind: ItemKind::ForcedBreak, width: 0.0, level: 0 }),
+            Some(false) if !spaces.contains(' ') => {
+                let hyphen = word.ends_with(SOFT_HYPHEN).then(|| (node, hyphen(node, fonts)));
+                let kind = ItemKind::BreakOpportunity(hyphen);
+                items.push(Item { kind, width: 0.0, level: 0 });
+            }
+            _ => {}
+        }
+        start = end;
+    }
+}
+
+/// A soft hyphen, which is invisible unless a line breaks after it.
+const SOFT_HYPHEN: char = '\u{AD}';
+
+/// Is this a character that forces a line break, other than a newline?  They aren't drawn.
+fn is_line_separator(c: char) -> bool {
+    matches!(c, '\u{B}' | '\u{C}' | '\u{85}' | '\u{2028}' | '\u{2029}')
+}
+
+fn push_spaces<'a>(node: &'a StyledNode<'a>, spaces: &str, fonts: &FontDatabase,
+                   items: &mut Vec<Item<'a>>) {
+    let spaces: String = spaces.chars().filter(|&c| c == ' ').collect();
+    if !spaces.is_empty() {
+        let text = fonts.shape(&spaces, &node.style);
+        items.push(Item { width: text.width, kind: ItemKind::Space(node, text), level: 0 });
+    }
+}
+
+/// Add the items for text without break opportunities, except where `hyphens: auto` hyphenates
+/// it.  Words with soft hyphens in them are only hyphenated there.
+fn flatten_word<'a>(node: &'a StyledNode<'a>, word: &str, wrap: bool, fonts: &FontDatabase,
+                    items: &mut Vec<Item<'a>>) {
+    let hyphenate = wrap && node.style.hyphens == Hyphens::Auto && !word.contains(SOFT_HYPHEN);
+    let points = if hyphenate { default_hyphenator().hyphenate(word) } else { Vec::new() };
+    let mut start = 0;
+    for end in points.into_iter().chain([word.len()]) {
+        let text: String = word[start..end].chars()
+            .filter(|&c| c != SOFT_HYPHEN && !is_line_separator(c))
+            .collect();
+        if !text.is_empty() {
+            let text = fonts.shape(&text, &node.style);
+            items.push(Item { width: text.width, kind: ItemKind::Text(node, text), level: 0 });
+        }
+        if end < word.len() {
+            let hyphen = Some((node, hyphen(node, fonts)));
+            items.push(Item { kind: ItemKind::BreakOpportunity(hyphen), width: 0.0, level: 0 });
+        }
+        start = end;
+    }
+}
+
+/// The hyphen at the end of a hyphenated line: U+2010 HYPHEN, or a hyphen-minus if the font
+/// doesn't have it.
+fn hyphen(node: &StyledNode, fonts: &FontDatabase) -> ShapedText {
+    let font = fonts.primary_font(&node.style);
+    fonts.shape(if font.has_glyph('\u{2010}') { "\u{2010}" } else { "-" }, &node.style)
+}
+
+/// Find the embedding level of each item with the bidirectional algorithm.  Text is split where
+/// its level changes, and right-to-left text is shaped in display order.  Forced line breaks and
+/// block-level boxes end paragraphs, whose direction is that of the block container `strut`.
+///
+/// https://www.w3.org/TR/css-writing-modes-3/#bidi-algo
+fn resolve_bidi<'a>(items: Vec<Item<'a>>, strut: &ComputedStyle,
+                    fonts: &FontDatabase) -> Vec<Item<'a>> {
+    let mut resolved = Vec::with_capacity(items.len());
+    let mut paragraph = Vec::new();
+    // The inline boxes that are open at the current item, and at the start of the paragraph.
+    let mut open = Vec::new();
+    let mut paragraph_open = Vec::new();
+    for item in items {
+        match item.kind {
+            ItemKind::ForcedBreak | ItemKind::Block(_) => {
+                let items = std::mem::take(&mut paragraph);
+                resolve_paragraph(items, &paragraph_open, strut, fonts, &mut resolved);
+                resolved.push(item);
+                paragraph_open = open.clone();
+                continue;
+            }
+            ItemKind::Open(node) => open.push(node),
+            ItemKind::Close(_) => { open.pop(); }
+            _ => {}
+        }
+        paragraph.push(item);
+    }
+    resolve_paragraph(paragraph, &paragraph_open, strut, fonts, &mut resolved);
+    resolved
+}
+
+/// Resolve the levels of the items in one paragraph, which starts inside the inline boxes in
+/// `open`, and add them to `resolved`.
+fn resolve_paragraph<'a>(items: Vec<Item<'a>>, open: &[&StyledNode], strut: &ComputedStyle,
+                         fonts: &FontDatabase, resolved: &mut Vec<Item<'a>>) {
+    // The paragraph's text, with control characters for the embeddings and isolates of inline
+    // boxes, and the range of it that comes from each item.
+    let mut text: String = open.iter().map(|node| bidi_controls(&node.style).0).collect();
+    let mut ranges = Vec::with_capacity(items.len());
+    for item in &items {
+        let start = text.len();
+        match item.kind {
+            ItemKind::Open(node) => text.push_str(bidi_controls(&node.style).0),
+            ItemKind::Close(node) => text.push_str(bidi_controls(&node.style).1),
+            ItemKind::Text(_, ref shaped) | ItemKind::Space(_, ref shaped) => {
+                text.push_str(&shaped.text);
+            }
+            _ => {}
+        }
+        ranges.push(start..text.len());
+    }
+    let direction = match strut.unicode_bidi {
+        UnicodeBidi::Plaintext => bidi::first_strong_direction(&text).unwrap_or(Direction::Ltr),
+        _ => strut.direction,
+    };
+    let paragraph_level = bidi::paragraph_level(direction);
+    let levels = bidi::resolve_levels(&text, paragraph_level);
+    let level_at = |i: usize| levels.get(i).copied().unwrap_or(paragraph_level);
+    let level_before = |i: usize| i.checked_sub(1).map_or(paragraph_level, level_at);
+
+    for (mut item, range) in items.into_iter().zip(ranges) {
+        let (node, is_space) = match item.kind {
+            ItemKind::Text(node, _) => (node, false),
+            ItemKind::Space(node, _) => (node, true),
+            // The start of a box goes with the content after it, and other items with the
+            // content before them.
+            ItemKind::Open(_) => {
+                item.level = level_at(range.end);
+                resolved.push(item);
+                continue;
+            }
+            _ => {
+                item.level = level_before(range.start);
+                resolved.push(item);
+                continue;
+            }
+        };
+        let level = levels[range.start];
+        if levels[range.clone()].iter().all(|&l| l == level) && level.is_multiple_of(2) {
+            item.level = level;
+            resolved.push(item);
+            continue;
+        }
+        // Split the text into runs with the same level.
+        let mut start = range.start;
+        while start < range.end {
+            let level = levels[start];
+            let end = (start..range.end).find(|&i| levels[i] != level).unwrap_or(range.end);
+            let shaped = shape(&text[start..end], level, node, fonts);
+            let kind = if is_space {
+                ItemKind::Space(node, shaped.clone())
+            } else {
+                ItemKind::Text(node, shaped.clone())
+            };
+            resolved.push(Item { width: shaped.width, kind, level });
+            start = end;
+        }
+    }
+}
+
+/// The control characters that start and end an inline box with this style, for the
+/// bidirectional algorithm.
+///
+/// https://www.w3.org/TR/css-writing-modes-3/#unicode-bidi
+fn bidi_controls(style: &ComputedStyle) -> (&'static str, &'static str) {
+    let rtl = style.direction == Direction::Rtl;
+    match style.unicode_bidi {
+        UnicodeBidi::Normal => ("", ""),
+        UnicodeBidi::Embed if rtl => ("\u{202B}", "\u{202C}"),
+        UnicodeBidi::Embed => ("\u{202A}", "\u{202C}"),
+        UnicodeBidi::Isolate if rtl => ("\u{2067}", "\u{2069}"),
+        UnicodeBidi::Isolate => ("\u{2066}", "\u{2069}"),
+        UnicodeBidi::BidiOverride if rtl => ("\u{202E}", "\u{202C}"),
+        UnicodeBidi::BidiOverride => ("\u{202D}", "\u{202C}"),
+        UnicodeBidi::IsolateOverride if rtl => ("\u{2067}\u{202E}", "\u{202C}\u{2069}"),
+        UnicodeBidi::IsolateOverride => ("\u{2066}\u{202D}", "\u{202C}\u{2069}"),
+        UnicodeBidi::Plaintext => ("\u{2068}", "\u{2069}"),
+    }
+}
+
+/// Shape text at an embedding level.  Right-to-left text is shaped in display order, but keeps
+/// its text in logical order, so it can still be split.
+fn shape(text: &str, level: Level, node: &StyledNode, fonts: &FontDatabase) -> ShapedText {
+    if level.is_multiple_of(2) {
+        return fonts.shape(text, &node.style);
+    }
+    let mut shaped = fonts.shape(&bidi::reverse(text), &node.style);
+    shaped.text = text.to_string();
+    shaped
+}
+
+/// Fill lines greedily.  A block-level item gets a line to itself, and a line that ends with a
+/// forced break keeps it as its last item.  The first line is shortened by `indent`.
+fn break_lines<'a>(items: Vec<Item<'a>>, available_width: f32, indent: f32,
+                   fonts: &FontDatabase) -> Vec<Vec<Item<'a>>> {
+    let mut lines = Vec::new();
+    let mut line: Vec<Item> = Vec::new();
+    let mut width = indent;
+    for mut item in items {
+        match item.kind {
+            ItemKind::Text(..) => {
+                // Breaking at the last opportunity can leave a line that is still too long, if a
+                // hyphen didn't fit before it.
+                while width + item.width > available_width {
+                    let Some(i) = last_break(&line, available_width) else { break };
+                    let rest = split_line(&mut line, i);
+                    width = rest.iter().map(|item| item.width).sum();
+                    lines.push(trim_end(std::mem::replace(&mut line, rest)));
+                }
+                // Text that still doesn't fit can be split anywhere, if `overflow-wrap` allows.
+                while width + item.width > available_width {
+                    let ItemKind::Text(node, ref text) = item.kind else { unreachable!() };
+                    if !can_wrap_anywhere(&node.style) {
+                        break;
+                    }
+                    let at_start = !line.iter().any(|item| {
+                        matches!(item.kind, ItemKind::Text(..) | ItemKind::Space(..))
+                    });
+                    let level = item.level;
+                    let available = available_width - width;
+                    match split_text(node, text, level, available, at_start, fonts) {
+                        Some((head, tail)) => {
+                            let (head_width, tail_width) = (head.width, tail.width);
+                            let kind = ItemKind::Text(node, head);
+                            line.push(Item { width: head_width, kind, level });
+                            let kind = ItemKind::Text(node, tail);
+                            item = Item { width: tail_width, kind, level };
+                        }
+                        None if at_start => break,
+                        None => {}
+                    }
+                    // Inline boxes that start right before the split start on the next line.
+                    let i = line.iter()
+                        .rposition(|item| !matches!(item.kind, ItemKind::Open(_)))
+                        .map_or(0, |i| i + 1);
+                    let rest = line.split_off(i);
+                    width = rest.iter().map(|item| item.width).sum();
+                    lines.push(trim_end(std::mem::replace(&mut line, rest)));
+                }
+            }
+            ItemKind::Space(node, _) => {
+                let rules = white_space_rules(&node.style);
+                let at_start = !line.iter().any(|item| {
+                    matches!(item.kind, ItemKind::Text(..) | ItemKind::Space(..))
+                });
+                // Collapsible spaces at the start of a line are removed.
+                if rules.collapse && at_start {
+                    continue;
+                }
+            }
+            ItemKind::ForcedBreak => {
+                let mut ended = trim_end(std::mem::take(&mut line));
+                ended.push(item);
+                lines.push(ended);
+                width = 0.0;
+                continue;
+            }
+            ItemKind::Block(_) => {
+                if !line.is_empty() {
+                    lines.push(trim_end(std::mem::take(&mut line)));
+                }
+                lines.push(vec![item]);
+                width = 0.0;
+                continue;
+            }
+            ItemKind::Open(_) | ItemKind::Close(_) | ItemKind::BreakOpportunity(_) => {}
+        }
+        width += item.width;
+        line.push(item);
+    }
+    if !line.is_empty() {
+        lines.push(trim_end(line));
+    }
+    lines
+}
+
+/// Find the position in a line after the last place where it can break.  A hyphenation
+/// opportunity where the hyphen wouldn't fit is only used if there's no other.
+fn last_break(line: &[Item], available_width: f32) -> Option<usize> {
+    let mut width = 0.0;
+    let mut break_at = None;
+    for (i, item) in line.iter().enumerate() {
+        width += item.width;
+        match item.kind {
+            ItemKind::Space(node, _) if white_space_rules(&node.style).wrap => {
+                break_at = Some(i + 1);
+            }
+            ItemKind::BreakOpportunity(ref hyphen) => {
+                let hyphen_width = hyphen.as_ref().map_or(0.0, |(_, hyphen)| hyphen.width);
+                if width + hyphen_width <= available_width || break_at.is_none() {
+                    break_at = Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    break_at
+}
+
+/// Break a line after the item before `i`, and return the items after the break.  Boxes that end
+/// right after the break end on this line.  If the break is at a hyphenation opportunity, the
+/// line ends with a hyphen.
+fn split_line<'a>(line: &mut Vec<Item<'a>>, mut i: usize) -> Vec<Item<'a>> {
+    let opportunity = &mut line[i - 1];
+    if let ItemKind::BreakOpportunity(ref mut hyphen) = opportunity.kind {
+        if let Some((node, hyphen)) = hyphen.take() {
+            let level = opportunity.level;
+            let width = hyphen.width;
+            *opportunity = Item { width, kind: ItemKind::Text(node, hyphen), level };
+        }
+    }
+    while matches!(line.get(i), Some(Item { kind: ItemKind::Close(_), .. })) {
+        i += 1;
+    }
+    line.split_off(i)
+}
+
+/// Can text with this style be broken between any two characters, if there's no other place to
+/// break a line?
+///
+/// https://www.w3.org/TR/css-text-3/#overflow-wrap-property
+fn can_wrap_anywhere(style: &ComputedStyle) -> bool {
+    white_space_rules(style).wrap
+        && (style.overflow_wrap != OverflowWrap::Normal || style.word_break == WordBreak::BreakWord)
+}
+
+/// Split text so that as much of it as possible fits in `available_width`.  If none of it fits,
+/// the split is after the first character if the text is at the start of a line, and there's no
+/// split otherwise.
+fn split_text(node: &StyledNode, text: &ShapedText, level: Level, available_width: f32,
+              at_start: bool, fonts: &FontDatabase) -> Option<(ShapedText, ShapedText)> {
+    let text = &text.text;
+    let candidates: Vec<usize> = line_breaks(text, WordBreak::Normal, LineBreak::Anywhere)
+        .into_iter().map(|b| b.index).collect();
+    let fitting = candidates.partition_point(|&i| {
+        shape(&text[..i], level, node, fonts).width <= available_width
+    });
+    let i = match fitting {
+        0 if at_start => *candidates.first()?,
+        0 => return None,
+        n => candidates[n - 1],
+    };
+    Some((shape(&text[..i], level, node, fonts), shape(&text[i..], level, node, fonts)))
+}
+
+/// Remove collapsible spaces from the end of a line, including those inside inline boxes that
+/// end there.
+fn trim_end(mut line: Vec<Item>) -> Vec<Item> {
+    let mut i = line.len();
+    while i > 0 {
+        match line[i - 1].kind {
+            ItemKind::Open(_) | ItemKind::Close(_) | ItemKind::BreakOpportunity(_) => {}
+            ItemKind::Space(node, _) if white_space_rules(&node.style).collapse => {
+                line.remove(i - 1);
+            }
+            _ => break,
+        }
+        i -= 1;
+    }
+    line
+}
+
+/// Align a line's items horizontally in a line box of width `containing_width`, whose start is
+/// indented by `indent`.  Spaces are stretched if the line is justified.  Returns how far the
+/// line's content must move right from the left edge of the line box.
+///
+/// The last line of a paragraph is aligned by `text-align-last`.  A line that overflows, or can't
+/// be justified because it has no spaces, is aligned to its start.
+///
+/// https://www.w3.org/TR/css-text-3/#text-align-property
+/// https://www.w3.org/TR/css-text-3/#justify-content
+fn align_line(line: &mut [Item], containing_width: f32, indent: f32, strut: &ComputedStyle,
+              last: bool) -> f32 {
+    let align = match strut.text_align_last {
+        _ if !last => strut.text_align,
+        TextAlignLast::Auto if strut.text_align == TextAlign::Justify => TextAlign::Start,
+        TextAlignLast::Auto => strut.text_align,
+        TextAlignLast::Start => TextAlign::Start,
+        TextAlignLast::End => TextAlign::End,
+        TextAlignLast::Left => TextAlign::Left,
+        TextAlignLast::Right => TextAlign::Right,
+        TextAlignLast::Center => TextAlign::Center,
+        TextAlignLast::Justify => TextAlign::Justify,
+    };
+    let free = containing_width - indent - line.iter().map(|item| item.width).sum::<f32>();
+    let align = match align {
+        _ if free < 0.0 => TextAlign::Start,
+        TextAlign::Justify if !justify(line, free) => TextAlign::Start,
+        align => align,
+    };
+    let rtl = strut.direction == Direction::Rtl;
+    // The left edge of the space the line's content goes in.
+    let left = if rtl { 0.0 } else { indent };
+    match align {
+        TextAlign::Left | TextAlign::Justify => left,
+        TextAlign::Start if !rtl => left,
+        TextAlign::End if rtl => left,
+        TextAlign::Right | TextAlign::Start | TextAlign::End => left + free,
+        TextAlign::Center => left + free / 2.0,
+    }
+}
+
+/// Stretch the spaces on a line to take up `free` more width between them, if it has any.
+fn justify(line: &mut [Item], free: f32) -> bool {
+    let space_count = |item: &Item| match item.kind {
+        ItemKind::Space(_, ref text) => text.text.chars().count(),
+        _ => 0,
+    };
+    let count: usize = line.iter().map(space_count).sum();
+    if count == 0 {
+        return false;
+    }
+    for item in line {
+        let extra = free * space_count(item) as f32 / count as f32;
+        if let ItemKind::Space(_, ref mut text) = item.kind {
+            text.width += extra;
+            item.width += extra;
+        }
+    }
+    true
+}
+
+/// A piece of a line that is reordered as a unit: an item, along with the inline boxes it is in,
+/// outermost first.
+struct Leaf<'a> {
+    item: Item<'a>,
+    boxes: Vec<&'a StyledNode<'a>>,
+}
+
+/// Build a line box with a fragment of each inline box on the line, positioned horizontally.
+///
+/// The line's items are put in display order by their embedding levels.  A box gets a fragment
+/// for each run of items in it that are next to each other after reordering.  Its left margin,
+/// border and padding go on its leftmost fragment, if the box starts on this line (or ends on it,
+/// for a right-to-left box), and likewise for its right edges.
+///
+/// `open` holds the inline boxes that continue from the previous line, and is updated with those
+/// that continue onto the next one.  `strut` is the style of the block container.
+///
+/// https://www.w3.org/TR/css-writing-modes-3/#bidi-box-model
+fn build_line<'a>(items: Vec<Item<'a>>, open: &mut Vec<&'a StyledNode<'a>>, block: &Dimensions,
+                  strut: &ComputedStyle) -> LayoutBox<'a> {
+    let containing_width = block.content.width;
+    let mut x = block.content.x;
+    let mut line_box = LayoutBox::new(LineBox);
+    line_box.dimensions.content.x = x;
+    line_box.dimensions.content.width = containing_width;
+
+    // The start and end of each box are leaves inside it, so that the box gets a fragment even
+    // if it is empty.
+    let mut leaves = Vec::new();
+    let (mut starts, mut ends) = (Vec::new(), Vec::new());
+    for item in items {
+        match item.kind {
+            ItemKind::Open(node) => {
+                open.push(node);
+                starts.push(node);
+                leaves.push(Leaf { item, boxes: open.clone() });
+            }
+            ItemKind::Close(node) => {
+                leaves.push(Leaf { item, boxes: open.clone() });
+                open.pop();
+                ends.push(node);
+            }
+            ItemKind::Text(..) | ItemKind::Space(..) => {
+                leaves.push(Leaf { item, boxes: open.clone() });
+            }
+            ItemKind::ForcedBreak | ItemKind::Block(_) | ItemKind::BreakOpportunity(_) => {}
+        }
+    }
+    // Rule L1: Spaces at the end of the line are at the paragraph's level.
+    for leaf in leaves.iter_mut().rev() {
+        match leaf.item.kind {
+            ItemKind::Space(..) => leaf.item.level = bidi::paragraph_level(strut.direction),
+            ItemKind::Open(_) | ItemKind::Close(_) => {}
+            _ => break,
+        }
+    }
+    let levels: Vec<Level> = leaves.iter().map(|leaf| leaf.item.level).collect();
+    let mut leaves: Vec<Option<Leaf>> = leaves.into_iter().map(Some).collect();
+    let leaves: Vec<Leaf> = bidi::visual_order(&levels).into_iter()
+        .map(|i| leaves[i].take().unwrap())
+        .collect();
+
+    let key = |node: &StyledNode| node as *const StyledNode as usize;
+    let contains = |nodes: &[&StyledNode], node: &StyledNode| {
+        nodes.iter().any(|&n| std::ptr::eq(n, node))
+    };
+    // Which sides of each box have their edges on this line.
+    let has_left_edges = |node: &StyledNode| match node.style.direction {
+        Direction::Ltr => contains(&starts, node),
+        Direction::Rtl => contains(&ends, node),
+    };
+    let has_right_edges = |node: &StyledNode| match node.style.direction {
+        Direction::Ltr => contains(&ends, node),
+        Direction::Rtl => contains(&starts, node),
+    };
+    // The last leaf in each box, from left to right.
+    let mut last_leaf = HashMap::new();
+    for (i, leaf) in leaves.iter().enumerate() {
+        for &node in &leaf.boxes {
+            last_leaf.insert(key(node), i);
+        }
+    }
+    let mut has_fragment = Vec::new();
+
+    // The fragments that are still open, innermost last, above the line box.
+    let mut stack = vec![line_box];
+    let mut nodes: Vec<&StyledNode> = Vec::new();
+    let close = |stack: &mut Vec<LayoutBox<'a>>, node: &StyledNode, x: &mut f32, last: bool| {
+        let mut fragment = stack.pop().unwrap();
+        let d = &mut fragment.dimensions;
+        d.content.width = *x - d.content.x;
+        // Boxes that continue after this fragment have no right edges on it.
+        if last && has_right_edges(node) {
+            let style = &node.style;
+            d.margin.right = style.margin_right.resolve(containing_width).unwrap_or(0.0);
+            d.border.right = style.border_right_width;
+            d.padding.right = style.padding_right.resolve(containing_width);
+            *x += right_edges(node, containing_width);
+        }
+        stack.last_mut().unwrap().children.push(fragment);
+    };
+    for (i, leaf) in leaves.into_iter().enumerate() {
+        let common = nodes.iter().zip(&leaf.boxes).take_while(|(a, b)| std::ptr::eq(**a, **b))
+            .count();
+        while nodes.len() > common {
+            let node = nodes.pop().unwrap();
+            close(&mut stack, node, &mut x, last_leaf[&key(node)] < i);
+        }
+        for &node in &leaf.boxes[common..] {
+            let mut fragment = LayoutBox::new(InlineNode(node));
+            let first = !has_fragment.contains(&key(node));
+            if first && has_left_edges(node) {
+                let style = &node.style;
+                let d = &mut fragment.dimensions;
+                d.margin.left = style.margin_left.resolve(containing_width).unwrap_or(0.0);
+                d.border.left = style.border_left_width;
+                d.padding.left = style.padding_left.resolve(containing_width);
+                x += left_edges(node, containing_width);
+            }
+            has_fragment.push(key(node));
+            fragment.dimensions.content.x = x;
+            stack.push(fragment);
+            nodes.push(node);
+        }
+        if let ItemKind::Text(node, text) | ItemKind::Space(node, text) = leaf.item.kind {
+            let parent = stack.last_mut().unwrap();
+            // Consecutive pieces of the same text node are kept together in one run.
+            match parent.children.last_mut() {
+                Some(LayoutBox { box_type: TextRun(run_node, ref mut run), dimensions, .. })
+                    if std::ptr::eq(*run_node, node) => {
+                    run.push(text);
+                    dimensions.content.width += leaf.item.width;
+                }
+                _ => {
+                    let mut run = LayoutBox::new(TextRun(node, text));
+                    run.dimensions.content.x = x;
+                    run.dimensions.content.width = leaf.item.width;
+                    parent.children.push(run);
+                }
+            }
+            x += leaf.item.width;
+        }
+    }
+    while let Some(node) = nodes.pop() {
+        close(&mut stack, node, &mut x, true);
+    }
+    stack.pop().unwrap()
+}
+
+/// Position a line box and its fragments vertically, with their baselines aligned.
+///
+/// Each box's line height is split evenly above and below its text, and the line box is just
+/// tall enough for all of them.  Vertical margins, borders and padding of inline boxes don't
+/// affect the height of the line.
+///
+/// https://www.w3.org/TR/CSS2/visudet.html#line-height
+fn place_line(line_box: &mut LayoutBox, top: f32, strut: &ComputedStyle, containing_width: f32,
+              fonts: &FontDatabase) {
+    let (mut above, mut below) = half_leading_extents(strut, fonts);
+    for_each_fragment(line_box, &mut |fragment| {
+        if let InlineNode(node) | TextRun(node, _) = fragment.box_type {
+            let (a, b) = half_leading_extents(&node.style, fonts);
+            above = above.max(a);
+            below = below.max(b);
+        }
+    });
+    let baseline = top + above;
+    line_box.dimensions.content.y = top;
+    line_box.dimensions.content.height = above + below;
+    for_each_fragment(line_box, &mut |fragment| {
+        let style = match fragment.box_type {
+            InlineNode(node) | TextRun(node, _) => &node.style,
+            _ => return,
+        };
+        let font = fonts.primary_font(style);
+        let d = &mut fragment.dimensions;
+        d.content.y = baseline - font.ascent(style.font_size);
+        d.content.height = font.ascent(style.font_size) + font.descent(style.font_size);
+        if let InlineNode(_) = fragment.box_type {
+            d.border.top = style.border_top_width;
+            d.border.bottom = style.border_bottom_width;
+            d.padding.top = style.padding_top.resolve(containing_width);
+            d.padding.bottom = style.padding_bottom.resolve(containing_width);
+        }
+    });
+}
+
+/// Call `f` on each box inside `layout_box`.
+fn for_each_fragment(layout_box: &mut LayoutBox, f: &mut impl FnMut(&mut LayoutBox)) {
+    for child in &mut layout_box.children {
+        f(child);
+        for_each_fragment(child, f);
+    }
+}
+
+/// How far a box with this style reaches above and below the baseline, including half the
+/// leading on each side.  The ascent and descent are those of the style's first available font.
+fn half_leading_extents(style: &ComputedStyle, fonts: &FontDatabase) -> (f32, f32) {
+    let font = fonts.primary_font(style);
+    let (ascent, descent) = (font.ascent(style.font_size), font.descent(style.font_size));
+    let line_height = match style.line_height {
+        LineHeight::Normal => font.normal_line_height(style.font_size),
+        LineHeight::Number(n) => n * style.font_size,
+        LineHeight::Length(px) => px,
+    };
+    let half_leading = (line_height - (ascent + descent)) / 2.0;
+    (ascent + half_leading, descent + half_leading)
+}
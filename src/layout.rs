@@ -1,10 +1,16 @@
-//! Basic CSS block layout.
-
-use crate::style::{StyledNode, Display};
-use crate::css::{Value::{Keyword, Length}, Unit::Px};
+//! Basic CSS block layout.  Inline content is laid out in line boxes by the `inline` module.
+
+use crate::font::ShapedText;
+use crate::font_database::FontDatabase;
+use crate::inline;
+use crate::css::PseudoElement;
+use crate::style::StyledNode;
+use crate::properties::ComputedStyle;
+use crate::values::{Direction, Display, LengthPercentage, LengthPercentageAuto};
+use crate::values::ListStylePosition;
 use std::default::Default;
 
-pub use self::BoxType::{AnonymousBlock, InlineNode, BlockNode};
+pub use self::BoxType::{AnonymousBlock, InlineNode, BlockNode, LineBox, TextRun};
 
 // CSS box model. All sizes are in px.
 
@@ -45,10 +51,14 @@ pub enum BoxType<'a> {
     BlockNode(&'a StyledNode<'a>),
     InlineNode(&'a StyledNode<'a>),
     AnonymousBlock,
+    /// One line of an inline formatting context, containing fragments of the inline boxes on it.
+    LineBox,
+    /// The part of a text node's text that is on one line.
+    TextRun(&'a StyledNode<'a>, ShapedText),
 }
 
 impl<'a> LayoutBox<'a> {
-    fn new(box_type: BoxType) -> LayoutBox {
+    pub fn new(box_type: BoxType) -> LayoutBox {
         LayoutBox {
             box_type,
             dimensions: Default::default(), // initially set all fields to 0.0
@@ -58,20 +68,22 @@ impl<'a> LayoutBox<'a> {
 
     fn get_style_node(&self) -> &'a StyledNode<'a> {
         match self.box_type {
-            BlockNode(node) | InlineNode(node) => node,
-            AnonymousBlock => panic!("Anonymous block box has no style node")
+            BlockNode(node) | InlineNode(node) | TextRun(node, _) => node,
+            AnonymousBlock | LineBox => panic!("Anonymous box has no style node")
         }
     }
 }
 
-/// Transform a style tree into a layout tree.
-pub fn layout_tree<'a>(node: &'a StyledNode<'a>, mut containing_block: Dimensions) -> LayoutBox<'a> {
+/// Transform a style tree into a layout tree, with text in fonts from `fonts`.
+pub fn layout_tree<'a>(node: &'a StyledNode<'a>, mut containing_block: Dimensions,
+                       fonts: &FontDatabase) -> LayoutBox<'a> {
     // The layout algorithm expects the container height to start at 0.
     // TODO: Save the initial containing block height, for calculating percent heights.
     containing_block.content.height = 0.0;
 
+    // The initial containing block takes its direction from the root element.
     let mut root_box = build_layout_tree(node);
-    root_box.layout(containing_block);
+    root_box.layout(containing_block, node.style.direction, fonts);
     root_box
 }
 
@@ -79,7 +91,7 @@ pub fn layout_tree<'a>(node: &'a StyledNode<'a>, mut containing_block: Dimension
 fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
     // Create the root box.
     let mut root = LayoutBox::new(match style_node.display() {
-        Display::Block => BlockNode(style_node),
+        Display::Block | Display::ListItem => BlockNode(style_node),
         Display::Inline => InlineNode(style_node),
         Display::None => panic!("Root node has display: none.")
     });
@@ -87,7 +99,11 @@ fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
     // Create the descendant boxes.
     for child in &style_node.children {
         match child.display() {
-            Display::Block => root.children.push(build_layout_tree(child)),
+            Display::Block | Display::ListItem => root.children.push(build_layout_tree(child)),
+            // An outside marker is laid out by its list item, outside of its content.
+            Display::Inline if is_outside_marker(child) => {
+                root.children.push(build_layout_tree(child))
+            }
             Display::Inline => root.get_inline_container().children.push(build_layout_tree(child)),
             Display::None => {} // Don't lay out nodes with `display: none;`
         }
@@ -96,29 +112,59 @@ fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
 }
 
 impl LayoutBox<'_> {
-    /// Lay out a box and its descendants.
-    fn layout(&mut self, containing_block: Dimensions) {
+    /// Lay out a block-level box and its descendants.  Inline-level boxes are laid out by the
+    /// anonymous block that contains them.  `direction` is the containing block's direction.
+    pub fn layout(&mut self, containing_block: Dimensions, direction: Direction,
+                  fonts: &FontDatabase) {
         match self.box_type {
-            BlockNode(_) => self.layout_block(containing_block),
-            InlineNode(_) | AnonymousBlock => {} // TODO
+            BlockNode(_) => self.layout_block(containing_block, direction, fonts),
+            InlineNode(_) | AnonymousBlock | LineBox | TextRun(..) => {}
         }
     }
 
     /// Lay out a block-level element and its descendants.
-    fn layout_block(&mut self, containing_block: Dimensions) {
+    fn layout_block(&mut self, containing_block: Dimensions, direction: Direction,
+                    fonts: &FontDatabase) {
         // Child width can depend on parent width, so we need to calculate this box's width before
         // laying out its children.
-        self.calculate_block_width(containing_block);
+        self.calculate_block_width(containing_block, direction);
 
         // Determine where the box is located within its container.
         self.calculate_block_position(containing_block);
 
+        // An outside marker isn't in the flow of the list item's content.
+        let marker = match self.children.first() {
+            Some(LayoutBox { box_type: InlineNode(node), .. }) if is_outside_marker(node) => {
+                Some(self.children.remove(0))
+            }
+            _ => None,
+        };
+
         // Recursively lay out the children of this box.
-        self.layout_block_children();
+        self.layout_block_children(fonts);
 
         // Parent height can depend on child height, so `calculate_height` must be called after the
         // children are laid out.
         self.calculate_block_height();
+
+        // The marker goes next to the first line of the list item, or at its top if it has none.
+        if let Some(marker) = marker {
+            let top = self.first_line_top().unwrap_or(self.dimensions.content.y);
+            let style = &self.get_style_node().style;
+            let marker = inline::layout_outside_marker(marker, &self.dimensions, top, style, fonts);
+            self.children.push(marker);
+        }
+    }
+
+    /// The top of the first line box inside this box.
+    fn first_line_top(&self) -> Option<f32> {
+        match self.box_type {
+            LineBox => Some(self.dimensions.content.y),
+            BlockNode(_) | AnonymousBlock => {
+                self.children.iter().find_map(|child| child.first_line_top())
+            }
+            InlineNode(_) | TextRun(..) => None,
+        }
     }
 
     /// Calculate the width of a block-level non-replaced element in normal flow.
@@ -126,35 +172,35 @@ impl LayoutBox<'_> {
     /// http://www.w3.org/TR/CSS2/visudet.html#blockwidth
     ///
     /// Sets the horizontal margin/padding/border dimensions, and the `width`.
-    fn calculate_block_width(&mut self, containing_block: Dimensions) {
-        let style = self.get_style_node();
-
-        // `width` has initial value `auto`.
-        let auto = Keyword("auto".to_string());
-        let mut width = style.value("width").unwrap_or(auto.clone());
+    ///
+    /// When the width and margins don't add up to the containing block's width, the margin at the
+    /// end of the containing block's `direction` takes up the difference.
+    fn calculate_block_width(&mut self, containing_block: Dimensions, direction: Direction) {
+        let style = &self.get_style_node().style;
 
-        // margin, border, and padding have initial value 0.
-        let zero = Length(0.0, Px);
+        // Percentages refer to the width of the containing block.  `None` means `auto`.
+        let cb_width = containing_block.content.width;
+        let mut width = style.width.resolve(cb_width);
 
-        let mut margin_left = style.lookup("margin-left", "margin", &zero);
-        let mut margin_right = style.lookup("margin-right", "margin", &zero);
+        let mut margin_left = style.margin_left.resolve(cb_width);
+        let mut margin_right = style.margin_right.resolve(cb_width);
 
-        let border_left = style.lookup("border-left-width", "border-width", &zero);
-        let border_right = style.lookup("border-right-width", "border-width", &zero);
+        let border_left = style.border_left_width;
+        let border_right = style.border_right_width;
 
-        let padding_left = style.lookup("padding-left", "padding", &zero);
-        let padding_right = style.lookup("padding-right", "padding", &zero);
+        let padding_left = style.padding_left.resolve(cb_width);
+        let padding_right = style.padding_right.resolve(cb_width);
 
-        let total = sum([&margin_left, &margin_right, &border_left, &border_right,
-                         &padding_left, &padding_right, &width].iter().map(|v| v.to_px()));
+        let total = sum([margin_left, margin_right, width].iter().map(|v| v.unwrap_or(0.0)))
+            + border_left + border_right + padding_left + padding_right;
 
         // If width is not auto and the total is wider than the container, treat auto margins as 0.
-        if width != auto && total > containing_block.content.width {
-            if margin_left == auto {
-                margin_left = Length(0.0, Px);
+        if width.is_some() && total > containing_block.content.width {
+            if margin_left.is_none() {
+                margin_left = Some(0.0);
             }
-            if margin_right == auto {
-                margin_right = Length(0.0, Px);
+            if margin_right.is_none() {
+                margin_right = Some(0.0);
             }
         }
 
@@ -163,49 +209,59 @@ impl LayoutBox<'_> {
         // and afterward all values should be absolute lengths in px.
         let underflow = containing_block.content.width - total;
 
-        match (width == auto, margin_left == auto, margin_right == auto) {
-            // If the values are overconstrained, calculate margin_right.
-            (false, false, false) => {
-                margin_right = Length(margin_right.to_px() + underflow, Px);
+        match (width, margin_left, margin_right) {
+            // If the values are overconstrained, calculate margin_right, or margin_left in a
+            // right-to-left containing block.
+            (Some(_), Some(left), Some(_)) if direction == Direction::Rtl => {
+                margin_left = Some(left + underflow);
+            }
+            (Some(_), Some(_), Some(right)) => {
+                margin_right = Some(right + underflow);
             }
 
             // If exactly one size is auto, its used value follows from the equality.
-            (false, false, true) => { margin_right = Length(underflow, Px); }
-            (false, true, false) => { margin_left  = Length(underflow, Px); }
+            (Some(_), Some(_), None) => { margin_right = Some(underflow); }
+            (Some(_), None, Some(_)) => { margin_left  = Some(underflow); }
 
             // If width is set to auto, any other auto values become 0.
-            (true, _, _) => {
-                if margin_left == auto { margin_left = Length(0.0, Px); }
-                if margin_right == auto { margin_right = Length(0.0, Px); }
+            (None, _, _) => {
+                let right = margin_right.unwrap_or(0.0);
+                margin_left = Some(margin_left.unwrap_or(0.0));
 
                 if underflow >= 0.0 {
                     // Expand width to fill the underflow.
-                    width = Length(underflow, Px);
+                    width = Some(underflow);
+                    margin_right = Some(right);
+                } else if direction == Direction::Rtl {
+                    // Width can't be negative. Adjust the left margin instead.
+                    width = Some(0.0);
+                    margin_left = margin_left.map(|left| left + underflow);
+                    margin_right = Some(right);
                 } else {
                     // Width can't be negative. Adjust the right margin instead.
-                    width = Length(0.0, Px);
-                    margin_right = Length(margin_right.to_px() + underflow, Px);
+                    width = Some(0.0);
+                    margin_right = Some(right + underflow);
                 }
             }
 
             // If margin-left and margin-right are both auto, their used values are equal.
-            (false, true, true) => {
-                margin_left = Length(underflow / 2.0, Px);
-                margin_right = Length(underflow / 2.0, Px);
+            (Some(_), None, None) => {
+                margin_left = Some(underflow / 2.0);
+                margin_right = Some(underflow / 2.0);
             }
         }
 
         let d = &mut self.dimensions;
-        d.content.width = width.to_px();
+        d.content.width = width.unwrap();
 
-        d.padding.left = padding_left.to_px();
-        d.padding.right = padding_right.to_px();
+        d.padding.left = padding_left;
+        d.padding.right = padding_right;
 
-        d.border.left = border_left.to_px();
-        d.border.right = border_right.to_px();
+        d.border.left = border_left;
+        d.border.right = border_right;
 
-        d.margin.left = margin_left.to_px();
-        d.margin.right = margin_right.to_px();
+        d.margin.left = margin_left.unwrap();
+        d.margin.right = margin_right.unwrap();
     }
 
     /// Finish calculating the block's edge sizes, and position it within its containing block.
@@ -214,21 +270,21 @@ impl LayoutBox<'_> {
     ///
     /// Sets the vertical margin/padding/border dimensions, and the `x`, `y` values.
     fn calculate_block_position(&mut self, containing_block: Dimensions) {
-        let style = self.get_style_node();
+        let style = &self.get_style_node().style;
         let d = &mut self.dimensions;
 
-        // margin, border, and padding have initial value 0.
-        let zero = Length(0.0, Px);
+        // Percentages refer to the *width* of the containing block, even for vertical edges.
+        let cb_width = containing_block.content.width;
 
         // If margin-top or margin-bottom is `auto`, the used value is zero.
-        d.margin.top = style.lookup("margin-top", "margin", &zero).to_px();
-        d.margin.bottom = style.lookup("margin-bottom", "margin", &zero).to_px();
+        d.margin.top = style.margin_top.resolve(cb_width).unwrap_or(0.0);
+        d.margin.bottom = style.margin_bottom.resolve(cb_width).unwrap_or(0.0);
 
-        d.border.top = style.lookup("border-top-width", "border-width", &zero).to_px();
-        d.border.bottom = style.lookup("border-bottom-width", "border-width", &zero).to_px();
+        d.border.top = style.border_top_width;
+        d.border.bottom = style.border_bottom_width;
 
-        d.padding.top = style.lookup("padding-top", "padding", &zero).to_px();
-        d.padding.bottom = style.lookup("padding-bottom", "padding", &zero).to_px();
+        d.padding.top = style.padding_top.resolve(cb_width);
+        d.padding.bottom = style.padding_bottom.resolve(cb_width);
 
         d.content.x = containing_block.content.x +
                       d.margin.left + d.border.left + d.padding.left;
@@ -241,9 +297,15 @@ impl LayoutBox<'_> {
     /// Lay out the block's children within its content area.
     ///
     /// Sets `self.dimensions.height` to the total content height.
-    fn layout_block_children(&mut self) {
-        for child in &mut self.children {
-            child.layout(self.dimensions);
+    fn layout_block_children(&mut self, fonts: &FontDatabase) {
+        let style = &self.get_style_node().style;
+        for (i, child) in self.children.iter_mut().enumerate() {
+            match child.box_type {
+                AnonymousBlock => {
+                    child.layout_anonymous_block(self.dimensions, style, i == 0, fonts);
+                }
+                _ => child.layout(self.dimensions, style.direction, fonts),
+            }
             // Increment the height so each child is laid out below the previous one.
             self.dimensions.content.height += child.dimensions.margin_box().height;
         }
@@ -253,15 +315,33 @@ impl LayoutBox<'_> {
     fn calculate_block_height(&mut self) {
         // If the height is set to an explicit length, use that exact length.
         // Otherwise, just keep the value set by `layout_block_children`.
-        if let Some(Length(h, Px)) = self.get_style_node().value("height") {
+        //
+        // TODO: Percentage heights are treated as `auto`, which is only correct when the height
+        // of the containing block is also `auto`.
+        if let LengthPercentageAuto::LengthPercentage(LengthPercentage::Length(h)) =
+            self.get_style_node().style.height {
             self.dimensions.content.height = h;
         }
     }
 
+    /// Lay out an anonymous block's inline content in line boxes.  `style` is the style of the
+    /// block container it's in, and `first` is true if it's the container's first child.
+    ///
+    /// http://www.w3.org/TR/CSS2/visuren.html#anonymous-block-level
+    fn layout_anonymous_block(&mut self, containing_block: Dimensions, style: &ComputedStyle,
+                              first: bool, fonts: &FontDatabase) {
+        let d = &mut self.dimensions;
+        d.content.x = containing_block.content.x;
+        d.content.y = containing_block.content.y + containing_block.content.height;
+        d.content.width = containing_block.content.width;
+        let children = std::mem::take(&mut self.children);
+        self.children = inline::layout_lines(children, d, style, first, fonts);
+    }
+
     /// Where a new inline child should go.
     fn get_inline_container(&mut self) -> &mut Self {
         match self.box_type {
-            InlineNode(_) | AnonymousBlock => self,
+            InlineNode(_) | AnonymousBlock | LineBox | TextRun(..) => self,
             BlockNode(_) => {
                 // If we've just generated an anonymous block box, keep using it.
                 // Otherwise, create a new one.
@@ -275,6 +355,12 @@ impl LayoutBox<'_> {
     }
 }
 
+/// Is this the `::marker` of a list item with `list-style-position: outside`?
+fn is_outside_marker(node: &StyledNode) -> bool {
+    node.pseudo == Some(PseudoElement::Marker)
+        && node.style.list_style_position == ListStylePosition::Outside
+}
+
 impl Rect {
     pub fn expanded_by(self, edge: EdgeSizes) -> Rect {
         Rect {
@@ -304,3 +390,164 @@ impl Dimensions {
 fn sum<I>(iter: I) -> f32 where I: Iterator<Item=f32> {
     iter.fold(0., |a, b| a + b)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css;
+    use crate::font::default_font;
+    use crate::html;
+    use crate::media::Device;
+    use crate::style::style_tree;
+
+    /// Lay out a document in an 800px wide viewport, and pass its root box to `f`.
+    fn with_layout(html: &str, css: &str, f: impl FnOnce(&LayoutBox)) {
+        let root = html::parse(html.to_string());
+        let mut viewport: Dimensions = Default::default();
+        viewport.content.width = 800.0;
+        let device = Device::screen(viewport.content);
+        let fonts = FontDatabase::new();
+        let tree = style_tree(&root, &[css::parse(css.to_string())], &device, &fonts);
+        f(&layout_tree(&tree, viewport, &fonts));
+    }
+
+    /// The line boxes in a box, in the order they appear in the layout tree.
+    fn line_boxes<'b, 'a>(layout_box: &'b LayoutBox<'a>) -> Vec<&'b LayoutBox<'a>> {
+        if let LineBox = layout_box.box_type {
+            return vec![layout_box];
+        }
+        layout_box.children.iter().flat_map(line_boxes).collect()
+    }
+
+    /// The text in a box.
+    fn text(layout_box: &LayoutBox) -> String {
+        match layout_box.box_type {
+            TextRun(_, ref shaped) => shaped.text.clone(),
+            _ => layout_box.children.iter().map(text).collect(),
+        }
+    }
+
+    /// The left and right edges of each line box in a list item, in the order they appear in the
+    /// layout tree, along with the left edge of the list item's content.
+    fn list_item_lines(css: &str) -> (f32, Vec<(f32, f32)>) {
+        let mut result = (0.0, Vec::new());
+        with_layout("<ul><li>item</li></ul>", css, |layout| {
+            let item = &layout.children[0];
+            let lines = line_boxes(item).into_iter().map(|line| {
+                let Rect { x, width, .. } = line.dimensions.content;
+                (x, x + width)
+            });
+            result = (item.dimensions.content.x, lines.collect());
+        });
+        result
+    }
+
+    #[test]
+    fn line_boxes_and_heights() {
+        // The HTML parser drops white space at the start of a text node.
+        let html = "<div><p>aaa <span>bbb ccc </span>ddd</p></div>";
+        let css = "p { width: 1px } span { line-height: 30px }";
+        with_layout(html, css, |layout| {
+            let p = &layout.children[0];
+            let lines = line_boxes(p);
+            let texts: Vec<_> = lines.iter().map(|line| text(line).trim().to_string()).collect();
+            assert_eq!(texts, ["aaa", "bbb", "ccc", "ddd"]);
+            // The span is split into a fragment on each of its lines.
+            let fragments: Vec<_> = lines.iter()
+                .map(|line| line.children.iter()
+                    .filter(|child| matches!(child.box_type, InlineNode(_)))
+                    .count())
+                .collect();
+            assert_eq!(fragments, [0, 1, 1, 0]);
+            // Lines are as tall as their tallest inline box, and stack with no gaps.
+            let normal = default_font().normal_line_height(16.0);
+            let heights: Vec<_> = lines.iter().map(|line| line.dimensions.content.height).collect();
+            assert_eq!(heights, [normal, 30.0, 30.0, normal]);
+            for pair in lines.windows(2) {
+                let (a, b) = (&pair[0].dimensions.content, &pair[1].dimensions.content);
+                assert!((a.y + a.height - b.y).abs() < 0.01);
+            }
+            let total = 2.0 * normal + 60.0;
+            assert!((p.dimensions.content.height - total).abs() < 0.01);
+        });
+    }
+
+    #[test]
+    fn outside_markers() {
+        let (content, lines) = list_item_lines("");
+        assert_eq!(content, 40.0);
+        // The line with the item's text, and then the marker's line, which ends at the content.
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].0, 40.0);
+        assert!(lines[1].0 < 40.0);
+        assert_eq!(lines[1].1, 40.0);
+
+        let (_, lines) = list_item_lines("ul { list-style-position: inside }");
+        assert_eq!(lines, [(40.0, 800.0)]);
+    }
+
+    /// The text, left edge and width of each text run in a document's first paragraph.
+    fn text_runs(html: &str, css: &str) -> Vec<(String, f32, f32)> {
+        fn collect(layout_box: &LayoutBox, runs: &mut Vec<(String, f32, f32)>) {
+            if let TextRun(_, ref shaped) = layout_box.box_type {
+                let Rect { x, width, .. } = layout_box.dimensions.content;
+                runs.push((shaped.text.clone(), x, width));
+            }
+            for child in &layout_box.children {
+                collect(child, runs);
+            }
+        }
+        let mut runs = Vec::new();
+        with_layout(html, css, |layout| collect(&layout.children[0], &mut runs));
+        runs
+    }
+
+    #[test]
+    fn text_align_and_indent() {
+        let html = "<div><p>aa bb</p></div>";
+        let width = default_font().text_width("aa bb", 16.0);
+        let left = |css: &str| text_runs(html, &format!("p {{ width: 200px; {} }}", css))[0].1;
+        assert_eq!(left(""), 0.0);
+        assert_eq!(left("text-align: right"), 200.0 - width);
+        assert_eq!(left("text-align: center"), (200.0 - width) / 2.0);
+        assert_eq!(left("text-align: end; direction: rtl"), 0.0);
+        assert_eq!(left("text-indent: 10px"), 10.0);
+        assert_eq!(left("text-indent: 10%"), 20.0);
+
+        // Justified lines fill the line by stretching the spaces, except for the last line.
+        let html = "<div><p>aa bb cc</p></div>";
+        let runs = text_runs(html, "p { width: 60px; text-align: justify }");
+        let texts: Vec<_> = runs.iter().map(|run| run.0.trim()).collect();
+        assert_eq!(texts, ["aa bb", "cc"]);
+        let (_, x, width) = runs[0];
+        assert_eq!(x, 0.0);
+        assert!((width - 60.0).abs() < 0.01);
+        assert_eq!(runs[1].2, default_font().text_width("cc", 16.0));
+        // Only the first line is indented.
+        let runs = text_runs(html, "p { width: 60px; text-indent: 5px }");
+        assert_eq!((runs[0].1, runs[1].1), (5.0, 0.0));
+    }
+
+    #[test]
+    fn spacing_and_transform() {
+        let html = "<div><p>ab cd</p></div>";
+        let width = |css: &str| -> f32 {
+            text_runs(html, css).iter().map(|&(_, _, width)| width).sum()
+        };
+        let normal = width("");
+        // Letter spacing is added after each character, and word spacing after each space.
+        assert!((width("p { letter-spacing: 2px }") - (normal + 10.0)).abs() < 0.01);
+        assert!((width("p { word-spacing: 3px }") - (normal + 3.0)).abs() < 0.01);
+        assert!((width("p { word-spacing: -1em }") - (normal - 16.0)).abs() < 0.01);
+
+        let text = |css: &str| -> String {
+            text_runs("<div><p>the stra\u{DF}e o'neil</p></div>", css).into_iter()
+                .map(|(text, _, _)| text)
+                .collect()
+        };
+        assert_eq!(text("p { text-transform: uppercase }"), "THE STRASSE O'NEIL");
+        assert_eq!(text("p { text-transform: capitalize }"), "The Stra\u{DF}e O'neil");
+        assert_eq!(text("p { text-transform: uppercase } p { text-transform: none }"),
+                   "the stra\u{DF}e o'neil");
+    }
+}
@@ -0,0 +1,28 @@
+//! A toy web rendering engine, with a command-line front end in `main.rs`.
+
+pub mod bidi;
+pub mod bloom;
+pub mod cascade;
+pub mod color;
+pub mod content;
+pub mod css;
+pub mod dom;
+pub mod font;
+pub mod font_database;
+pub mod html;
+pub mod hyphenation;
+pub mod inline;
+pub mod layout;
+pub mod line_break;
+pub mod media;
+pub mod style;
+pub mod painting;
+pub mod pdf;
+pub mod properties;
+pub mod raster;
+pub mod selector_map;
+pub mod serialize;
+pub mod shorthands;
+pub mod ucd;
+pub mod values;
+pub mod variables;
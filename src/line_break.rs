@@ -0,0 +1,251 @@
+//! The Unicode line breaking algorithm, which finds where text can be broken across lines.
+//!
+//! Each character has a line breaking class, like `AL` for letters, `ID` for ideographs, or `OP`
+//! for opening punctuation, from the Unicode Character Database.  Rules about pairs of classes,
+//! with some looking back past spaces, decide whether a line can break between two characters.
+//! The `word-break` and `line-break` properties adjust the classes of some characters.
+//!
+//! https://www.unicode.org/reports/tr14/
+//! https://www.w3.org/TR/css-text-3/#line-breaking
+
+use crate::ucd;
+use crate::values::{LineBreak, WordBreak};
+use std::sync::OnceLock;
+
+/// A place where text can break, before the character at byte `index`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Break {
+    pub index: usize,
+    /// Whether the text must break here, as it does after a line separator.
+    pub mandatory: bool,
+}
+
+/// Define the line breaking classes, and how to parse their names.
+macro_rules! classes {
+    ($($class:ident,)*) => {
+        /// Line breaking classes.
+        ///
+        /// https://www.unicode.org/reports/tr14/#Table1
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        #[allow(clippy::upper_case_acronyms)]
+        enum Class {
+            $($class,)*
+        }
+
+        fn parse_class(name: &str) -> Class {
+            match name {
+                $(stringify!($class) => Class::$class,)*
+                _ => Class::XX,
+            }
+        }
+    }
+}
+
+classes! {
+    BK, CR, LF, CM, NL, SG, WJ, ZW, GL, SP, ZWJ,
+    B2, BA, BB, HY, CB, CL, CP, EX, IN, NS, OP, QU, IS, NU, PO, PR, SY,
+    AI, AL, CJ, EB, EM, H2, H3, HL, ID, JL, JV, JT, RI, SA, XX,
+}
+
+use self::Class::*;
+
+/// The class of each range of code points, sorted.
+fn classes() -> &'static [(u32, u32, Class)] {
+    static CLASSES: OnceLock<Vec<(u32, u32, Class)>> = OnceLock::new();
+    CLASSES.get_or_init(|| ucd::parse_ranges(include_str!("../data/LineBreak.txt"), parse_class))
+}
+
+fn class(c: char) -> Class {
+    ucd::lookup(classes(), c).unwrap_or(XX)
+}
+
+/// Resolve the classes that the pair rules don't handle directly (rule LB1), and apply the
+/// `word-break` and `line-break` properties.
+///
+/// Complex-context scripts like Thai (class `SA`) need a dictionary to find word boundaries, so
+/// they are treated as letters, and only break at spaces.
+fn resolve(class: Class, word_break: WordBreak, strictness: LineBreak) -> Class {
+    let class = match class {
+        AI | SG | XX | SA => AL,
+        // Small kana, which only strict line breaking keeps at the start of a line.
+        CJ if strictness == LineBreak::Strict => NS,
+        CJ => ID,
+        // Loose line breaking also allows breaks before iteration marks and between
+        // inseparable characters like `…`.
+        NS | IN if strictness == LineBreak::Loose => ID,
+        class => class,
+    };
+    match (word_break, class) {
+        (WordBreak::BreakAll, AL | HL | NU) => ID,
+        (WordBreak::KeepAll, ID | H2 | H3 | JL | JV | JT) => AL,
+        (_, class) => class,
+    }
+}
+
+/// Find the places where a line can break inside `text`.  Breaks at the start and end of the
+/// text aren't included.
+pub fn line_breaks(text: &str, word_break: WordBreak, strictness: LineBreak) -> Vec<Break> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    if strictness == LineBreak::Anywhere {
+        return anywhere(&chars);
+    }
+    let original: Vec<Class> = chars.iter().map(|&(_, c)| class(c)).collect();
+    let mut classes: Vec<Class> = original.iter()
+        .map(|&class| resolve(class, word_break, strictness))
+        .collect();
+    // LB9: A combining mark takes the class of the character it is attached to, and the line
+    // can't break before it.  LB10: Marks that aren't attached to anything are letters.
+    let mut attached = vec![false; chars.len()];
+    for i in 0..chars.len() {
+        if matches!(classes[i], CM | ZWJ) {
+            match i.checked_sub(1).map(|j| classes[j]) {
+                Some(BK | CR | LF | NL | SP | ZW) | None => classes[i] = AL,
+                Some(base) => {
+                    classes[i] = base;
+                    attached[i] = true;
+                }
+            }
+        }
+    }
+
+    let mut breaks = Vec::new();
+    // The class before the latest run of spaces, for the rules that apply across spaces.
+    let mut before_spaces = classes.first().copied().unwrap_or(XX);
+    // The number of regional indicators in a row before the current position.
+    let mut regional_indicators = 0;
+    for i in 1..chars.len() {
+        let (before, after) = (classes[i - 1], classes[i]);
+        if before != SP {
+            before_spaces = before;
+        }
+        regional_indicators = if before == RI { regional_indicators + 1 } else { 0 };
+        let wide = is_east_asian_wide(chars[i].1);
+        let before_wide = is_east_asian_wide(chars[i - 1].1);
+        let rule = match (before, after) {
+            // LB4, LB5: Always break after hard line breaks, but not between CR and LF.
+            (CR, LF) => Some(false),
+            (BK | CR | LF | NL, _) => {
+                breaks.push(Break { index: chars[i].0, mandatory: true });
+                continue;
+            }
+            // LB6, LB7: Don't break before hard line breaks, spaces or zero width spaces.
+            (_, BK | CR | LF | NL | SP | ZW) => Some(false),
+            // LB8: Break after a zero width space, even if spaces follow it.
+            _ if before_spaces == ZW => Some(true),
+            // LB8a: Don't break after a zero width joiner.  LB9: Don't break before a
+            // combining mark.
+            _ if original[i - 1] == ZWJ || attached[i] => Some(false),
+            // LB11, LB12, LB12a: Don't break around word joiners or after glue, or before glue
+            // except after spaces and hyphens.
+            (_, WJ) | (WJ | GL, _) => Some(false),
+            (_, GL) if !matches!(before, SP | BA | HY) => Some(false),
+            // LB13: Don't break before closing punctuation, `!`, or separators.
+            (_, CL | CP | EX | IS | SY) => Some(false),
+            // LB14 to LB17: Don't break after opening punctuation, or in some other pairs,
+            // even with spaces between them.
+            _ if before_spaces == OP => Some(false),
+            (_, OP) if before_spaces == QU => Some(false),
+            (_, NS) if matches!(before_spaces, CL | CP) => Some(false),
+            (_, B2) if before_spaces == B2 => Some(false),
+            // LB18: Break after spaces.
+            (SP, _) => Some(true),
+            // LB19, LB20: Don't break around quotation marks, and break around contingent
+            // breaks.
+            (_, QU) | (QU, _) => Some(false),
+            (_, CB) | (CB, _) => Some(true),
+            // LB21 to LB22: Don't break before hyphens and other non-starters, after a hyphen
+            // that follows a Hebrew letter, or before an ellipsis.
+            (_, BA | HY | NS) | (BB, _) => Some(false),
+            (HY | BA, _) if i >= 2 && classes[i - 2] == HL => Some(false),
+            (SY, HL) | (_, IN) => Some(false),
+            // LB23 to LB25: Don't break inside words, numbers, or numbers with prefixes and
+            // suffixes like currency symbols.
+            (AL | HL, NU) | (NU, AL | HL) => Some(false),
+            (PR, ID | EB | EM) | (ID | EB | EM, PO) => Some(false),
+            (PR | PO, AL | HL) | (AL | HL, PR | PO) => Some(false),
+            (CL | CP | NU, PO | PR) | (PO | PR, OP | NU) | (HY | IS | NU | SY, NU) => Some(false),
+            // LB26, LB27: Don't break inside Korean syllables.
+            (JL, JL | JV | H2 | H3) | (JV | H2, JV | JT) | (JT | H3, JT) => Some(false),
+            (JL | JV | JT | H2 | H3, PO) | (PR, JL | JV | JT | H2 | H3) => Some(false),
+            // LB28, LB29: Don't break between letters, or after a separator before a letter.
+            (AL | HL, AL | HL) | (IS, AL | HL) => Some(false),
+            // LB30: Don't break between letters and parentheses, unless they are East Asian.
+            (AL | HL | NU, OP) if !wide => Some(false),
+            (CP, AL | HL | NU) if !before_wide => Some(false),
+            // LB30a: Regional indicators make flags in pairs.
+            (RI, RI) if regional_indicators % 2 == 1 => Some(false),
+            // LB30b: Don't break between an emoji and its skin tone modifier.
+            (EB, EM) => Some(false),
+            _ => None,
+        };
+        // LB31: Break everywhere else.
+        if rule.unwrap_or(true) {
+            breaks.push(Break { index: chars[i].0, mandatory: false });
+        }
+    }
+    breaks
+}
+
+/// Breaks for `line-break: anywhere`, which can break between any characters except before
+/// combining marks.  Hard line breaks are still mandatory.
+fn anywhere(chars: &[(usize, char)]) -> Vec<Break> {
+    chars.windows(2).filter_map(|pair| {
+        let ((_, before), (index, after)) = (pair[0], pair[1]);
+        let mandatory = matches!(class(before), BK | CR | LF | NL)
+            && !(before == '\r' && after == '\n');
+        let can_break = mandatory || !matches!(class(after), CM | ZWJ) && class(before) != ZWJ;
+        can_break.then_some(Break { index, mandatory })
+    }).collect()
+}
+
+/// Whether a character is wide or fullwidth in East Asian typography.  This is an approximation
+/// by block, which is enough to tell CJK brackets from others.
+///
+/// https://www.unicode.org/reports/tr11/
+fn is_east_asian_wide(c: char) -> bool {
+    matches!(c as u32, 0x1100..=0x115F | 0x2E80..=0xA4CF | 0xAC00..=0xD7A3 | 0xF900..=0xFAFF
+                       | 0xFE30..=0xFE6F | 0xFF00..=0xFFEF | 0x20000..=0x3FFFD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `text` with `÷` at each place it can break, or `!` where it must break, like the
+    /// notation of the Unicode line breaking tests.
+    fn breaks(text: &str, word_break: WordBreak, strictness: LineBreak) -> String {
+        let mut result = String::new();
+        let mut last = 0;
+        for Break { index, mandatory } in line_breaks(text, word_break, strictness) {
+            result.push_str(&text[last..index]);
+            result.push(if mandatory { '!' } else { '÷' });
+            last = index;
+        }
+        result + &text[last..]
+    }
+
+    #[test]
+    fn break_opportunities() {
+        let cases = [
+            ("hello world", "hello ÷world"),
+            ("a , b", "a , ÷b"),
+            ("a-b -1", "a-÷b ÷-1"),
+            ("(a) [b]", "(a) ÷[b]"),
+            ("$100 12.5% can't", "$100 ÷12.5% ÷can't"),
+            ("a\u{A0}b a\u{200B}b", "a\u{A0}b ÷a\u{200B}÷b"),
+            ("a\nb\r\nc", "a\n!b\r\n!c"),
+            ("日本語。", "日÷本÷語。"),
+            ("한국어", "한÷국÷어"),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(breaks(text, WordBreak::Normal, LineBreak::Normal), expected);
+        }
+    }
+
+    #[test]
+    fn word_break() {
+        assert_eq!(breaks("한국어 ab", WordBreak::KeepAll, LineBreak::Normal), "한국어 ÷ab");
+        assert_eq!(breaks("ab cd", WordBreak::BreakAll, LineBreak::Normal), "a÷b ÷c÷d");
+        assert_eq!(breaks("ab", WordBreak::Normal, LineBreak::Anywhere), "a÷b");
+    }
+}
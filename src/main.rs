@@ -4,22 +4,19 @@ extern crate image;
 use std::default::Default;
 use std::io::{Read, BufWriter};
 use std::fs::File;
+use std::path::Path;
 
-pub mod css;
-pub mod dom;
-pub mod html;
-pub mod layout;
-pub mod style;
-pub mod painting;
-pub mod pdf;
+use robinson::{css, font_database, html, layout, media, painting, pdf, style};
 
 fn main() {
     // Parse command-line options:
     let mut opts = getopts::Options::new();
     opts.optopt("h", "html", "HTML document", "FILENAME");
-    opts.optopt("c", "css", "CSS stylesheet", "FILENAME");
+    opts.optmulti("c", "css", "CSS stylesheet (may be repeated)", "FILENAME");
+    opts.optmulti("u", "user-css", "User stylesheet (may be repeated)", "FILENAME");
     opts.optopt("o", "output", "Output file", "FILENAME");
     opts.optopt("f", "format", "Output file format", "png | pdf");
+    opts.optmulti("", "font-dir", "Directory of font files (may be repeated)", "DIRECTORY");
 
     let matches = opts.parse(std::env::args().skip(1)).unwrap();
     let str_arg = |flag: &str, default: &str| -> String {
@@ -35,7 +32,10 @@ fn main() {
 
     // Read input files:
     let html = read_source(str_arg("h", "examples/test.html"));
-    let css  = read_source(str_arg("c", "examples/test.css"));
+    let mut css_files = matches.opt_strs("c");
+    if css_files.is_empty() {
+        css_files.push("examples/test.css".to_string());
+    }
 
     // Since we don't have an actual window, hard-code the "viewport" size.
     let mut viewport: layout::Dimensions = Default::default();
@@ -44,9 +44,31 @@ fn main() {
 
     // Parsing and rendering:
     let root_node = html::parse(html);
-    let stylesheet = css::parse(css);
-    let style_root = style::style_tree(&root_node, &stylesheet);
-    let layout_root = layout::layout_tree(&style_root, viewport);
+    let user_css_files = matches.opt_strs("u");
+    let user_stylesheets = user_css_files.iter()
+        .map(|file| css::parse_with_origin(read_source(file.clone()), css::Origin::User));
+    let author_stylesheets = css_files.iter().map(|file| css::parse(read_source(file.clone())));
+    let stylesheets: Vec<_> = user_stylesheets.chain(author_stylesheets).collect();
+
+    // Load fonts from the font directories, and from `@font-face` rules, whose URLs are relative
+    // to their stylesheets:
+    let mut fonts = font_database::FontDatabase::new();
+    for directory in matches.opt_strs("font-dir") {
+        if let Err(error) = fonts.load_directory(Path::new(&directory)) {
+            eprintln!("Can't load fonts from {}: {}", directory, error);
+            std::process::exit(1);
+        }
+    }
+    for (stylesheet, file) in stylesheets.iter().zip(user_css_files.iter().chain(&css_files)) {
+        fonts.load_font_faces(stylesheet, Path::new(file).parent().unwrap_or(Path::new(".")));
+    }
+
+    let device = media::Device {
+        media_type: if png { media::MediaType::Screen } else { media::MediaType::Print },
+        ..media::Device::screen(viewport.content)
+    };
+    let style_root = style::style_tree(&root_node, &stylesheets, &device, &fonts);
+    let layout_root = layout::layout_tree(&style_root, viewport, &fonts);
 
     // Create the output file:
     let filename = str_arg("o", if png { "output.png" } else { "output.pdf" });
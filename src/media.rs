@@ -0,0 +1,206 @@
+//! Media queries, and the device they are evaluated against.
+//!
+//! https://www.w3.org/TR/mediaqueries-5/
+
+use crate::layout::Rect;
+use std::cmp::Ordering;
+
+/// The output device that a document is styled for.
+#[derive(Clone, Debug)]
+pub struct Device {
+    /// The size of the viewport, in px.
+    pub viewport: Rect,
+    pub media_type: MediaType,
+    /// The number of device pixels per px.
+    pub device_pixel_ratio: f32,
+    pub color_scheme: ColorScheme,
+}
+
+impl Device {
+    /// A screen with one device pixel per px, and a light color scheme.
+    pub fn screen(viewport: Rect) -> Device {
+        Device {
+            viewport,
+            media_type: MediaType::Screen,
+            device_pixel_ratio: 1.0,
+            color_scheme: ColorScheme::Light,
+        }
+    }
+
+    /// The value of a media feature, or `None` if it is not supported.
+    ///
+    /// https://www.w3.org/TR/mediaqueries-5/#mq-features
+    fn feature(&self, name: &str) -> Option<MediaValue> {
+        let Rect { width, height, .. } = self.viewport;
+        Some(match name {
+            "width" => MediaValue::Length(width),
+            "height" => MediaValue::Length(height),
+            "orientation" => {
+                let orientation = if height >= width { "portrait" } else { "landscape" };
+                MediaValue::Ident(orientation.to_string())
+            }
+            "resolution" => MediaValue::Resolution(self.device_pixel_ratio),
+            "prefers-color-scheme" => MediaValue::Ident(match self.color_scheme {
+                ColorScheme::Light => "light".to_string(),
+                ColorScheme::Dark => "dark".to_string(),
+            }),
+            // Bits per color component.
+            "color" => MediaValue::Number(8.0),
+            "monochrome" => MediaValue::Number(0.0),
+            _ => return None,
+        })
+    }
+}
+
+/// https://www.w3.org/TR/mediaqueries-5/#media-types
+#[derive(Clone, Debug, PartialEq)]
+pub enum MediaType {
+    All,
+    Screen,
+    Print,
+    /// A media type this engine doesn't render, which never matches.
+    Unknown(String),
+}
+
+/// https://www.w3.org/TR/mediaqueries-5/#prefers-color-scheme
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+/// A comma-separated list of media queries, like `screen and (min-width: 600px), print`.  It
+/// matches if any of its queries does, or if it is empty.
+#[derive(Debug, Default, PartialEq)]
+pub struct MediaQueryList(pub Vec<MediaQuery>);
+
+#[derive(Debug, PartialEq)]
+pub struct MediaQuery {
+    /// Whether the query starts with `not`, which inverts the result of the whole query.
+    pub negated: bool,
+    pub media_type: MediaType,
+    pub condition: Option<MediaCondition>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MediaCondition {
+    Feature(MediaFeature),
+    Not(Box<MediaCondition>),
+    And(Vec<MediaCondition>),
+    Or(Vec<MediaCondition>),
+}
+
+/// A test of one feature of the device, like `(color)`, `(min-width: 600px)`, or
+/// `(400px <= width < 700px)`.
+#[derive(Debug, PartialEq)]
+pub struct MediaFeature {
+    /// The name of the feature, without any `min-` or `max-` prefix.
+    pub name: String,
+    /// How the feature's value compares to each of these values.  If there are none, the feature
+    /// is evaluated in a boolean context.
+    pub comparisons: Vec<(Comparison, MediaValue)>,
+}
+
+/// An operator in a media feature, with the feature's value on its left-hand side.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Lt,
+    Le,
+    Eq,
+    Ge,
+    Gt,
+}
+
+impl Comparison {
+    /// The same comparison with the operands swapped, e.g. `a < b` to `b > a`.
+    pub fn flip(self) -> Comparison {
+        match self {
+            Comparison::Lt => Comparison::Gt,
+            Comparison::Le => Comparison::Ge,
+            Comparison::Eq => Comparison::Eq,
+            Comparison::Ge => Comparison::Le,
+            Comparison::Gt => Comparison::Lt,
+        }
+    }
+
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Lt => ordering == Ordering::Less,
+            Comparison::Le => ordering != Ordering::Greater,
+            Comparison::Eq => ordering == Ordering::Equal,
+            Comparison::Ge => ordering != Ordering::Less,
+            Comparison::Gt => ordering == Ordering::Greater,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MediaValue {
+    /// A length in px.
+    Length(f32),
+    /// A resolution in dppx.
+    Resolution(f32),
+    Number(f32),
+    Ident(String),
+}
+
+impl MediaQueryList {
+    pub fn matches(&self, device: &Device) -> bool {
+        self.0.is_empty() || self.0.iter().any(|query| query.matches(device))
+    }
+}
+
+impl MediaQuery {
+    fn matches(&self, device: &Device) -> bool {
+        let media_type_matches = match self.media_type {
+            MediaType::All => true,
+            MediaType::Unknown(_) => false,
+            ref media_type => *media_type == device.media_type,
+        };
+        let matches = media_type_matches
+            && self.condition.as_ref().is_none_or(|condition| condition.matches(device));
+        matches != self.negated
+    }
+}
+
+impl MediaCondition {
+    fn matches(&self, device: &Device) -> bool {
+        match self {
+            MediaCondition::Feature(feature) => feature.matches(device),
+            MediaCondition::Not(condition) => !condition.matches(device),
+            MediaCondition::And(conditions) => conditions.iter().all(|c| c.matches(device)),
+            MediaCondition::Or(conditions) => conditions.iter().any(|c| c.matches(device)),
+        }
+    }
+}
+
+impl MediaFeature {
+    /// Unsupported features, and comparisons between values of different types, never match.
+    fn matches(&self, device: &Device) -> bool {
+        let Some(value) = device.feature(&self.name) else {
+            return false;
+        };
+        if self.comparisons.is_empty() {
+            return match value {
+                MediaValue::Length(n) | MediaValue::Resolution(n) |
+                MediaValue::Number(n) => n != 0.0,
+                MediaValue::Ident(ref k) => k != "none",
+            };
+        }
+        self.comparisons.iter().all(|(comparison, other)| {
+            let ordering = match (&value, other) {
+                (MediaValue::Length(a), MediaValue::Length(b)) |
+                (MediaValue::Resolution(a), MediaValue::Resolution(b)) |
+                (MediaValue::Number(a), MediaValue::Number(b)) => a.partial_cmp(b),
+                // A length can be written as a unitless zero.
+                (MediaValue::Length(a), MediaValue::Number(b)) if *b == 0.0 => a.partial_cmp(b),
+                // Keywords can only be tested for equality.
+                (MediaValue::Ident(a), MediaValue::Ident(b)) if *comparison == Comparison::Eq => {
+                    return a == b;
+                }
+                _ => None,
+            };
+            ordering.is_some_and(|ordering| comparison.holds(ordering))
+        })
+    }
+}
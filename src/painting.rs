@@ -1,12 +1,32 @@
-use crate::layout::{AnonymousBlock, BlockNode, InlineNode, LayoutBox, Rect};
-use crate::css::{Value, Color};
+use crate::layout::{AnonymousBlock, BlockNode, InlineNode, LayoutBox, LineBox, Rect, TextRun};
+use crate::css::Color;
+use crate::font::{Font, PositionedGlyph};
+use crate::properties::ComputedStyle;
+use crate::raster::{rasterize_glyph, Bitmap};
+use std::collections::HashMap;
+use std::sync::Arc;
 
 pub struct Canvas {
     pub pixels: Vec<Color>,
     pub width: usize,
     pub height: usize,
+    glyph_cache: HashMap<GlyphKey, Bitmap>,
 }
 
+/// Glyphs are rasterized once for each font, size, and horizontal offset from the pixel grid.
+#[derive(PartialEq, Eq, Hash)]
+struct GlyphKey {
+    /// The address of the `Font`, which is kept alive by the display list while painting.
+    font: usize,
+    glyph: u16,
+    size: u32,
+    subpixel_offset: u8,
+}
+
+/// The number of horizontal positions within a pixel that glyphs are rasterized at.  Vertically,
+/// glyphs are aligned to whole pixels.
+const SUBPIXEL_POSITIONS: u8 = 4;
+
 /// Paint a tree of LayoutBoxes to an array of pixels.
 pub fn paint(layout_root: &LayoutBox, bounds: Rect) -> Canvas {
     let display_list = build_display_list(layout_root);
@@ -20,6 +40,8 @@ pub fn paint(layout_root: &LayoutBox, bounds: Rect) -> Canvas {
 #[derive(Debug)]
 pub enum DisplayCommand {
     SolidColor(Color, Rect),
+    /// Glyphs from a font at a size in px, positioned at their origins on the baseline.
+    Text { font: Arc<Font>, size: f32, color: Color, glyphs: Vec<PositionedGlyph> },
 }
 
 pub type DisplayList = Vec<DisplayCommand>;
@@ -33,67 +55,91 @@ pub fn build_display_list(layout_root: &LayoutBox) -> DisplayList {
 fn render_layout_box(list: &mut DisplayList, layout_box: &LayoutBox) {
     render_background(list, layout_box);
     render_borders(list, layout_box);
+    render_text(list, layout_box);
     for child in &layout_box.children {
         render_layout_box(list, child);
     }
 }
 
 fn render_background(list: &mut DisplayList, layout_box: &LayoutBox) {
-    if let Some(color) = get_color(layout_box, "background") {
+    if let Some(color) = get_color(layout_box, |style| style.background_color) {
         list.push(DisplayCommand::SolidColor(color, layout_box.dimensions.border_box()));
     }
 }
 
 fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox) {
-    let color = match get_color(layout_box, "border-color") {
-        Some(color) => color,
-        _ => return
-    };
-
     let d = &layout_box.dimensions;
     let border_box = d.border_box();
 
     // Left border
-    list.push(DisplayCommand::SolidColor(color, Rect {
-        x: border_box.x,
-        y: border_box.y,
-        width: d.border.left,
-        height: border_box.height,
-    }));
+    if let Some(color) = get_color(layout_box, |style| style.border_left_color) {
+        list.push(DisplayCommand::SolidColor(color, Rect {
+            x: border_box.x,
+            y: border_box.y,
+            width: d.border.left,
+            height: border_box.height,
+        }));
+    }
 
     // Right border
-    list.push(DisplayCommand::SolidColor(color, Rect {
-        x: border_box.x + border_box.width - d.border.right,
-        y: border_box.y,
-        width: d.border.right,
-        height: border_box.height,
-    }));
+    if let Some(color) = get_color(layout_box, |style| style.border_right_color) {
+        list.push(DisplayCommand::SolidColor(color, Rect {
+            x: border_box.x + border_box.width - d.border.right,
+            y: border_box.y,
+            width: d.border.right,
+            height: border_box.height,
+        }));
+    }
 
     // Top border
-    list.push(DisplayCommand::SolidColor(color, Rect {
-        x: border_box.x,
-        y: border_box.y,
-        width: border_box.width,
-        height: d.border.top,
-    }));
+    if let Some(color) = get_color(layout_box, |style| style.border_top_color) {
+        list.push(DisplayCommand::SolidColor(color, Rect {
+            x: border_box.x,
+            y: border_box.y,
+            width: border_box.width,
+            height: d.border.top,
+        }));
+    }
 
     // Bottom border
-    list.push(DisplayCommand::SolidColor(color, Rect {
-        x: border_box.x,
-        y: border_box.y + border_box.height - d.border.bottom,
-        width: border_box.width,
-        height: d.border.bottom,
-    }));
+    if let Some(color) = get_color(layout_box, |style| style.border_bottom_color) {
+        list.push(DisplayCommand::SolidColor(color, Rect {
+            x: border_box.x,
+            y: border_box.y + border_box.height - d.border.bottom,
+            width: border_box.width,
+            height: d.border.bottom,
+        }));
+    }
+}
+
+fn render_text(list: &mut DisplayList, layout_box: &LayoutBox) {
+    let TextRun(node, ref text) = layout_box.box_type else { return };
+    let style = &node.style;
+    if style.color.a == 0 {
+        return;
+    }
+    let content = layout_box.dimensions.content;
+    for run in &text.runs {
+        let glyphs = run.glyphs.iter().map(|glyph| PositionedGlyph {
+            x: content.x + glyph.x,
+            y: content.y + glyph.y,
+            ..*glyph
+        }).collect();
+        list.push(DisplayCommand::Text {
+            font: run.font.clone(),
+            size: style.font_size,
+            color: style.color,
+            glyphs,
+        });
+    }
 }
 
-/// Return the specified color for CSS property `name`, or None if no color was specified.
-fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
+/// Return the color selected by `property` from a box's computed style, or None if it is fully
+/// transparent.
+fn get_color(layout_box: &LayoutBox, property: fn(&ComputedStyle) -> Color) -> Option<Color> {
     match layout_box.box_type {
-        BlockNode(style) | InlineNode(style) => match style.value(name) {
-            Some(Value::ColorValue(color)) => Some(color),
-            _ => None
-        },
-        AnonymousBlock => None
+        BlockNode(node) | InlineNode(node) => Some(property(&node.style)).filter(|c| c.a > 0),
+        AnonymousBlock | LineBox | TextRun(..) => None
     }
 }
 
@@ -105,6 +151,7 @@ impl Canvas {
             pixels: vec![white; width * height],
             width,
             height,
+            glyph_cache: HashMap::new(),
         }
     }
 
@@ -119,11 +166,103 @@ impl Canvas {
 
                 for y in y0 .. y1 {
                     for x in x0 .. x1 {
-                        // TODO: alpha compositing with existing pixel
-                        self.pixels[y * self.width + x] = color;
+                        let pixel = &mut self.pixels[y * self.width + x];
+                        *pixel = blend(color, *pixel);
                     }
                 }
             }
+            DisplayCommand::Text { ref font, size, color, ref glyphs } => {
+                for glyph in glyphs {
+                    let x = (glyph.x * SUBPIXEL_POSITIONS as f32).round();
+                    let subpixel_offset = x.rem_euclid(SUBPIXEL_POSITIONS as f32) as u8;
+                    let x = (x / SUBPIXEL_POSITIONS as f32).floor() as i32;
+                    let y = glyph.y.round() as i32;
+                    let key = GlyphKey {
+                        font: Arc::as_ptr(font) as usize,
+                        glyph: glyph.id,
+                        size: size.to_bits(),
+                        subpixel_offset,
+                    };
+                    let bitmap = self.glyph_cache.entry(key).or_insert_with(|| {
+                        let offset = subpixel_offset as f32 / SUBPIXEL_POSITIONS as f32;
+                        rasterize_glyph(font, glyph.id, size, offset)
+                    });
+                    paint_bitmap(&mut self.pixels, self.width, self.height, bitmap,
+                                 x + bitmap.left, y + bitmap.top, color);
+                }
+            }
         }
     }
 }
+
+/// Blend `color` into the pixels covered by an alpha mask, with its top left corner at (x, y).
+fn paint_bitmap(pixels: &mut [Color], width: usize, height: usize, bitmap: &Bitmap,
+                x: i32, y: i32, color: Color) {
+    for row in 0..bitmap.height {
+        let py = y + row as i32;
+        if py < 0 || py >= height as i32 {
+            continue;
+        }
+        for column in 0..bitmap.width {
+            let px = x + column as i32;
+            let coverage = bitmap.coverage[row * bitmap.width + column] as u32;
+            if px < 0 || px >= width as i32 || coverage == 0 {
+                continue;
+            }
+            let src = Color { a: ((color.a as u32 * coverage + 127) / 255) as u8, ..color };
+            let pixel = &mut pixels[py as usize * width + px as usize];
+            *pixel = blend(src, *pixel);
+        }
+    }
+}
+
+/// Composite `src` over `dst` using the "source over" operator.
+fn blend(src: Color, dst: Color) -> Color {
+    if src.a == 255 {
+        return src;
+    }
+    let alpha = src.a as f32 / 255.0;
+    let dst_alpha = dst.a as f32 / 255.0;
+    let out_alpha = alpha + dst_alpha * (1.0 - alpha);
+    if out_alpha == 0.0 {
+        return Color { r: 0, g: 0, b: 0, a: 0 };
+    }
+    let channel = |s: u8, d: u8| {
+        ((s as f32 * alpha + d as f32 * dst_alpha * (1.0 - alpha)) / out_alpha).round() as u8
+    };
+    Color {
+        r: channel(src.r, dst.r),
+        g: channel(src.g, dst.g),
+        b: channel(src.b, dst.b),
+        a: (out_alpha * 255.0).round() as u8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::default_font;
+
+    #[test]
+    fn glyph_cache() {
+        let font = default_font();
+        let black = Color { r: 0, g: 0, b: 0, a: 255 };
+        let glyph = |id, x| PositionedGlyph { id, x, y: 20.0 };
+        let (a, b) = (font.glyph_index('a'), font.glyph_index('b'));
+        let text = |glyphs| {
+            DisplayCommand::Text { font: font.clone(), size: 16.0, color: black, glyphs }
+        };
+        let mut canvas = Canvas::new(100, 30);
+        // Glyphs at the same offset from the pixel grid share a bitmap.
+        canvas.paint_item(&text(vec![glyph(a, 0.0), glyph(b, 10.0), glyph(a, 20.0)]));
+        assert_eq!(canvas.glyph_cache.len(), 2);
+        canvas.paint_item(&text(vec![glyph(a, 40.5), glyph(a, 60.5)]));
+        assert_eq!(canvas.glyph_cache.len(), 3);
+        // Each copy is painted.
+        let inked = |x0: usize| (0..30).any(|y| (x0..x0 + 10).any(|x| {
+            canvas.pixels[y * 100 + x] != Color { r: 255, g: 255, b: 255, a: 255 }
+        }));
+        assert!([0, 10, 20, 40, 60].into_iter().all(inked));
+        assert!(!inked(80));
+    }
+}
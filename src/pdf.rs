@@ -1,3 +1,4 @@
+use crate::font::{PathSegment, Point};
 use crate::layout::{LayoutBox, Rect};
 use crate::painting::{build_display_list, DisplayCommand};
 use std::io::{self, Seek, Write};
@@ -41,9 +42,53 @@ fn render_item<W: Write>(item: &DisplayCommand, output: &mut W) -> io::Result<()
                 rect.height
             )
         }
+        DisplayCommand::Text { ref font, size, color, ref glyphs } => {
+            // Draw the glyph outlines as filled paths.
+            writeln!(output, "{} {} {} sc", color.r, color.g, color.b)?;
+            let scale = font.scale(size);
+            for glyph in glyphs {
+                // Font units have y pointing up, and the page has it pointing down.
+                let p = |point: Point| (glyph.x + point.x * scale, glyph.y - point.y * scale);
+                let mut current = (0.0, 0.0);
+                for segment in font.outline(glyph.id) {
+                    match segment {
+                        PathSegment::MoveTo(to) => {
+                            current = p(to);
+                            writeln!(output, "{} {} m", current.0, current.1)?;
+                        }
+                        PathSegment::LineTo(to) => {
+                            current = p(to);
+                            writeln!(output, "{} {} l", current.0, current.1)?;
+                        }
+                        // PDF only has cubic curves, which can represent any quadratic one.
+                        PathSegment::QuadTo(control, to) => {
+                            let (c, to) = (p(control), p(to));
+                            let c1 = (current.0 + 2.0 / 3.0 * (c.0 - current.0),
+                                      current.1 + 2.0 / 3.0 * (c.1 - current.1));
+                            let c2 = (to.0 + 2.0 / 3.0 * (c.0 - to.0),
+                                      to.1 + 2.0 / 3.0 * (c.1 - to.1));
+                            write_curve(output, c1, c2, to)?;
+                            current = to;
+                        }
+                        PathSegment::CurveTo(c1, c2, to) => {
+                            let (c1, c2, to) = (p(c1), p(c2), p(to));
+                            write_curve(output, c1, c2, to)?;
+                            current = to;
+                        }
+                        PathSegment::Close => writeln!(output, "h")?,
+                    }
+                }
+            }
+            writeln!(output, "f")
+        }
     }
 }
 
+fn write_curve<W: Write>(output: &mut W, c1: (f32, f32), c2: (f32, f32), to: (f32, f32))
+                         -> io::Result<()> {
+    writeln!(output, "{} {} {} {} {} {} c", c1.0, c1.1, c2.0, c2.1, to.0, to.1)
+}
+
 struct Pdf<'a, W: 'a + Write + Seek> {
     output: &'a mut W,
     object_offsets: Vec<i64>,
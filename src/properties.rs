@@ -0,0 +1,192 @@
+//! The longhand properties supported by the style system.
+//!
+//! Everything about a property is defined in one place, the table at the bottom of this file: its
+//! name, the type of its value, whether it is inherited, and its initial value.  From that table,
+//! the `properties!` macro generates the `ComputedStyle` struct and the list of `PROPERTIES`, and
+//! it decides which declarations are valid when parsing.
+//!
+//! https://www.w3.org/TR/css-cascade-5/#defaulting
+
+use crate::css::{self, Color, Declaration, Value};
+use crate::shorthands::is_css_wide_keyword;
+use crate::values::*;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+pub struct Property {
+    pub name: &'static str,
+    /// Inherited properties take their parent's computed value when they have no cascaded value.
+    pub inherited: bool,
+    /// The source text of the initial value.
+    initial: &'static str,
+}
+
+impl Property {
+    /// The initial value, as a specified value.
+    pub fn initial_value(&self) -> Value {
+        static INITIAL_VALUES: OnceLock<HashMap<&'static str, Value>> = OnceLock::new();
+        let values = INITIAL_VALUES.get_or_init(|| {
+            PROPERTIES.iter().map(|p| (p.name, css::parse_value(p.initial))).collect()
+        });
+        values[self.name].clone()
+    }
+}
+
+/// Find a supported property by name.
+pub fn lookup(name: &str) -> Option<&'static Property> {
+    PROPERTIES.iter().find(|property| property.name == name)
+}
+
+/// Is this declaration valid?  Declarations of unsupported properties, and declarations whose
+/// values don't match the type of the property, are ignored by the parser.
+///
+/// https://www.w3.org/TR/css-syntax-3/#style-rules
+pub fn is_valid(declaration: &Declaration) -> bool {
+    match declaration.value {
+        _ if declaration.name.starts_with("--") => true,
+        // Values with variables can't be checked until the variables are substituted.
+        Value::Unparsed(_) | Value::PendingSubstitution(..) => true,
+        Value::Keyword(ref k) if is_css_wide_keyword(k) => lookup(&declaration.name).is_some(),
+        ref value => is_valid_value(&declaration.name, value),
+    }
+}
+
+impl ComputedStyle {
+    /// The computed values of the root element's parent, where every property has its initial
+    /// value.
+    pub fn initial() -> &'static ComputedStyle {
+        static INITIAL: OnceLock<ComputedStyle> = OnceLock::new();
+        INITIAL.get_or_init(|| ComputedStyle::from_initial_values(&Context::initial()))
+    }
+}
+
+/// Whether a property is inherited, in the `properties!` table.
+macro_rules! is_inherited {
+    (inherited) => { true };
+    (reset) => { false };
+}
+
+macro_rules! properties {
+    ($($inherited:ident $name:literal $field:ident: $ty:ty = $initial:literal;)*) => {
+        /// The computed value of every supported property of a node.
+        ///
+        /// https://www.w3.org/TR/css-cascade-5/#computed
+        #[derive(Clone, Debug)]
+        pub struct ComputedStyle {
+            $(pub $field: <$ty as PropertyType>::Computed,)*
+        }
+
+        /// All supported properties, in the order they are computed.
+        pub static PROPERTIES: &[Property] = &[
+            $(Property {
+                name: $name,
+                inherited: is_inherited!($inherited),
+                initial: $initial,
+            },)*
+        ];
+
+        impl ComputedStyle {
+            fn from_initial_values(context: &Context) -> ComputedStyle {
+                ComputedStyle {
+                    $($field: <$ty as PropertyType>::compute(css::parse_value($initial), context)
+                        .expect(concat!("invalid initial value for ", $name)),)*
+                }
+            }
+
+            /// Set the computed value of property `name` from its specified value.  Returns
+            /// false, leaving the style unchanged, if the value is invalid for the property.
+            pub fn set(&mut self, name: &str, value: Value, context: &Context) -> bool {
+                match name {
+                    $($name => {
+                        match <$ty as PropertyType>::compute(value, context) {
+                            Some(value) => { self.$field = value; true }
+                            None => false,
+                        }
+                    })*
+                    _ => false,
+                }
+            }
+        }
+
+        fn is_valid_value(name: &str, value: &Value) -> bool {
+            match name {
+                $($name => <$ty as PropertyType>::is_valid(value),)*
+                _ => false,
+            }
+        }
+    }
+}
+
+// `color` and the properties that choose the font come first, since other values can depend on
+// them.  `font-style` is the last of those.
+properties! {
+    inherited "color" color: Color = "black";
+    inherited "font-size" font_size: FontSize = "medium";
+    inherited "font-weight" font_weight: FontWeight = "normal";
+    inherited "font-family" font_family: FontFamily = "serif";
+    inherited "font-stretch" font_stretch: FontStretch = "normal";
+    inherited "font-style" font_style: FontStyle = "normal";
+    inherited "direction" direction: Direction = "ltr";
+    inherited "font-variant" font_variant: Value = "normal";
+    inherited "hyphens" hyphens: Hyphens = "manual";
+    inherited "letter-spacing" letter_spacing: Spacing = "normal";
+    inherited "line-break" line_break: LineBreak = "auto";
+    inherited "line-height" line_height: LineHeight = "normal";
+    inherited "list-style-image" list_style_image: Value = "none";
+    inherited "list-style-position" list_style_position: ListStylePosition = "outside";
+    inherited "list-style-type" list_style_type: Value = "disc";
+    inherited "overflow-wrap" overflow_wrap: OverflowWrap = "normal";
+    inherited "quotes" quotes: Value = "auto";
+    inherited "text-align" text_align: TextAlign = "start";
+    inherited "text-align-last" text_align_last: TextAlignLast = "auto";
+    inherited "text-indent" text_indent: LengthPercentage = "0px";
+    inherited "text-transform" text_transform: TextTransform = "none";
+    inherited "visibility" visibility: Visibility = "visible";
+    inherited "white-space" white_space: WhiteSpace = "normal";
+    inherited "word-break" word_break: WordBreak = "normal";
+    inherited "word-spacing" word_spacing: Spacing = "normal";
+    reset "background-attachment" background_attachment: Value = "scroll";
+    reset "background-clip" background_clip: Value = "border-box";
+    reset "background-color" background_color: Color = "transparent";
+    reset "background-image" background_image: Value = "none";
+    reset "background-origin" background_origin: Value = "padding-box";
+    reset "background-position" background_position: Value = "0% 0%";
+    reset "background-repeat" background_repeat: Value = "repeat";
+    reset "background-size" background_size: Value = "auto";
+    reset "border-top-color" border_top_color: Color = "currentcolor";
+    reset "border-right-color" border_right_color: Color = "currentcolor";
+    reset "border-bottom-color" border_bottom_color: Color = "currentcolor";
+    reset "border-left-color" border_left_color: Color = "currentcolor";
+    reset "border-top-style" border_top_style: BorderStyle = "none";
+    reset "border-right-style" border_right_style: BorderStyle = "none";
+    reset "border-bottom-style" border_bottom_style: BorderStyle = "none";
+    reset "border-left-style" border_left_style: BorderStyle = "none";
+    reset "border-top-width" border_top_width: LineWidth = "medium";
+    reset "border-right-width" border_right_width: LineWidth = "medium";
+    reset "border-bottom-width" border_bottom_width: LineWidth = "medium";
+    reset "border-left-width" border_left_width: LineWidth = "medium";
+    reset "bottom" bottom: LengthPercentageAuto = "auto";
+    reset "column-gap" column_gap: Value = "normal";
+    reset "content" content: Value = "normal";
+    reset "counter-increment" counter_increment: Value = "none";
+    reset "counter-reset" counter_reset: Value = "none";
+    reset "counter-set" counter_set: Value = "none";
+    reset "display" display: Display = "inline";
+    reset "height" height: LengthPercentageAuto = "auto";
+    reset "left" left: LengthPercentageAuto = "auto";
+    reset "margin-top" margin_top: LengthPercentageAuto = "0px";
+    reset "margin-right" margin_right: LengthPercentageAuto = "0px";
+    reset "margin-bottom" margin_bottom: LengthPercentageAuto = "0px";
+    reset "margin-left" margin_left: LengthPercentageAuto = "0px";
+    reset "padding-top" padding_top: NonNegativeLengthPercentage = "0px";
+    reset "padding-right" padding_right: NonNegativeLengthPercentage = "0px";
+    reset "padding-bottom" padding_bottom: NonNegativeLengthPercentage = "0px";
+    reset "padding-left" padding_left: NonNegativeLengthPercentage = "0px";
+    reset "right" right: LengthPercentageAuto = "auto";
+    reset "row-gap" row_gap: Value = "normal";
+    reset "text-decoration" text_decoration: Value = "none";
+    reset "top" top: LengthPercentageAuto = "auto";
+    reset "unicode-bidi" unicode_bidi: UnicodeBidi = "normal";
+    reset "vertical-align" vertical_align: Value = "baseline";
+    reset "width" width: LengthPercentageAuto = "auto";
+}
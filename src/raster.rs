@@ -0,0 +1,246 @@
+//! Anti-aliased rasterization of glyph outlines.
+//!
+//! Curves are flattened into line segments, and each segment adds the exact area it covers in
+//! each pixel to an accumulation buffer, signed by its direction.  A running sum along each row
+//! then gives every pixel its coverage under the non-zero fill rule, as long as the outline's
+//! contours don't overlap each other.
+//!
+//! https://medium.com/@raphlinus/inside-the-fastest-font-renderer-in-the-world-75ae5270c445
+
+use crate::font::{Font, PathSegment, Point};
+
+/// An alpha mask for a glyph, positioned relative to its origin on the baseline.
+pub struct Bitmap {
+    pub left: i32,
+    pub top: i32,
+    pub width: usize,
+    pub height: usize,
+    /// The fraction of each pixel covered by the glyph, from 0 to 255, row by row.
+    pub coverage: Vec<u8>,
+}
+
+/// Rasterize a glyph at a font size in px, with its origin `x_offset` px to the right of a pixel
+/// boundary.
+pub fn rasterize_glyph(font: &Font, glyph: u16, size: f32, x_offset: f32) -> Bitmap {
+    let scale = font.scale(size);
+    // Font units have y pointing up, and pixels have it pointing down.
+    let transform = |p: Point| Point { x: x_offset + p.x * scale, y: -p.y * scale };
+    let outline: Vec<PathSegment> = font.outline(glyph).into_iter().map(|segment| match segment {
+        PathSegment::MoveTo(p) => PathSegment::MoveTo(transform(p)),
+        PathSegment::LineTo(p) => PathSegment::LineTo(transform(p)),
+        PathSegment::QuadTo(c, p) => PathSegment::QuadTo(transform(c), transform(p)),
+        PathSegment::CurveTo(c1, c2, p) => {
+            PathSegment::CurveTo(transform(c1), transform(c2), transform(p))
+        }
+        PathSegment::Close => PathSegment::Close,
+    }).collect();
+
+    // Curves are inside the bounds of their control points.
+    let points = outline.iter().flat_map(|segment| match *segment {
+        PathSegment::MoveTo(p) | PathSegment::LineTo(p) => vec![p],
+        PathSegment::QuadTo(c, p) => vec![c, p],
+        PathSegment::CurveTo(c1, c2, p) => vec![c1, c2, p],
+        PathSegment::Close => vec![],
+    });
+    let mut min = Point { x: f32::MAX, y: f32::MAX };
+    let mut max = Point { x: f32::MIN, y: f32::MIN };
+    for p in points {
+        min = Point { x: min.x.min(p.x), y: min.y.min(p.y) };
+        max = Point { x: max.x.max(p.x), y: max.y.max(p.y) };
+    }
+    if min.x > max.x {
+        return Bitmap { left: 0, top: 0, width: 0, height: 0, coverage: Vec::new() };
+    }
+    let (left, top) = (min.x.floor() as i32, min.y.floor() as i32);
+    let width = (max.x.ceil() as i32 - left) as usize;
+    let height = (max.y.ceil() as i32 - top) as usize;
+
+    let mut accumulator = Accumulator::new(width, height);
+    let origin = Point { x: left as f32, y: top as f32 };
+    let relative = |p: Point| Point { x: p.x - origin.x, y: p.y - origin.y };
+    let mut start = origin;
+    let mut current = origin;
+    for segment in outline {
+        match segment {
+            PathSegment::MoveTo(p) => {
+                start = relative(p);
+                current = start;
+            }
+            PathSegment::LineTo(p) => {
+                let p = relative(p);
+                accumulator.line(current, p);
+                current = p;
+            }
+            PathSegment::QuadTo(c, p) => {
+                let (c, p) = (relative(c), relative(p));
+                accumulator.quad(current, c, p);
+                current = p;
+            }
+            PathSegment::CurveTo(c1, c2, p) => {
+                let (c1, c2, p) = (relative(c1), relative(c2), relative(p));
+                accumulator.cubic(current, c1, c2, p);
+                current = p;
+            }
+            PathSegment::Close => {
+                accumulator.line(current, start);
+                current = start;
+            }
+        }
+    }
+    Bitmap { left, top, width, height, coverage: accumulator.coverage() }
+}
+
+/// How far, in px, a flattened curve may be from the true curve, roughly.
+const TOLERANCE: f32 = 0.1;
+
+struct Accumulator {
+    width: usize,
+    height: usize,
+    /// The signed area added to each pixel, with an extra column on the right for areas that
+    /// spill past the last pixel.
+    area: Vec<f32>,
+}
+
+impl Accumulator {
+    fn new(width: usize, height: usize) -> Accumulator {
+        Accumulator { width, height, area: vec![0.0; (width + 1) * height] }
+    }
+
+    fn add(&mut self, x: i32, y: usize, area: f32) {
+        let x = x.clamp(0, self.width as i32) as usize;
+        self.area[y * (self.width + 1) + x] += area;
+    }
+
+    /// Add the area to the right of a line, within each pixel it crosses and the rest of each row.
+    fn line(&mut self, p0: Point, p1: Point) {
+        if p0.y == p1.y {
+            return;
+        }
+        // Downward lines add area, and upward lines remove it.
+        let (direction, p0, p1) = if p0.y < p1.y { (1.0, p0, p1) } else { (-1.0, p1, p0) };
+        let dxdy = (p1.x - p0.x) / (p1.y - p0.y);
+        let y_start = p0.y.max(0.0);
+        let y_end = p1.y.min(self.height as f32);
+        let mut y = y_start.floor() as usize;
+        while (y as f32) < y_end {
+            // The part of the line within this row.
+            let top = (y as f32).max(y_start);
+            let bottom = ((y + 1) as f32).min(y_end);
+            let height = (bottom - top) * direction;
+            let x_top = p0.x + (top - p0.y) * dxdy;
+            let x_bottom = p0.x + (bottom - p0.y) * dxdy;
+            let (x0, x1) = if x_top < x_bottom { (x_top, x_bottom) } else { (x_bottom, x_top) };
+            let x0_floor = x0.floor();
+            let first = x0_floor as i32;
+            if x1 <= x0_floor + 1.0 {
+                // The line is within one pixel, which it splits at its average x.
+                let right = 1.0 - ((x0 + x1) / 2.0 - x0_floor);
+                self.add(first, y, height * right);
+                self.add(first + 1, y, height * (1.0 - right));
+            } else {
+                // The line crosses several pixels.  The area to its right in each of them grows
+                // quadratically in the first and last pixels, and linearly in between.
+                let slope = 1.0 / (x1 - x0);
+                let last = x1.ceil() as i32 - 1;
+                let first_fraction = 1.0 - (x0 - x0_floor);
+                let first_area = 0.5 * slope * first_fraction * first_fraction;
+                let last_fraction = x1 - last as f32;
+                let last_area = 0.5 * slope * last_fraction * last_fraction;
+                // The total area added to this row so far.
+                let mut covered = first_area;
+                self.add(first, y, height * first_area);
+                for x in first + 1..last {
+                    let area = if x == first + 1 {
+                        slope * (first_fraction + 0.5) - first_area
+                    } else {
+                        slope
+                    };
+                    self.add(x, y, height * area);
+                    covered += area;
+                }
+                self.add(last, y, height * (1.0 - covered - last_area));
+                self.add(last + 1, y, height * last_area);
+            }
+            y += 1;
+        }
+    }
+
+    fn quad(&mut self, p0: Point, c: Point, p1: Point) {
+        // The number of segments grows with the square root of the curve's deviation from a line.
+        let deviation = distance(Point { x: p0.x - 2.0 * c.x + p1.x, y: p0.y - 2.0 * c.y + p1.y });
+        let n = ((deviation / (4.0 * TOLERANCE)).sqrt().ceil() as usize).max(1);
+        let mut previous = p0;
+        for i in 1..=n {
+            let t = i as f32 / n as f32;
+            let u = 1.0 - t;
+            let p = Point {
+                x: u * u * p0.x + 2.0 * u * t * c.x + t * t * p1.x,
+                y: u * u * p0.y + 2.0 * u * t * c.y + t * t * p1.y,
+            };
+            self.line(previous, p);
+            previous = p;
+        }
+    }
+
+    fn cubic(&mut self, p0: Point, c1: Point, c2: Point, p1: Point) {
+        let deviation = distance(Point { x: p0.x - 2.0 * c1.x + c2.x, y: p0.y - 2.0 * c1.y + c2.y })
+            .max(distance(Point { x: c1.x - 2.0 * c2.x + p1.x, y: c1.y - 2.0 * c2.y + p1.y }));
+        let n = ((3.0 * deviation / (4.0 * TOLERANCE)).sqrt().ceil() as usize).max(1);
+        let mut previous = p0;
+        for i in 1..=n {
+            let t = i as f32 / n as f32;
+            let u = 1.0 - t;
+            let (a, b, c, d) = (u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t);
+            let p = Point {
+                x: a * p0.x + b * c1.x + c * c2.x + d * p1.x,
+                y: a * p0.y + b * c1.y + c * c2.y + d * p1.y,
+            };
+            self.line(previous, p);
+            previous = p;
+        }
+    }
+
+    /// Sum the areas along each row to get the coverage of each pixel.
+    fn coverage(&self) -> Vec<u8> {
+        let mut coverage = Vec::with_capacity(self.width * self.height);
+        for row in self.area.chunks_exact(self.width + 1) {
+            let mut sum = 0.0;
+            for &area in &row[..self.width] {
+                sum += area;
+                coverage.push((sum.abs().min(1.0) * 255.0).round() as u8);
+            }
+        }
+        coverage
+    }
+}
+
+fn distance(p: Point) -> f32 {
+    (p.x * p.x + p.y * p.y).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::font::default_font;
+
+    #[test]
+    fn rasterizes_glyphs() {
+        let font = default_font();
+        // The stem of an "l" is a vertical bar, fully covering the pixels in its middle, with
+        // partial coverage at its edges.
+        let bitmap = rasterize_glyph(font, font.glyph_index('l'), 32.0, 0.0);
+        assert!(bitmap.top < 0 && bitmap.width > 0);
+        assert_eq!(bitmap.coverage.len(), bitmap.width * bitmap.height);
+        let middle = &bitmap.coverage[bitmap.height / 2 * bitmap.width..][..bitmap.width];
+        assert!(middle.contains(&255));
+        assert!(middle.iter().any(|&c| c > 0 && c < 255));
+        // The total coverage is about the area of the glyph, wherever it is placed.
+        let total = |bitmap: &Bitmap| bitmap.coverage.iter().map(|&c| c as f32).sum::<f32>();
+        let shifted = rasterize_glyph(font, font.glyph_index('l'), 32.0, 0.5);
+        assert!((total(&bitmap) - total(&shifted)).abs() < 0.01 * total(&bitmap));
+        assert_ne!(bitmap.coverage, shifted.coverage);
+
+        let space = rasterize_glyph(font, font.glyph_index(' '), 32.0, 0.0);
+        assert_eq!((space.width, space.height), (0, 0));
+    }
+}
@@ -0,0 +1,143 @@
+//! An index of the rules in a stylesheet, to quickly find the rules that might match an element.
+//!
+//! Each selector is stored in a single bucket, keyed by the most selective part of its rightmost
+//! simple selector: its id if it has one, otherwise its first class, otherwise its tag name.
+//! Selectors with none of these go in the universal bucket.  To find the rules that match an
+//! element, only the buckets for the element's own id, classes, and tag name need to be checked,
+//! along with the universal bucket.
+
+use crate::bloom::{self, Ancestors};
+use crate::css::{PseudoElement, Rule, Selector, Specificity, Stylesheet};
+use crate::dom::ElementData;
+use crate::media::Device;
+use crate::style::matches;
+use std::collections::HashMap;
+
+pub struct SelectorMap<'a> {
+    id: HashMap<&'a str, Vec<Entry<'a>>>,
+    class: HashMap<&'a str, Vec<Entry<'a>>>,
+    tag: HashMap<&'a str, Vec<Entry<'a>>>,
+    universal: Vec<Entry<'a>>,
+}
+
+/// One selector of a rule.
+struct Entry<'a> {
+    /// The position of the rule in its stylesheet.
+    rule_index: usize,
+    rule: &'a Rule,
+    selector: &'a Selector,
+    /// Hashes of names that the selector needs the element's ancestors to have.
+    ancestor_hashes: Vec<u32>,
+}
+
+/// A rule that matches an element: its position in the stylesheet, the rule, and the specificity
+/// of its most specific matching selector.
+pub type MatchedRule<'a> = (usize, Specificity, &'a Rule);
+
+impl<'a> SelectorMap<'a> {
+    /// Index the rules of `stylesheet`, leaving out those in `@media` rules that don't match
+    /// `device`.
+    pub fn new(stylesheet: &'a Stylesheet, device: &Device) -> SelectorMap<'a> {
+        let mut map = SelectorMap {
+            id: HashMap::new(),
+            class: HashMap::new(),
+            tag: HashMap::new(),
+            universal: Vec::new(),
+        };
+        for (rule_index, rule) in stylesheet.rules.iter().enumerate() {
+            if !rule.media.iter().all(|media| media.matches(device)) {
+                continue;
+            }
+            for selector in &rule.selectors {
+                let ancestor_hashes = bloom::ancestor_hashes(selector);
+                let entry = Entry { rule_index, rule, selector, ancestor_hashes };
+                let simple = selector.subject();
+                let bucket = if let Some(ref id) = simple.id {
+                    map.id.entry(id).or_default()
+                } else if let Some(class) = simple.class.first() {
+                    map.class.entry(class).or_default()
+                } else if let Some(ref tag_name) = simple.tag_name {
+                    map.tag.entry(tag_name).or_default()
+                } else {
+                    &mut map.universal
+                };
+                bucket.push(entry);
+            }
+        }
+        map
+    }
+
+    /// Find all rules that match the given element (or its pseudo-element), in the order they
+    /// appear in the stylesheet.
+    pub fn matching_rules(&self, elem: &ElementData, pseudo: Option<PseudoElement>,
+                          ancestors: &Ancestors) -> Vec<MatchedRule<'a>> {
+        let mut matched = Vec::new();
+        let mut check = |entries: Option<&Vec<Entry<'a>>>| {
+            for entry in entries.into_iter().flatten() {
+                if ancestors.might_match(&entry.ancestor_hashes)
+                    && matches(elem, pseudo, ancestors.elements(), entry.selector) {
+                    matched.push((entry.rule_index, entry.selector.specificity(), entry.rule));
+                }
+            }
+        };
+        if let Some(id) = elem.id() {
+            check(self.id.get(&**id));
+        }
+        for class in elem.classes() {
+            check(self.class.get(class));
+        }
+        check(self.tag.get(&*elem.tag_name));
+        check(Some(&self.universal));
+
+        // A rule with several matching selectors is only included once, with the highest
+        // specificity.
+        matched.sort_unstable_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+        matched.dedup_by_key(|&mut (rule_index, _, _)| rule_index);
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css;
+    use crate::layout::Rect;
+
+    fn element(tag_name: &str, attrs: &[(&str, &str)]) -> ElementData {
+        let attrs = attrs.iter().map(|&(name, value)| (name.to_string(), value.to_string()));
+        ElementData { tag_name: tag_name.to_string(), attrs: attrs.collect() }
+    }
+
+    #[test]
+    fn buckets_and_candidates() {
+        let sheet = css::parse("#x { color: red } \
+                                .a.b { color: red } \
+                                div#y.a { color: red } \
+                                div.c, div, #x { color: red } \
+                                p { color: red } \
+                                * { color: red } \
+                                :root { color: red } \
+                                p div { color: red }".to_string());
+        let map = SelectorMap::new(&sheet, &Device::screen(Rect::default()));
+        let mut ids: Vec<_> = map.id.keys().copied().collect();
+        ids.sort();
+        assert_eq!(ids, ["x", "y"]);
+        let mut classes: Vec<_> = map.class.keys().copied().collect();
+        classes.sort();
+        assert_eq!(classes, ["a", "c"]);
+        let mut tags: Vec<_> = map.tag.keys().copied().collect();
+        tags.sort();
+        assert_eq!(tags, ["div", "p"]);
+        assert_eq!(map.universal.len(), 2);
+
+        let section = element("section", &[]);
+        let mut ancestors = Ancestors::default();
+        ancestors.push(&section);
+        let div = element("div", &[("id", "x"), ("class", "a b")]);
+        let matched: Vec<_> = map.matching_rules(&div, None, &ancestors).iter()
+            .map(|&(index, specificity, _)| (index, specificity))
+            .collect();
+        // Each rule is found once, with its most specific matching selector, in order.
+        assert_eq!(matched, [(0, (1, 0, 0)), (1, (0, 2, 0)), (3, (1, 0, 0)), (5, (0, 0, 0))]);
+    }
+}
@@ -0,0 +1,802 @@
+//! Serialization of stylesheets back to CSS text.
+//!
+//! `Display` writes canonical, indented CSS, and the alternate form (`{:#}`) writes the same CSS
+//! minified, without any optional whitespace.  Either way, parsing the output gives a stylesheet
+//! equal to the original.
+//!
+//! The parser flattens stylesheets: nested rules, `@layer` and `@media` blocks are recorded on
+//! each rule, and shorthands are expanded into longhands.  So the output is in a canonical form,
+//! with `@layer` and `@media` blocks rebuilt around consecutive rules that share them.
+//!
+//! https://www.w3.org/TR/cssom-1/#serializing-css-values
+
+use crate::css::{AtRule, CalcNode, Color, Declaration, Keyframe, PseudoClass, PseudoElement};
+use crate::css::{is_anonymous_layer, Rule, Selector, SimpleSelector, Stylesheet, Unit, Value};
+use crate::media::{Comparison, MediaCondition, MediaFeature, MediaQuery, MediaQueryList};
+use crate::media::{MediaType, MediaValue};
+use crate::shorthands;
+use std::fmt::{self, Display, Formatter, Write};
+use std::sync::Arc;
+
+/// A block around some of the rules in a stylesheet.
+#[derive(Clone, Copy)]
+enum Block<'a> {
+    /// One component of a layer name.
+    Layer(&'a str),
+    Media(&'a Arc<MediaQueryList>),
+}
+
+impl Block<'_> {
+    fn is_same(&self, other: &Block) -> bool {
+        match (self, other) {
+            (Block::Layer(a), Block::Layer(b)) => a == b,
+            // Separate `@media` blocks stay separate, even if their queries are equal.
+            (Block::Media(a), Block::Media(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+/// The blocks that a rule is inside, from the outermost.
+fn blocks(rule: &Rule) -> Vec<Block<'_>> {
+    let layers = rule.layer.iter().flat_map(|layer| layer.split('.')).map(Block::Layer);
+    layers.chain(rule.media.iter().map(Block::Media)).collect()
+}
+
+/// Writes the items of a stylesheet, with blank lines between them at the top level.
+struct Writer<'a, 'b> {
+    f: &'a mut Formatter<'b>,
+    /// The number of blocks that are open.
+    depth: usize,
+    /// Whether anything has been written at the top level yet.
+    started: bool,
+}
+
+impl Writer<'_, '_> {
+    /// Start a new item in the current block.
+    fn item(&mut self) -> fmt::Result {
+        if !self.f.alternate() {
+            if self.depth > 0 {
+                self.f.write_char('\n')?;
+                indent(self.f, self.depth)?;
+            } else if self.started {
+                self.f.write_str("\n\n")?;
+            }
+        }
+        self.started = true;
+        Ok(())
+    }
+
+    fn open(&mut self, block: Block) -> fmt::Result {
+        self.item()?;
+        match block {
+            Block::Layer(name) if name.starts_with('<') => self.f.write_str("@layer")?,
+            Block::Layer(name) => write!(self.f, "@layer {}", name)?,
+            Block::Media(media) => {
+                self.f.write_str("@media ")?;
+                write_item(self.f, &**media)?;
+            }
+        }
+        space(self.f)?;
+        self.f.write_char('{')?;
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn close(&mut self) -> fmt::Result {
+        self.depth -= 1;
+        if !self.f.alternate() {
+            self.f.write_char('\n')?;
+            indent(self.f, self.depth)?;
+        }
+        self.f.write_char('}')
+    }
+
+    /// Write a `@layer` statement that declares the named layers in `layers`, so they keep their
+    /// order even if they have no rules, or their rules come later.
+    fn declare(&mut self, layers: &[String]) -> fmt::Result {
+        let mut named = layers.iter().filter(|layer| !is_anonymous_layer(layer)).peekable();
+        if named.peek().is_none() {
+            return Ok(());
+        }
+        self.item()?;
+        self.f.write_str("@layer ")?;
+        for (i, layer) in named.enumerate() {
+            if i > 0 {
+                comma(self.f)?;
+            }
+            self.f.write_str(layer)?;
+        }
+        self.f.write_char(';')
+    }
+}
+
+impl Display for Stylesheet {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut writer = Writer { f, depth: 0, started: false };
+        let mut open: Vec<Block> = Vec::new();
+        // Anonymous layers can only be declared by their blocks, so the named layers are declared
+        // in batches between them.
+        let mut declared = self.layers.iter().position(|layer| is_anonymous_layer(layer))
+            .unwrap_or(self.layers.len());
+        writer.declare(&self.layers[..declared])?;
+
+        for rule in &self.rules {
+            let blocks = blocks(rule);
+            let common = open.iter().zip(&blocks).take_while(|(a, b)| a.is_same(b)).count();
+            for _ in common..open.len() {
+                writer.close()?;
+            }
+            open.truncate(common);
+            for &block in &blocks[common..] {
+                if let (true, Block::Layer(name)) = (open.is_empty(), block) {
+                    if let Some(i) = self.layers.iter().position(|layer| layer == name) {
+                        if i >= declared {
+                            writer.declare(&self.layers[declared..i])?;
+                            declared = i + 1;
+                        }
+                    }
+                }
+                writer.open(block)?;
+                open.push(block);
+            }
+            writer.item()?;
+            write_rule(writer.f, rule, writer.depth)?;
+        }
+        for _ in 0..open.len() {
+            writer.close()?;
+        }
+        writer.declare(&self.layers[declared..])?;
+
+        for at_rule in &self.at_rules {
+            writer.item()?;
+            write_at_rule(writer.f, at_rule)?;
+        }
+        if writer.started && !writer.f.alternate() {
+            writer.f.write_char('\n')?;
+        }
+        Ok(())
+    }
+}
+
+/// A style rule, without the `@layer` and `@media` blocks it is inside.
+impl Display for Rule {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write_rule(f, self, 0)
+    }
+}
+
+fn write_rule(f: &mut Formatter, rule: &Rule, depth: usize) -> fmt::Result {
+    for (i, selector) in rule.selectors.iter().enumerate() {
+        if i > 0 {
+            comma(f)?;
+        }
+        write_item(f, selector)?;
+    }
+    space(f)?;
+    write_declaration_block(f, &rule.declarations, depth)
+}
+
+fn write_at_rule(f: &mut Formatter, at_rule: &AtRule) -> fmt::Result {
+    match at_rule {
+        AtRule::FontFace(descriptors) => {
+            f.write_str("@font-face")?;
+            space(f)?;
+            write_declaration_block(f, descriptors, 0)
+        }
+        AtRule::Page(selectors, declarations) => {
+            f.write_str("@page")?;
+            for (i, selector) in selectors.iter().enumerate() {
+                if i > 0 {
+                    comma(f)?;
+                } else {
+                    f.write_char(' ')?;
+                }
+                f.write_str(selector)?;
+            }
+            space(f)?;
+            write_declaration_block(f, declarations, 0)
+        }
+        AtRule::Keyframes(name, keyframes) => {
+            f.write_str("@keyframes ")?;
+            let is_identifier = !name.is_empty()
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if is_identifier {
+                f.write_str(name)?;
+            } else {
+                write_string(f, name)?;
+            }
+            space(f)?;
+            f.write_char('{')?;
+            for keyframe in keyframes {
+                if !f.alternate() {
+                    f.write_char('\n')?;
+                    indent(f, 1)?;
+                }
+                write_keyframe(f, keyframe)?;
+            }
+            if !f.alternate() {
+                f.write_char('\n')?;
+            }
+            f.write_char('}')
+        }
+    }
+}
+
+fn write_keyframe(f: &mut Formatter, keyframe: &Keyframe) -> fmt::Result {
+    for (i, &offset) in keyframe.offsets.iter().enumerate() {
+        if i > 0 {
+            comma(f)?;
+        }
+        // Offsets were divided by 100 when parsed, so use the fewest digits that give back the
+        // same offset.
+        let percent = offset as f64 * 100.0;
+        let digits = (0..10).map(|precision| format!("{:.*}", precision, percent))
+            .find(|s| s.parse::<f32>().is_ok_and(|p| p / 100.0 == offset))
+            .unwrap_or_else(|| percent.to_string());
+        write_number_str(f, &digits)?;
+        f.write_char('%')?;
+    }
+    space(f)?;
+    write_declaration_block(f, &keyframe.declarations, 1)
+}
+
+/// Write declarations in braces, one per line indented to `depth + 1` unless minified.
+fn write_declaration_block(f: &mut Formatter, declarations: &[Declaration],
+                           depth: usize) -> fmt::Result {
+    f.write_char('{')?;
+    let mut rest = declarations;
+    while let Some(declaration) = rest.first() {
+        if !f.alternate() {
+            f.write_char('\n')?;
+            indent(f, depth + 1)?;
+        }
+        let n = match pending_shorthand(rest) {
+            Some((shorthand, source, n)) => {
+                f.write_str(shorthand)?;
+                f.write_char(':')?;
+                space(f)?;
+                f.write_str(source)?;
+                n
+            }
+            None => {
+                f.write_str(&declaration.name)?;
+                f.write_char(':')?;
+                space(f)?;
+                write_item(f, &declaration.value)?;
+                1
+            }
+        };
+        if declaration.important {
+            space(f)?;
+            f.write_str("!important")?;
+        }
+        rest = &rest[n..];
+        // The last semicolon is optional.
+        if !rest.is_empty() || !f.alternate() {
+            f.write_char(';')?;
+        }
+    }
+    if !f.alternate() && !declarations.is_empty() {
+        f.write_char('\n')?;
+        indent(f, depth)?;
+    }
+    f.write_char('}')
+}
+
+/// If `declarations` start with the longhands of a shorthand that uses `var()`, return the name
+/// and source text of the shorthand, and the number of longhands.  These are written as the
+/// shorthand again, which expands to the same longhands when parsed.
+fn pending_shorthand(declarations: &[Declaration]) -> Option<(&str, &str, usize)> {
+    let first = declarations.first()?;
+    let Value::PendingSubstitution(ref shorthand, ref source) = first.value else {
+        return None;
+    };
+    let longhands = shorthands::expand(Declaration {
+        name: shorthand.clone(),
+        value: Value::Unparsed(source.clone()),
+        important: first.important,
+    });
+    let n = longhands.len();
+    let matches = declarations.len() >= n && declarations[..n] == longhands[..];
+    matches.then_some((shorthand, source, n))
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Selector::Simple(simple) => write_item(f, simple),
+            Selector::Descendant(ancestor, simple) => {
+                write_item(f, &**ancestor)?;
+                f.write_char(' ')?;
+                write_item(f, simple)
+            }
+            Selector::Child(parent, simple) => {
+                write_item(f, &**parent)?;
+                f.write_str(if f.alternate() { ">" } else { " > " })?;
+                write_item(f, simple)
+            }
+        }
+    }
+}
+
+impl Display for SimpleSelector {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let SimpleSelector { tag_name, id, class, pseudo_classes, pseudo_element } = self;
+        if tag_name.is_none() && id.is_none() && class.is_empty() && pseudo_classes.is_empty()
+            && pseudo_element.is_none() {
+            return f.write_char('*');
+        }
+        if let Some(tag_name) = tag_name {
+            f.write_str(tag_name)?;
+        }
+        if let Some(id) = id {
+            write!(f, "#{}", id)?;
+        }
+        for class in class {
+            write!(f, ".{}", class)?;
+        }
+        for pseudo_class in pseudo_classes {
+            let (name, selectors) = match pseudo_class {
+                PseudoClass::Is(selectors) => ("is", selectors),
+                PseudoClass::Where(selectors) => ("where", selectors),
+                PseudoClass::Root => {
+                    f.write_str(":root")?;
+                    continue;
+                }
+                // These all parse to the same thing.
+                PseudoClass::UserAction => {
+                    f.write_str(":hover")?;
+                    continue;
+                }
+            };
+            write!(f, ":{}(", name)?;
+            for (i, selector) in selectors.iter().enumerate() {
+                if i > 0 {
+                    comma(f)?;
+                }
+                write_item(f, selector)?;
+            }
+            f.write_char(')')?;
+        }
+        match pseudo_element {
+            Some(PseudoElement::Before) => f.write_str("::before"),
+            Some(PseudoElement::After) => f.write_str("::after"),
+            Some(PseudoElement::Marker) => f.write_str("::marker"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Value::Keyword(keyword) => f.write_str(keyword),
+            Value::Length(n, unit) => {
+                write_number(f, *n)?;
+                write_item(f, unit)
+            }
+            Value::Number(n) => write_number(f, *n),
+            Value::ColorValue(color) => write_item(f, color),
+            Value::Str(s) => write_string(f, s),
+            Value::Function(name, args) => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        comma(f)?;
+                    }
+                    write_item(f, arg)?;
+                }
+                f.write_char(')')
+            }
+            Value::List(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(' ')?;
+                    }
+                    write_item(f, value)?;
+                }
+                Ok(())
+            }
+            Value::Calc(node) => match **node {
+                CalcNode::Min(_) | CalcNode::Max(_) | CalcNode::Clamp(..) => write_item(f, &**node),
+                _ => {
+                    f.write_str("calc(")?;
+                    write_item(f, &**node)?;
+                    f.write_char(')')
+                }
+            },
+            Value::CommaList(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        comma(f)?;
+                    }
+                    write_item(f, value)?;
+                }
+                Ok(())
+            }
+            Value::Delim(c) => f.write_char(*c),
+            Value::Unparsed(source) | Value::PendingSubstitution(_, source) => f.write_str(source),
+        }
+    }
+}
+
+/// A math expression, as it appears inside `calc()`.
+impl Display for CalcNode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            CalcNode::Number(n) => write_number(f, *n),
+            CalcNode::Length(n, unit) => {
+                write_number(f, *n)?;
+                write_item(f, unit)
+            }
+            CalcNode::Sum(terms) => {
+                for (i, term) in terms.iter().enumerate() {
+                    match term {
+                        CalcNode::Negate(term) if i > 0 => {
+                            f.write_str(" - ")?;
+                            write_operand(f, term, false)?;
+                        }
+                        term => {
+                            if i > 0 {
+                                f.write_str(" + ")?;
+                            }
+                            write_operand(f, term, false)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+            CalcNode::Product(factors) => {
+                for (i, factor) in factors.iter().enumerate() {
+                    let (operator, factor) = match factor {
+                        CalcNode::Invert(factor) if i > 0 => ("/", &**factor),
+                        factor => ("*", factor),
+                    };
+                    if i > 0 {
+                        if f.alternate() {
+                            f.write_str(operator)?;
+                        } else {
+                            write!(f, " {} ", operator)?;
+                        }
+                    }
+                    write_operand(f, factor, true)?;
+                }
+                Ok(())
+            }
+            // The parser only makes these inside sums and products.
+            CalcNode::Negate(node) => {
+                f.write_str("-1 * ")?;
+                write_operand(f, node, true)
+            }
+            CalcNode::Invert(node) => {
+                f.write_str("1 / ")?;
+                write_operand(f, node, true)
+            }
+            CalcNode::Min(args) => write_math_function(f, "min", args.iter()),
+            CalcNode::Max(args) => write_math_function(f, "max", args.iter()),
+            CalcNode::Clamp(min, value, max) => {
+                write_math_function(f, "clamp", [&**min, &**value, &**max].into_iter())
+            }
+        }
+    }
+}
+
+/// Write a term of a sum, or a factor of a product if `in_product` is true, with parentheses if
+/// they are needed to parse it back into the same tree.
+fn write_operand(f: &mut Formatter, node: &CalcNode, in_product: bool) -> fmt::Result {
+    let parenthesize = match node {
+        CalcNode::Sum(_) => true,
+        CalcNode::Product(_) | CalcNode::Negate(_) | CalcNode::Invert(_) => in_product,
+        _ => false,
+    };
+    if parenthesize {
+        f.write_char('(')?;
+        write_item(f, node)?;
+        f.write_char(')')
+    } else {
+        write_item(f, node)
+    }
+}
+
+fn write_math_function<'a>(f: &mut Formatter, name: &str,
+                           args: impl Iterator<Item = &'a CalcNode>) -> fmt::Result {
+    write!(f, "{}(", name)?;
+    for (i, arg) in args.enumerate() {
+        if i > 0 {
+            comma(f)?;
+        }
+        write_item(f, arg)?;
+    }
+    f.write_char(')')
+}
+
+impl Display for Unit {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Unit::Px => "px",
+            Unit::Pt => "pt",
+            Unit::Pc => "pc",
+            Unit::In => "in",
+            Unit::Cm => "cm",
+            Unit::Mm => "mm",
+            Unit::Q => "q",
+            Unit::Em => "em",
+            Unit::Rem => "rem",
+            Unit::Ex => "ex",
+            Unit::Ch => "ch",
+            Unit::Vw => "vw",
+            Unit::Vh => "vh",
+            Unit::Vmin => "vmin",
+            Unit::Vmax => "vmax",
+            Unit::Percent => "%",
+        })
+    }
+}
+
+/// A hex color, in its shortest form if minified.
+impl Display for Color {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let Color { r, g, b, a } = *self;
+        let short = f.alternate() && [r, g, b, a].iter().all(|&c| c % 17 == 0);
+        match (short, a) {
+            (true, 255) => write!(f, "#{:x}{:x}{:x}", r / 17, g / 17, b / 17),
+            (true, _) => write!(f, "#{:x}{:x}{:x}{:x}", r / 17, g / 17, b / 17, a / 17),
+            (false, 255) => write!(f, "#{:02x}{:02x}{:02x}", r, g, b),
+            (false, _) => write!(f, "#{:02x}{:02x}{:02x}{:02x}", r, g, b, a),
+        }
+    }
+}
+
+impl Display for MediaQueryList {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (i, query) in self.0.iter().enumerate() {
+            if i > 0 {
+                comma(f)?;
+            }
+            write_item(f, query)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for MediaQuery {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let condition = match (&self.media_type, self.negated, &self.condition) {
+            (MediaType::All, false, Some(condition)) => return write_item(f, condition),
+            (_, _, condition) => condition,
+        };
+        if self.negated {
+            f.write_str("not ")?;
+        }
+        f.write_str(match self.media_type {
+            MediaType::All => "all",
+            MediaType::Screen => "screen",
+            MediaType::Print => "print",
+            MediaType::Unknown(ref name) => name,
+        })?;
+        match condition {
+            // After a media type, conditions joined by `or` need parentheses.
+            Some(condition @ MediaCondition::Or(_)) => {
+                f.write_str(" and (")?;
+                write_item(f, condition)?;
+                f.write_char(')')
+            }
+            Some(condition) => {
+                f.write_str(" and ")?;
+                write_item(f, condition)
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Display for MediaCondition {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let (conditions, combinator) = match self {
+            MediaCondition::Feature(feature) => return write_item(f, feature),
+            MediaCondition::Not(condition) => {
+                f.write_str("not ")?;
+                return write_media_in_parens(f, condition);
+            }
+            MediaCondition::And(conditions) => (conditions, " and "),
+            MediaCondition::Or(conditions) => (conditions, " or "),
+        };
+        for (i, condition) in conditions.iter().enumerate() {
+            if i > 0 {
+                f.write_str(combinator)?;
+            }
+            write_media_in_parens(f, condition)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write a media feature, which has its own parentheses, or a condition in parentheses.
+fn write_media_in_parens(f: &mut Formatter, condition: &MediaCondition) -> fmt::Result {
+    match condition {
+        MediaCondition::Feature(feature) => write_item(f, feature),
+        condition => {
+            f.write_char('(')?;
+            write_item(f, condition)?;
+            f.write_char(')')
+        }
+    }
+}
+
+/// A media feature in range syntax, like `(width >= 600px)`, or `(orientation: portrait)`.
+impl Display for MediaFeature {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_char('(')?;
+        match &self.comparisons[..] {
+            [] => f.write_str(&self.name)?,
+            [(Comparison::Eq, value)] => {
+                f.write_str(&self.name)?;
+                f.write_char(':')?;
+                space(f)?;
+                write_item(f, value)?;
+            }
+            [(comparison, value)] => {
+                f.write_str(&self.name)?;
+                write_comparison(f, *comparison)?;
+                write_item(f, value)?;
+            }
+            // A range like `400px <= width < 700px`.
+            [(first, low), rest @ ..] => {
+                write_item(f, low)?;
+                write_comparison(f, first.flip())?;
+                f.write_str(&self.name)?;
+                for (comparison, value) in rest {
+                    write_comparison(f, *comparison)?;
+                    write_item(f, value)?;
+                }
+            }
+        }
+        f.write_char(')')
+    }
+}
+
+fn write_comparison(f: &mut Formatter, comparison: Comparison) -> fmt::Result {
+    let operator = match comparison {
+        Comparison::Lt => "<",
+        Comparison::Le => "<=",
+        Comparison::Eq => "=",
+        Comparison::Ge => ">=",
+        Comparison::Gt => ">",
+    };
+    if f.alternate() {
+        f.write_str(operator)
+    } else {
+        write!(f, " {} ", operator)
+    }
+}
+
+impl Display for MediaValue {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            MediaValue::Length(px) => {
+                write_number(f, *px)?;
+                f.write_str("px")
+            }
+            MediaValue::Resolution(dppx) => {
+                write_number(f, *dppx)?;
+                f.write_str("dppx")
+            }
+            MediaValue::Number(n) => write_number(f, *n),
+            MediaValue::Ident(ident) => f.write_str(ident),
+        }
+    }
+}
+
+/// Write `item` in the same mode, normal or minified, as `f`.
+fn write_item(f: &mut Formatter, item: &impl Display) -> fmt::Result {
+    if f.alternate() {
+        write!(f, "{:#}", item)
+    } else {
+        write!(f, "{}", item)
+    }
+}
+
+/// Write a number with as many digits as it takes to parse back to the same value.
+fn write_number(f: &mut Formatter, n: f32) -> fmt::Result {
+    write_number_str(f, &n.to_string())
+}
+
+/// Write the digits of a number, leaving out the zero before the decimal point if minified.
+fn write_number_str(f: &mut Formatter, digits: &str) -> fmt::Result {
+    if f.alternate() {
+        if let Some(fraction) = digits.strip_prefix("0.") {
+            return write!(f, ".{}", fraction);
+        }
+        if let Some(fraction) = digits.strip_prefix("-0.") {
+            return write!(f, "-.{}", fraction);
+        }
+    }
+    f.write_str(digits)
+}
+
+/// Write a double-quoted string, escaping quotes, backslashes, and control characters.
+fn write_string(f: &mut Formatter, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' | '\\' => write!(f, "\\{}", c)?,
+            // A space after a hex escape ends it, and is not part of the string.
+            c if c.is_control() => write!(f, "\\{:x} ", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// A comma between list items, followed by a space unless minified.
+fn comma(f: &mut Formatter) -> fmt::Result {
+    f.write_char(',')?;
+    space(f)
+}
+
+/// A space that is left out when minified.
+fn space(f: &mut Formatter) -> fmt::Result {
+    if f.alternate() { Ok(()) } else { f.write_char(' ') }
+}
+
+fn indent(f: &mut Formatter, depth: usize) -> fmt::Result {
+    for _ in 0..depth {
+        f.write_str("  ")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::css;
+
+    /// Check that both forms of serializing `source` parse back to the same stylesheet, and
+    /// serialize to the same text again.
+    fn assert_round_trips(source: &str) {
+        let sheet = css::parse(source.to_string());
+        for text in [format!("{}", sheet), format!("{:#}", sheet)] {
+            let reparsed = css::parse(text.clone());
+            assert_eq!(reparsed, sheet, "{}", text);
+            assert_eq!(format!("{}", reparsed), format!("{}", sheet));
+            assert_eq!(format!("{:#}", reparsed), format!("{:#}", sheet));
+        }
+    }
+
+    #[test]
+    fn nesting() {
+        assert_round_trips(".a { color: red; & > .b { color: blue } color: green; \
+                                 .c & { margin: 0 auto } \
+                                 @media (min-width: 600px) { padding: 1em 2px; } }");
+    }
+
+    #[test]
+    fn layers_and_media() {
+        assert_round_trips("@layer reset, base; \
+                            @layer base { a { color: red } @layer inner { b { color: blue } } } \
+                            @layer { c { color: green } } \
+                            @layer { d { color: white } } \
+                            @media screen and (400px <= width < 700px), not print { \
+                                @layer reset { e { display: none } } } \
+                            @media (prefers-color-scheme: dark) { f { color: black } }");
+    }
+
+    #[test]
+    fn at_rules() {
+        assert_round_trips("@font-face { font-family: \"My Font\"; \
+                                         src: url(\"a b.woff\") format(\"woff\"); \
+                                         unicode-range: U+0-7F; } \
+                            @keyframes spin { from { width: 0 } 50% { width: 10px } \
+                                              to { width: 100% } }");
+    }
+
+    #[test]
+    fn values() {
+        assert_round_trips("a { width: calc(100% - 2 * (1em + 3px)); \
+                                height: min(10px, max(2em, 5vh)); \
+                                font-family: \"Quote \\\" and \\\\ back\", 'single', serif; \
+                                --custom: { weird } stuff; \
+                                margin: var(--x, 1px) 2px; \
+                                color: rgb(10 20 30 / 50%) !important; }");
+    }
+}
@@ -0,0 +1,351 @@
+//! Expansion of shorthand properties into their longhand sub-properties.
+//!
+//! https://www.w3.org/TR/css-cascade-4/#shorthand
+
+use crate::css::{Color, Declaration, Unit, Value};
+use crate::properties;
+
+/// Expand a shorthand declaration into declarations of all its longhands.  Other declarations are
+/// returned unchanged.
+///
+/// Longhands that are omitted from the shorthand are set to their initial values.  If the value
+/// is not valid for the shorthand, or the part of it for any one longhand is not valid for that
+/// longhand, the whole declaration is dropped.
+pub fn expand(declaration: Declaration) -> Vec<Declaration> {
+    let names = match longhands(&declaration.name) {
+        Some(names) => names,
+        None => return vec![declaration],
+    };
+    let values = match declaration.value {
+        // Until variables are substituted we can't tell which part goes to which longhand.
+        Value::Unparsed(ref source) => {
+            let pending = Value::PendingSubstitution(declaration.name.clone(), source.clone());
+            vec![pending; names.len()]
+        }
+        // CSS-wide keywords apply to every longhand.
+        Value::Keyword(ref k) if is_css_wide_keyword(k) => {
+            vec![declaration.value.clone(); names.len()]
+        }
+        ref value => match expand_value(&declaration.name, value) {
+            Some(values) => values,
+            None => return vec![],
+        }
+    };
+    debug_assert_eq!(names.len(), values.len());
+    let important = declaration.important;
+    let longhands: Vec<_> = names.iter().zip(values)
+        .map(|(name, value)| Declaration { name: name.to_string(), value, important })
+        .collect();
+    if !longhands.iter().all(properties::is_valid) {
+        return vec![];
+    }
+    longhands
+}
+
+/// The longhands set by a shorthand property, or `None` if `name` is not a shorthand.
+fn longhands(name: &str) -> Option<&'static [&'static str]> {
+    Some(match name {
+        "margin" => &["margin-top", "margin-right", "margin-bottom", "margin-left"],
+        "padding" => &["padding-top", "padding-right", "padding-bottom", "padding-left"],
+        "inset" => &["top", "right", "bottom", "left"],
+        "border-width" => &["border-top-width", "border-right-width",
+                            "border-bottom-width", "border-left-width"],
+        "border-style" => &["border-top-style", "border-right-style",
+                            "border-bottom-style", "border-left-style"],
+        "border-color" => &["border-top-color", "border-right-color",
+                            "border-bottom-color", "border-left-color"],
+        "border-top" => &["border-top-width", "border-top-style", "border-top-color"],
+        "border-right" => &["border-right-width", "border-right-style", "border-right-color"],
+        "border-bottom" => &["border-bottom-width", "border-bottom-style", "border-bottom-color"],
+        "border-left" => &["border-left-width", "border-left-style", "border-left-color"],
+        "border" => &["border-top-width", "border-top-style", "border-top-color",
+                      "border-right-width", "border-right-style", "border-right-color",
+                      "border-bottom-width", "border-bottom-style", "border-bottom-color",
+                      "border-left-width", "border-left-style", "border-left-color"],
+        "background" => &["background-color", "background-image", "background-repeat",
+                          "background-attachment", "background-position", "background-size",
+                          "background-origin", "background-clip"],
+        "font" => &["font-style", "font-variant", "font-weight", "font-stretch", "font-size",
+                    "line-height", "font-family"],
+        "gap" => &["row-gap", "column-gap"],
+        "list-style" => &["list-style-position", "list-style-image", "list-style-type"],
+        // A legacy name for `overflow-wrap`.
+        "word-wrap" => &["overflow-wrap"],
+        _ => return None
+    })
+}
+
+/// Split a shorthand's value into the values of its longhands, in the order given by `longhands`.
+fn expand_value(name: &str, value: &Value) -> Option<Vec<Value>> {
+    match name {
+        "margin" | "padding" | "inset" | "border-width" | "border-style" | "border-color" => {
+            box_edges(value)
+        }
+        "border-top" | "border-right" | "border-bottom" | "border-left" => border_side(value),
+        "border" => border_side(value).map(|side| {
+            (0..4).flat_map(|_| side.iter().cloned()).collect()
+        }),
+        "background" => background(value),
+        "font" => font(value),
+        "gap" => match value.items() {
+            [gap] => Some(vec![gap.clone(), gap.clone()]),
+            [row, column] => Some(vec![row.clone(), column.clone()]),
+            _ => None
+        },
+        "list-style" => list_style(value),
+        "word-wrap" => Some(vec![value.clone()]),
+        _ => unreachable!()
+    }
+}
+
+/// Is `k` a keyword that every property accepts?
+///
+/// https://www.w3.org/TR/css-cascade-5/#defaulting-keywords
+pub fn is_css_wide_keyword(k: &str) -> bool {
+    matches!(k, "initial" | "inherit" | "unset" | "revert" | "revert-layer")
+}
+
+fn keyword(k: &str) -> Value {
+    Value::Keyword(k.to_string())
+}
+
+/// Expand one to four values into top, right, bottom, and left values.
+///
+/// https://www.w3.org/TR/css-box-4/#margin-shorthand
+fn box_edges(value: &Value) -> Option<Vec<Value>> {
+    let [top, right, bottom, left] = match value.items() {
+        [all] => [all, all, all, all],
+        [vertical, horizontal] => [vertical, horizontal, vertical, horizontal],
+        [top, horizontal, bottom] => [top, horizontal, bottom, horizontal],
+        [top, right, bottom, left] => [top, right, bottom, left],
+        _ => return None
+    };
+    Some(vec![top.clone(), right.clone(), bottom.clone(), left.clone()])
+}
+
+/// Parse `<line-width> || <line-style> || <color>` into width, style, and color.
+///
+/// https://www.w3.org/TR/css-backgrounds-3/#border-shorthands
+fn border_side(value: &Value) -> Option<Vec<Value>> {
+    let (mut width, mut style, mut color) = (None, None, None);
+    for item in value.items() {
+        let slot = match item {
+            Value::Keyword(k) if is_line_style(k) => &mut style,
+            Value::Keyword(k) if matches!(&**k, "thin" | "medium" | "thick") => &mut width,
+            Value::Length(..) | Value::Number(_) | Value::Calc(_) => &mut width,
+            _ if is_color(item) => &mut color,
+            _ => return None
+        };
+        if slot.replace(item.clone()).is_some() {
+            return None;
+        }
+    }
+    Some(vec![
+        width.unwrap_or_else(|| keyword("medium")),
+        style.unwrap_or_else(|| keyword("none")),
+        color.unwrap_or_else(|| keyword("currentcolor")),
+    ])
+}
+
+fn is_line_style(k: &str) -> bool {
+    matches!(k, "none" | "hidden" | "dotted" | "dashed" | "solid" | "double" | "groove" | "ridge"
+                | "inset" | "outset")
+}
+
+fn is_color(value: &Value) -> bool {
+    match value {
+        Value::ColorValue(_) => true,
+        Value::Keyword(k) => k == "currentcolor",
+        _ => false
+    }
+}
+
+/// Parse the `background` shorthand, which may have several comma-separated layers.  Only the
+/// final layer may have a color.
+///
+/// https://www.w3.org/TR/css-backgrounds-3/#background
+fn background(value: &Value) -> Option<Vec<Value>> {
+    let layers = match value {
+        Value::CommaList(layers) => &layers[..],
+        value => std::slice::from_ref(value),
+    };
+    // Each longhand after background-color gets one value per layer.
+    let mut per_layer: Vec<Vec<Value>> = vec![Vec::new(); 7];
+    let mut color = Value::ColorValue(Color { r: 0, g: 0, b: 0, a: 0 });
+    for (i, layer) in layers.iter().enumerate() {
+        let final_layer = i == layers.len() - 1;
+        let (mut image, mut repeat, mut attachment) = (None, Vec::new(), None);
+        let (mut position, mut size, mut boxes) = (Vec::new(), Vec::new(), Vec::new());
+        let mut layer_color = None;
+        let mut after_slash = false;
+        for item in layer.items() {
+            let is_length = matches!(item, Value::Length(..) | Value::Number(_) | Value::Calc(_));
+            let k = match item {
+                Value::Keyword(k) => &**k,
+                _ => ""
+            };
+            match item {
+                Value::Delim('/') if !position.is_empty() => after_slash = true,
+                _ if after_slash && (is_length || matches!(k, "auto" | "cover" | "contain")) => {
+                    size.push(item.clone())
+                }
+                Value::Function(f, _) if f == "url" || f.ends_with("gradient") => {
+                    image = Some(item.clone())
+                }
+                _ if k == "none" => image = Some(item.clone()),
+                _ if matches!(k, "repeat" | "repeat-x" | "repeat-y" | "no-repeat" | "space"
+                              | "round") => repeat.push(item.clone()),
+                _ if matches!(k, "scroll" | "fixed" | "local") => attachment = Some(item.clone()),
+                _ if matches!(k, "border-box" | "padding-box" | "content-box") => {
+                    boxes.push(item.clone())
+                }
+                _ if is_length || matches!(k, "left" | "right" | "top" | "bottom" | "center") => {
+                    position.push(item.clone())
+                }
+                _ if is_color(item) && final_layer && layer_color.is_none() => {
+                    layer_color = Some(item.clone())
+                }
+                _ => return None
+            }
+        }
+        if let Some(c) = layer_color {
+            color = c;
+        }
+        let origin = boxes.first().cloned().unwrap_or_else(|| keyword("padding-box"));
+        let clip = boxes.get(1).cloned().or(boxes.first().cloned())
+            .unwrap_or_else(|| keyword("border-box"));
+        let or_default = |values: Vec<Value>, default: &str| {
+            if values.is_empty() { keyword(default) } else { Value::from_list(values) }
+        };
+        let layer_values = [
+            image.unwrap_or_else(|| keyword("none")),
+            or_default(repeat, "repeat"),
+            attachment.unwrap_or_else(|| keyword("scroll")),
+            if position.is_empty() {
+                Value::List(vec![Value::Length(0.0, Unit::Percent); 2])
+            } else {
+                Value::from_list(position)
+            },
+            or_default(size, "auto"),
+            origin,
+            clip,
+        ];
+        for (longhand, value) in per_layer.iter_mut().zip(layer_values) {
+            longhand.push(value);
+        }
+    }
+    let mut values = vec![color];
+    values.extend(per_layer.into_iter().map(|mut layers| {
+        if layers.len() == 1 { layers.remove(0) } else { Value::CommaList(layers) }
+    }));
+    Some(values)
+}
+
+/// Parse the `font` shorthand:
+///
+/// `[ <font-style> || <font-variant> || <font-weight> || <font-stretch> ]? <font-size>
+///  [ / <line-height> ]? <font-family>`
+///
+/// https://www.w3.org/TR/css-fonts-4/#font-prop
+fn font(value: &Value) -> Option<Vec<Value>> {
+    let (first, other_families) = match value {
+        Value::CommaList(groups) => (groups[0].items(), &groups[1..]),
+        value => (value.items(), &[][..]),
+    };
+    let (mut style, mut variant, mut weight, mut stretch) = (None, None, None, None);
+    let mut i = 0;
+    while i < first.len() {
+        let item = &first[i];
+        let slot = match item {
+            // `normal` can apply to any of the four, and is the initial value of each anyway.
+            Value::Keyword(k) if k == "normal" => { i += 1; continue; }
+            Value::Keyword(k) if matches!(&**k, "italic" | "oblique") => &mut style,
+            Value::Keyword(k) if k == "small-caps" => &mut variant,
+            Value::Keyword(k) if matches!(&**k, "bold" | "bolder" | "lighter") => &mut weight,
+            Value::Number(n) if (1.0..=1000.0).contains(n) => &mut weight,
+            Value::Keyword(k) if is_font_stretch(k) => &mut stretch,
+            _ => break
+        };
+        if slot.replace(item.clone()).is_some() {
+            return None;
+        }
+        i += 1;
+    }
+
+    let size = match first.get(i)? {
+        size @ (Value::Length(..) | Value::Calc(_)) => size.clone(),
+        size @ Value::Keyword(k) if is_font_size_keyword(k) => size.clone(),
+        _ => return None
+    };
+    i += 1;
+
+    let mut line_height = keyword("normal");
+    if let Some(Value::Delim('/')) = first.get(i) {
+        line_height = first.get(i + 1)?.clone();
+        i += 2;
+    }
+
+    if i >= first.len() {
+        return None;
+    }
+    let mut families = vec![Value::from_list(first[i..].to_vec())];
+    families.extend(other_families.iter().cloned());
+    let family = if families.len() == 1 { families.remove(0) } else { Value::CommaList(families) };
+
+    Some(vec![
+        style.unwrap_or_else(|| keyword("normal")),
+        variant.unwrap_or_else(|| keyword("normal")),
+        weight.unwrap_or_else(|| keyword("normal")),
+        stretch.unwrap_or_else(|| keyword("normal")),
+        size,
+        line_height,
+        family,
+    ])
+}
+
+fn is_font_stretch(k: &str) -> bool {
+    matches!(k, "ultra-condensed" | "extra-condensed" | "condensed" | "semi-condensed"
+                | "semi-expanded" | "expanded" | "extra-expanded" | "ultra-expanded")
+}
+
+fn is_font_size_keyword(k: &str) -> bool {
+    matches!(k, "xx-small" | "x-small" | "small" | "medium" | "large" | "x-large" | "xx-large"
+                | "xxx-large" | "smaller" | "larger")
+}
+
+/// Parse `<list-style-position> || <list-style-image> || <list-style-type>`.
+///
+/// https://www.w3.org/TR/css-lists-3/#list-style-property
+fn list_style(value: &Value) -> Option<Vec<Value>> {
+    let (mut position, mut image, mut list_type) = (None, None, None);
+    let mut nones = 0;
+    for item in value.items() {
+        let slot = match item {
+            // `none` could be the image or the type; sort it out below.
+            Value::Keyword(k) if k == "none" => { nones += 1; continue; }
+            Value::Keyword(k) if matches!(&**k, "inside" | "outside") => &mut position,
+            Value::Function(f, _) if f == "url" => &mut image,
+            Value::Keyword(_) | Value::Str(_) => &mut list_type,
+            _ => return None
+        };
+        if slot.replace(item.clone()).is_some() {
+            return None;
+        }
+    }
+    match (nones, &image, &list_type) {
+        (0, _, _) => {}
+        // A single `none` with nothing else applies to both.
+        (1, None, None) | (2, None, None) => {
+            image = Some(keyword("none"));
+            list_type = Some(keyword("none"));
+        }
+        (1, None, Some(_)) => image = Some(keyword("none")),
+        (1, Some(_), None) => list_type = Some(keyword("none")),
+        _ => return None
+    }
+    Some(vec![
+        position.unwrap_or_else(|| keyword("outside")),
+        image.unwrap_or_else(|| keyword("none")),
+        list_type.unwrap_or_else(|| keyword("disc")),
+    ])
+}
@@ -4,8 +4,22 @@
 //! complicated if I add support for compound selectors.
 
 use crate::dom::{Node, NodeType, ElementData};
-use crate::css::{Stylesheet, Rule, Selector, SimpleSelector, Value, Specificity};
+use crate::css::{Stylesheet, Selector, SimpleSelector, PseudoClass, PseudoElement};
+use crate::css::{self, Declaration, Origin, Value};
+use crate::bidi;
+use crate::bloom::Ancestors;
+use crate::cascade::{Cascade, CascadeKey};
+use crate::content;
+use crate::font::Font;
+use crate::font_database::FontDatabase;
+use crate::layout::Rect;
+use crate::media::Device;
+use crate::properties::{ComputedStyle, PROPERTIES};
+use crate::shorthands::is_css_wide_keyword;
+use crate::values::{BorderStyle, Context, Direction, Display, DEFAULT_FONT_SIZE};
+use crate::variables::{self, CustomProperties};
 use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
 
 /// Map from CSS property names to values.
 pub type PropertyMap = HashMap<String, Value>;
@@ -13,102 +27,512 @@ pub type PropertyMap = HashMap<String, Value>;
 /// A node with associated style data.
 pub struct StyledNode<'a> {
     pub node: &'a Node,
-    pub specified_values: PropertyMap,
+    /// For boxes generated by `::before` or `::after`, which pseudo-element this is.  In that
+    /// case `node` is the originating element.
+    pub pseudo: Option<PseudoElement>,
+    /// For the contents of a pseudo-element, the text generated by its `content` property.
+    pub generated_text: Option<String>,
+    pub style: Arc<ComputedStyle>,
     pub children: Vec<StyledNode<'a>>,
 }
 
-#[derive(PartialEq)]
-pub enum Display {
-    Inline,
-    Block,
-    None,
+impl<'a> StyledNode<'a> {
+    /// The text to render for this node, if it is a text node or generated text.
+    pub fn text(&self) -> Option<&str> {
+        match (&self.generated_text, &self.node.node_type) {
+            (Some(text), _) => Some(text),
+            (None, NodeType::Text(text)) if self.pseudo.is_none() => Some(text),
+            _ => None
+        }
+    }
+
+    /// The value of the `display` property.
+    pub fn display(&self) -> Display {
+        self.style.display
+    }
 }
 
-impl<'a> StyledNode<'a> {
-    /// Return the specified value of a property if it exists, otherwise `None`.
-    pub fn value(&self, name: &str) -> Option<Value> {
-        self.specified_values.get(name).cloned()
+/// Apply stylesheets to an entire DOM tree, returning a StyledNode tree.
+///
+/// `stylesheets` may come from any origin.  Within an origin, later stylesheets take precedence.
+/// The built-in `user_agent_stylesheet` always comes first.  Rules inside `@media` blocks only
+/// apply if their media queries match `device`.
+///
+/// Every node gets the computed value of every supported property, either from the cascade, by
+/// inheritance from its parent, or from the property's initial value.  Percentages depend on the
+/// property and are left for layout to resolve, but all other lengths are converted to px.
+///
+/// Elements whose `::before` or `::after` styles have a `content` value get an extra child at the
+/// start or end of their children, and list items get a `::marker` child before everything else.
+/// Once the whole tree is styled, the generated text is filled in by `content::generate_content`,
+/// since counters and quotes depend on document order.
+///
+/// Siblings that are sure to have the same styles share them, and a Bloom filter of each
+/// element's ancestors quickly rules out most descendant selectors that don't match.
+///
+/// `fonts` are the fonts that text will be drawn with, whose metrics the `ex` and `ch` units
+/// depend on.
+pub fn style_tree<'a>(root: &'a Node, stylesheets: &[Stylesheet], device: &Device,
+                      fonts: &FontDatabase) -> StyledNode<'a> {
+    let stylesheets = std::iter::once(user_agent_stylesheet()).chain(stylesheets).collect();
+    let cascade = Cascade::new(stylesheets, device, fonts);
+    let initial = StyleContext {
+        inherited: None,
+        root_font_size: None,
+        viewport: device.viewport,
+        custom_properties: Default::default(),
+        font: fonts.primary_font(ComputedStyle::initial()).clone(),
+    };
+    let mut ancestors = Ancestors::default();
+    let mut tree = style_node(root, &cascade, &initial, &mut ancestors,
+                              &mut SharingCache::default());
+    content::generate_content(&mut tree);
+    tree
+}
+
+/// The default styles for HTML elements, applied at the user-agent origin.
+///
+/// https://html.spec.whatwg.org/multipage/rendering.html
+pub fn user_agent_stylesheet() -> &'static Stylesheet {
+    static STYLESHEET: OnceLock<Stylesheet> = OnceLock::new();
+    STYLESHEET.get_or_init(|| {
+        css::parse_with_origin(include_str!("ua.css").to_string(), Origin::UserAgent)
+    })
+}
+
+/// The information needed to compute an element's values: what it inherits from its parent, and
+/// what relative lengths (other than percentages) are resolved against.
+#[derive(Clone)]
+struct StyleContext {
+    /// The computed values that children inherit, or `None` for the root element.
+    inherited: Option<Arc<ComputedStyle>>,
+    /// The computed `font-size` of the root element, for `rem`, or `None` while styling the root.
+    root_font_size: Option<f32>,
+    viewport: Rect,
+    custom_properties: CustomProperties,
+    /// The primary font that `ex` and `ch` are relative to in the font properties.
+    font: Arc<Font>,
+}
+
+/// The styles of an element and of its pseudo-elements that generate boxes.
+#[derive(Clone)]
+struct ElementStyles {
+    style: Arc<ComputedStyle>,
+    /// The context for styling the element's children.
+    context: StyleContext,
+    /// `::marker`, `::before` and `::after`, in that order, if they generate boxes.
+    pseudos: Vec<(PseudoElement, Arc<ComputedStyle>, StyleContext)>,
+}
+
+/// Styles of earlier siblings that can be reused by later ones.
+///
+/// Selectors can only test an element's tag name, id and classes, and those of its ancestors.  So
+/// two siblings with the same tag name and classes, no id, and no `style` attribute match the same
+/// rules, and inherit from the same parent, which means they have the same computed values.
+#[derive(Default)]
+struct SharingCache<'a> {
+    elements: HashMap<(&'a str, Vec<&'a str>), ElementStyles>,
+    /// The style of text nodes, which depends only on the parent.
+    text: Option<Arc<ComputedStyle>>,
+}
+
+/// The key for sharing `elem`'s styles with its siblings, or `None` if they can't be shared.
+fn sharing_key(elem: &ElementData) -> Option<(&str, Vec<&str>)> {
+    if elem.id().is_some() || elem.attrs.contains_key("style") || has_dir_declarations(elem)
+        || !list_declarations(elem).is_empty() {
+        return None;
     }
+    let mut classes: Vec<&str> = elem.classes().into_iter().collect();
+    classes.sort_unstable();
+    Some((&elem.tag_name, classes))
+}
+
+/// Style a node and its descendants, including any pseudo-elements.
+///
+/// `ancestors` are the node's ancestor elements, and `shared` holds the shareable styles of its
+/// earlier siblings.
+fn style_node<'a>(root: &'a Node, cascade: &Cascade, parent: &StyleContext,
+                  ancestors: &mut Ancestors<'a>, shared: &mut SharingCache<'a>) -> StyledNode<'a> {
+    let elem = match root.node_type {
+        NodeType::Element(ref elem) => elem,
+        NodeType::Text(_) => {
+            let style = shared.text.get_or_insert_with(|| text_style(parent, cascade.fonts));
+            return text_node(root, None, style.clone());
+        }
+    };
+    let styles = match sharing_key(elem) {
+        Some(key) => shared.elements.entry(key)
+            .or_insert_with(|| element_styles(root, elem, cascade, ancestors, parent))
+            .clone(),
+        None => element_styles(root, elem, cascade, ancestors, parent),
+    };
 
-    /// Return the specified value of property `name`, or property `fallback_name` if that doesn't
-    /// exist, or value `default` if neither does.
-    pub fn lookup(&self, name: &str, fallback_name: &str, default: &Value) -> Value {
-        self.value(name).unwrap_or_else(|| self.value(fallback_name)
-                        .unwrap_or_else(|| default.clone()))
+    ancestors.push(elem);
+    let mut children = style_children(root, cascade, &styles.context, ancestors);
+    ancestors.pop();
+
+    // `::marker` and `::before` go before the element's own children, and `::after` after them.
+    let mut leading = Vec::new();
+    for (pseudo, style, context) in styles.pseudos {
+        let node = pseudo_element(root, pseudo, style, &context, cascade.fonts);
+        match pseudo {
+            PseudoElement::After => children.push(node),
+            _ => leading.push(node),
+        }
     }
+    children.splice(0..0, leading);
 
-    /// The value of the `display` property (defaults to inline).
-    pub fn display(&self) -> Display {
-        match self.value("display") {
-            Some(Value::Keyword(s)) => match &*s {
-                "block" => Display::Block,
-                "none" => Display::None,
-                _ => Display::Inline
-            },
-            _ => Display::Inline
+    StyledNode {
+        node: root,
+        pseudo: None,
+        generated_text: None,
+        style: styles.style,
+        children,
+    }
+}
+
+/// Style the children of `parent`, whose ancestors (including `parent` itself) are `ancestors`.
+#[cfg(not(feature = "parallel"))]
+fn style_children<'a>(parent: &'a Node, cascade: &Cascade, context: &StyleContext,
+                      ancestors: &mut Ancestors<'a>) -> Vec<StyledNode<'a>> {
+    let mut shared = SharingCache::default();
+    parent.children.iter()
+        .map(|child| style_node(child, cascade, context, ancestors, &mut shared))
+        .collect()
+}
+
+/// Style the children of `parent`, whose ancestors (including `parent` itself) are `ancestors`.
+///
+/// With the `parallel` feature, sibling subtrees are styled concurrently by rayon's work-stealing
+/// thread pool.  Each task gets its own copy of the ancestors and its own sharing cache, so fewer
+/// styles are shared, but the results are the same.
+#[cfg(feature = "parallel")]
+fn style_children<'a>(parent: &'a Node, cascade: &Cascade, context: &StyleContext,
+                      ancestors: &mut Ancestors<'a>) -> Vec<StyledNode<'a>> {
+    use rayon::prelude::*;
+    parent.children.par_iter()
+        .map_init(|| (ancestors.clone(), SharingCache::default()), |(ancestors, shared), child| {
+            style_node(child, cascade, context, ancestors, shared)
+        })
+        .collect()
+}
+
+/// Compute the styles of an element, and of its pseudo-elements.  `node` is the element's node.
+fn element_styles(node: &Node, elem: &ElementData, cascade: &Cascade, ancestors: &Ancestors,
+                  parent: &StyleContext) -> ElementStyles {
+    let mut hints = dir_declarations(node, elem);
+    hints.extend(list_declarations(elem));
+    let cascaded = cascaded_values(elem, None, &hints, cascade, ancestors);
+    let (style, context) = compute_values(cascaded, parent, cascade.fonts);
+
+    let mut pseudos = Vec::new();
+    if style.display == Display::ListItem {
+        pseudos.push(PseudoElement::Marker);
+    }
+    pseudos.extend([PseudoElement::Before, PseudoElement::After]);
+    let pseudos = pseudos.into_iter().filter_map(|pseudo| {
+        let cascaded = cascaded_values(elem, Some(pseudo), &[], cascade, ancestors);
+        // Without any declarations, `content` is `normal`, so only `::marker` can have a box.
+        if cascaded.is_empty() && pseudo != PseudoElement::Marker {
+            return None;
         }
+        let (style, context) = compute_values(cascaded, &context, cascade.fonts);
+        generates_box(pseudo, &style).then_some((pseudo, style, context))
+    }).collect();
+
+    ElementStyles { style, context, pseudos }
+}
+
+/// A text node, or the text inside a pseudo-element.
+fn text_node<'a>(node: &'a Node, pseudo: Option<PseudoElement>,
+                 style: Arc<ComputedStyle>) -> StyledNode<'a> {
+    StyledNode {
+        node,
+        pseudo,
+        generated_text: None,
+        style,
+        children: Vec::new(),
     }
 }
 
-/// Apply a stylesheet to an entire DOM tree, returning a StyledNode tree.
+/// The style of text.  Text has no declarations of its own, so it inherits or uses the initial
+/// value of every property.
+fn text_style(parent: &StyleContext, fonts: &FontDatabase) -> Arc<ComputedStyle> {
+    compute_values(HashMap::new(), parent, fonts).0
+}
+
+/// Find the computed values of an element from its cascaded values.
 ///
-/// This finds only the specified values at the moment. Eventually it should be extended to find the
-/// computed values too, including inherited values.
-pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
+/// This substitutes variables, takes inherited or initial values for any properties without a
+/// cascaded value (or with a CSS-wide keyword), and converts all non-percentage lengths to px.
+/// Returns the computed values, and the context for computing the values of the element's
+/// children.
+///
+/// https://www.w3.org/TR/css-cascade-5/#defaulting
+fn compute_values(mut cascaded: PropertyMap, parent: &StyleContext,
+                  fonts: &FontDatabase) -> (Arc<ComputedStyle>, StyleContext) {
+    let custom_properties = variables::substitute(&mut cascaded, &parent.custom_properties);
+
+    // Start from the parent's values, so inherited properties are already set.
+    let parent_style = parent.inherited.as_deref().unwrap_or(ComputedStyle::initial());
+    let mut style = parent_style.clone();
+    let mut context = Context {
+        font_size: parent_style.font_size,
+        root_font_size: parent.root_font_size.unwrap_or(DEFAULT_FONT_SIZE),
+        viewport: parent.viewport,
+        color: parent_style.color,
+        parent_font_weight: parent_style.font_weight,
+        font: parent.font.clone(),
+    };
+    for property in PROPERTIES {
+        let value = match cascaded.remove(property.name) {
+            Some(Value::Keyword(ref k)) if k == "inherit" => None,
+            Some(Value::Keyword(ref k)) if k == "initial" => Some(property.initial_value()),
+            // `unset`, or `revert` with nothing to revert to.
+            Some(Value::Keyword(ref k)) if is_css_wide_keyword(k) && property.inherited => None,
+            Some(Value::Keyword(ref k)) if is_css_wide_keyword(k) => Some(property.initial_value()),
+            Some(value) => Some(value),
+            None if property.inherited => None,
+            None => Some(property.initial_value()),
+        };
+        // A value that is only found to be invalid after substituting variables acts like
+        // `unset`.
+        if let Some(value) = value {
+            if !style.set(property.name, value, &context) && !property.inherited {
+                style.set(property.name, property.initial_value(), &context);
+            }
+        }
+        match property.name {
+            "color" => context.color = style.color,
+            "font-size" => {
+                context.font_size = style.font_size;
+                context.root_font_size = parent.root_font_size.unwrap_or(style.font_size);
+            }
+            // This is the last of the properties that choose the font.
+            "font-style" => context.font = fonts.primary_font(&style).clone(),
+            _ => {}
+        }
+    }
+
+    // A border with no style has no width.
+    //
+    // https://www.w3.org/TR/css-backgrounds-3/#border-width
+    for (border_style, width) in [(style.border_top_style, &mut style.border_top_width),
+                                  (style.border_right_style, &mut style.border_right_width),
+                                  (style.border_bottom_style, &mut style.border_bottom_width),
+                                  (style.border_left_style, &mut style.border_left_width)] {
+        if matches!(border_style, BorderStyle::None | BorderStyle::Hidden) {
+            *width = 0.0;
+        }
+    }
+
+    let style = Arc::new(style);
+    let context = StyleContext {
+        inherited: Some(style.clone()),
+        root_font_size: Some(context.root_font_size),
+        viewport: parent.viewport,
+        custom_properties,
+        font: context.font,
+    };
+    (style, context)
+}
+
+/// Does a pseudo-element with this style generate a box?
+///
+/// https://www.w3.org/TR/css-pseudo-4/#generated-content
+fn generates_box(pseudo: PseudoElement, style: &ComputedStyle) -> bool {
+    match &style.content {
+        Value::Keyword(k) if k == "none" => false,
+        // `::before` and `::after` are only generated if `content` is not `normal` or `none`.
+        // For `::marker`, `normal` means the marker text comes from `list-style-type`.
+        Value::Keyword(k) if k == "normal" => pseudo == PseudoElement::Marker,
+        _ => true,
+    }
+}
+
+/// The box for a pseudo-element of `node`.
+///
+/// Its text is filled in later by `content::generate_content`.
+fn pseudo_element<'a>(node: &'a Node, pseudo: PseudoElement, style: Arc<ComputedStyle>,
+                      context: &StyleContext, fonts: &FontDatabase) -> StyledNode<'a> {
     StyledNode {
-        node: root,
-        specified_values: match root.node_type {
-            NodeType::Element(ref elem) => specified_values(elem, stylesheet),
-            NodeType::Text(_) => HashMap::new()
-        },
-        children: root.children.iter().map(|child| style_tree(child, stylesheet)).collect(),
+        node,
+        pseudo: Some(pseudo),
+        generated_text: None,
+        style,
+        children: vec![text_node(node, Some(pseudo), text_style(context, fonts))],
     }
 }
 
-/// Apply styles to a single element, returning the specified styles.
+/// Apply styles to a single element or one of its pseudo-elements, returning the cascaded value
+/// of each property that has one.
 ///
-/// To do: Allow multiple UA/author/user stylesheets, and implement the cascade.
-fn specified_values(elem: &ElementData, stylesheet: &Stylesheet) -> PropertyMap {
-    let mut values = HashMap::new();
-    let mut rules = matching_rules(elem, stylesheet);
+/// The declarations from every matching rule, from `hints` (which are user-agent styles that
+/// come after the user-agent stylesheet), and from the element's `style` attribute, are sorted
+/// into cascade order.  For each property, the last declaration wins, unless it is
+/// `revert` or `revert-layer`.  Those roll back to the declarations from an earlier origin or
+/// layer.
+///
+/// https://www.w3.org/TR/css-cascade-5/#default
+fn cascaded_values(elem: &ElementData, pseudo: Option<PseudoElement>, hints: &[Declaration],
+                   cascade: &Cascade, ancestors: &Ancestors) -> PropertyMap {
+    let mut declarations: Vec<(CascadeKey, &Declaration)> = Vec::new();
+    let sheets = cascade.stylesheets.iter().zip(&cascade.selector_maps);
+    for (sheet_index, (&stylesheet, selector_map)) in sheets.enumerate() {
+        let rules = selector_map.matching_rules(elem, pseudo, ancestors);
+        for (rule_index, specificity, rule) in rules {
+            let layer = cascade.layer_order(stylesheet.origin, sheet_index, rule.layer.as_deref());
+            for (i, declaration) in rule.declarations.iter().enumerate() {
+                let order = (sheet_index, rule_index, i);
+                let key = CascadeKey::new(stylesheet.origin, declaration.important, layer,
+                                          specificity, order);
+                declarations.push((key, declaration));
+            }
+        }
+    }
+
+    let layer = cascade.layer_order(Origin::UserAgent, 0, None);
+    for (i, declaration) in hints.iter().enumerate() {
+        let key = CascadeKey::new(Origin::UserAgent, false, layer, (0, 1, 0), (0, usize::MAX, i));
+        declarations.push((key, declaration));
+    }
 
-    // Go through the rules from lowest to highest specificity.
-    rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
-    for (_, rule) in rules {
-        for declaration in &rule.declarations {
-            values.insert(declaration.name.clone(), declaration.value.clone());
+    // Pseudo-elements can't have a `style` attribute.
+    let inline = match (pseudo, elem.attrs.get("style")) {
+        (None, Some(style)) => css::parse_inline_style(style),
+        _ => Vec::new(),
+    };
+    for (i, declaration) in inline.iter().enumerate() {
+        declarations.push((CascadeKey::inline(declaration.important, i), declaration));
+    }
+
+    // Go through the declarations from highest to lowest precedence.
+    declarations.sort_by_key(|&(key, _)| key);
+    let mut values = HashMap::new();
+    let mut reverted: HashMap<&str, (CascadeKey, bool)> = HashMap::new();
+    for (key, declaration) in declarations.into_iter().rev() {
+        let name = &*declaration.name;
+        if values.contains_key(name) {
+            continue;
+        }
+        if let Some(&(revert, layer)) = reverted.get(name) {
+            if key.is_reverted_by(&revert, layer) {
+                continue;
+            }
+        }
+        match declaration.value {
+            Value::Keyword(ref k) if k == "revert" => { reverted.insert(name, (key, false)); }
+            Value::Keyword(ref k) if k == "revert-layer" => { reverted.insert(name, (key, true)); }
+            ref value => { values.insert(name.to_string(), value.clone()); }
         }
     }
     values
 }
 
-/// A single CSS rule and the specificity of its most specific matching selector.
-type MatchedRule<'a> = (Specificity, &'a Rule);
+/// Does this element get declarations from `dir_declarations`?
+fn has_dir_declarations(elem: &ElementData) -> bool {
+    elem.attrs.contains_key("dir") || elem.tag_name == "bdi"
+}
+
+/// User-agent styles for the `dir` attribute.  The HTML user-agent stylesheet sets `direction`
+/// and `unicode-bidi` with attribute selectors and `:dir()`, which aren't supported.
+///
+/// With `dir="auto"`, or on a `bdi` element without a valid `dir`, the direction comes from the
+/// first character in the element's text that has a strong direction.
+///
+/// https://html.spec.whatwg.org/multipage/rendering.html#bidi-rendering
+/// https://html.spec.whatwg.org/multipage/dom.html#the-dir-attribute
+fn dir_declarations(node: &Node, elem: &ElementData) -> Vec<Declaration> {
+    let dir = elem.attrs.get("dir").map(|dir| dir.to_ascii_lowercase());
+    let direction = match dir.as_deref() {
+        Some("ltr") => Direction::Ltr,
+        Some("rtl") => Direction::Rtl,
+        Some("auto") => auto_direction(node),
+        _ if elem.tag_name == "bdi" => auto_direction(node),
+        _ => return Vec::new(),
+    };
+    let keyword = |name: &str, value: &str| Declaration {
+        name: name.to_string(),
+        value: Value::Keyword(value.to_string()),
+        important: false,
+    };
+    let mut declarations = vec![keyword("direction", match direction {
+        Direction::Ltr => "ltr",
+        Direction::Rtl => "rtl",
+    })];
+    // `bdo` overrides the direction of its text, which the user-agent stylesheet handles.
+    if elem.tag_name != "bdo" && dir.is_some() {
+        declarations.push(keyword("unicode-bidi", "isolate"));
+    }
+    declarations
+}
 
-/// Find all CSS rules that match the given element.
-fn matching_rules<'a>(elem: &ElementData, stylesheet: &'a Stylesheet) -> Vec<MatchedRule<'a>> {
-    // For now, we just do a linear scan of all the rules.  For large
-    // documents, it would be more efficient to store the rules in hash tables
-    // based on tag name, id, class, etc.
-    stylesheet.rules.iter().filter_map(|rule| match_rule(elem, rule)).collect()
+/// User-agent styles for the `start` attribute of `ol` and the `value` attribute of `li`, which
+/// set the `list-item` counter.
+///
+/// https://html.spec.whatwg.org/multipage/rendering.html#lists
+fn list_declarations(elem: &ElementData) -> Vec<Declaration> {
+    // The first item of a list increments the counter, so it starts one below `start`.
+    let (attr, name, offset) = match &*elem.tag_name {
+        "ol" => ("start", "counter-reset", -1),
+        "li" => ("value", "counter-set", 0),
+        _ => return Vec::new(),
+    };
+    match elem.attrs.get(attr).and_then(|value| value.trim().parse::<i32>().ok()) {
+        Some(value) => {
+            let counter = Value::Keyword("list-item".to_string());
+            let value = Value::List(vec![counter, Value::Number((value + offset) as f32)]);
+            vec![Declaration { name: name.to_string(), value, important: false }]
+        }
+        None => Vec::new(),
+    }
 }
 
-/// If `rule` matches `elem`, return a `MatchedRule`. Otherwise return `None`.
-fn match_rule<'a>(elem: &ElementData, rule: &'a Rule) -> Option<MatchedRule<'a>> {
-    // Find the first (most specific) matching selector.
-    rule.selectors
-        .iter().find(|selector| matches(elem, selector))
-        .map(|selector| (selector.specificity(), rule))
+/// The direction of the first strongly directional character in an element's text, skipping
+/// elements with their own direction, or left to right if there is none.
+fn auto_direction(node: &Node) -> Direction {
+    fn text_direction(node: &Node) -> Option<Direction> {
+        match node.node_type {
+            NodeType::Text(ref text) => bidi::first_strong_direction(text),
+            NodeType::Element(ref elem) => {
+                if has_dir_declarations(elem)
+                    || matches!(&*elem.tag_name, "script" | "style" | "textarea") {
+                    return None;
+                }
+                node.children.iter().find_map(text_direction)
+            }
+        }
+    }
+    node.children.iter().find_map(text_direction).unwrap_or(Direction::Ltr)
 }
 
-/// Selector matching:
-fn matches(elem: &ElementData, selector: &Selector) -> bool {
+/// Selector matching.  `ancestors` are the element's ancestors, from the root element down.
+pub fn matches(elem: &ElementData, pseudo: Option<PseudoElement>, ancestors: &[&ElementData],
+               selector: &Selector) -> bool {
     match selector {
-        Selector::Simple(s) => matches_simple_selector(elem, s)
+        Selector::Simple(s) => matches_simple_selector(elem, pseudo, ancestors, s),
+        Selector::Descendant(ancestor, s) => {
+            matches_simple_selector(elem, pseudo, ancestors, s)
+                && (0..ancestors.len()).rev().any(|i| {
+                    matches(ancestors[i], None, &ancestors[..i], ancestor)
+                })
+        }
+        Selector::Child(parent, s) => {
+            matches_simple_selector(elem, pseudo, ancestors, s)
+                && ancestors.split_last().is_some_and(|(last, rest)| {
+                    matches(last, None, rest, parent)
+                })
+        }
     }
 }
 
-fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> bool {
+fn matches_simple_selector(elem: &ElementData, pseudo: Option<PseudoElement>,
+                           ancestors: &[&ElementData], selector: &SimpleSelector) -> bool {
+    // A selector with a pseudo-element only applies to that pseudo-element.
+    if selector.pseudo_element != pseudo {
+        return false;
+    }
+
     // Check type selector
     if selector.tag_name.iter().any(|name| elem.tag_name != *name) {
         return false;
@@ -124,6 +548,102 @@ fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> boo
         return false;
     }
 
+    // Check pseudo-classes
+    if !selector.pseudo_classes.iter().all(|pseudo_class| match pseudo_class {
+        PseudoClass::Is(selectors) | PseudoClass::Where(selectors) => {
+            selectors.iter().any(|s| matches(elem, None, ancestors, s))
+        }
+        PseudoClass::Root => ancestors.is_empty(),
+        // There is no user interaction with a rendered document.
+        PseudoClass::UserAction => false,
+    }) {
+        return false;
+    }
+
     // We didn't find any non-matching selector components.
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css::Color;
+    use crate::font::default_font;
+    use crate::html;
+    use crate::values::LengthPercentage;
+
+    fn device() -> Device {
+        Device::screen(Rect { width: 800.0, height: 600.0, ..Default::default() })
+    }
+
+    fn rgb(r: u8, g: u8, b: u8) -> Color {
+        Color { r, g, b, a: 255 }
+    }
+
+    #[test]
+    fn before_and_after_boxes() {
+        let root = html::parse("<div><p title=\"t\">x</p></div>".to_string());
+        let sheet = css::parse("p { color: red } \
+                                p::before { content: \"a\" } \
+                                p::after { content: attr(title); color: blue } \
+                                div::before { color: green } \
+                                div::after { content: none }".to_string());
+        let tree = style_tree(&root, &[sheet], &device(), &FontDatabase::new());
+        // Without a `content` value, the `div`'s pseudo-elements have no boxes.
+        assert_eq!(tree.children.len(), 1);
+        let p = &tree.children[0];
+        let pseudos: Vec<_> = p.children.iter().map(|child| child.pseudo).collect();
+        assert_eq!(pseudos, [Some(PseudoElement::Before), None, Some(PseudoElement::After)]);
+        let texts: Vec<_> = p.children.iter()
+            .map(|child| child.children.first().unwrap_or(child).text())
+            .collect();
+        assert_eq!(texts, [Some("a"), Some("x"), Some("t")]);
+        // Pseudo-elements inherit from their element, and their text inherits from them.
+        assert_eq!(p.children[0].style.color, rgb(255, 0, 0));
+        assert_eq!(p.children[2].children[0].style.color, rgb(0, 0, 255));
+    }
+
+    #[test]
+    fn font_relative_units() {
+        let root = html::parse("<div><p>x</p></div>".to_string());
+        let sheet = css::parse("div { font-size: 20px } \
+                                p { width: 2ex; height: 3ch; font-size: 2ex }".to_string());
+        let tree = style_tree(&root, &[sheet], &device(), &FontDatabase::new());
+        let style = &tree.children[0].style;
+        // `font-size` is relative to the parent's font.
+        let font = default_font();
+        let font_size = 2.0 * font.x_height(20.0);
+        assert_eq!(style.font_size, font_size);
+        assert_eq!(style.width.resolve(0.0), Some(2.0 * font.x_height(font_size)));
+        assert_eq!(style.height.resolve(0.0), Some(3.0 * font.zero_advance(font_size)));
+        // DejaVu Sans has both an "x" and a "0", and neither is half an em.
+        assert_ne!(font.x_height(10.0), 5.0);
+        assert_ne!(font.zero_advance(10.0), 5.0);
+    }
+
+    #[test]
+    fn inheritance_and_css_wide_keywords() {
+        let root = html::parse("<div><p><span>x</span></p><b></b></div>".to_string());
+        let sheet = css::parse("div { color: red; font-size: 20px; width: 10px; \
+                                      text-indent: 2em } \
+                                p { font-size: 2em; width: inherit; color: initial } \
+                                span { font-size: unset; width: unset; color: inherit } \
+                                b { text-indent: unset; color: unset }".to_string());
+        let tree = style_tree(&root, &[sheet], &device(), &FontDatabase::new());
+        let (div, p, b) = (&tree.style, &tree.children[0], &tree.children[1]);
+        let span = &p.children[0];
+        let text = &span.children[0];
+        // Reset properties aren't inherited unless asked to be.
+        assert_eq!(p.style.width.resolve(0.0), Some(10.0));
+        assert_eq!(span.style.width.resolve(0.0), None);
+        // `initial` works for inherited properties too.
+        assert_eq!(p.style.color, rgb(0, 0, 0));
+        assert_eq!(span.style.color, rgb(0, 0, 0));
+        assert_eq!(b.style.color, div.color);
+        // Relative values are inherited as computed, not as specified.
+        assert_eq!(p.style.font_size, 40.0);
+        assert_eq!(span.style.font_size, 40.0);
+        assert_eq!(text.style.font_size, 40.0);
+        assert_eq!(b.style.text_indent, LengthPercentage::Length(40.0));
+    }
+}
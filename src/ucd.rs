@@ -0,0 +1,31 @@
+//! Loading data files from the Unicode Character Database.
+//!
+//! https://www.unicode.org/reports/tr44/#Format_Conventions
+
+/// Parse a data file with one code point or range of code points per line, followed by fields
+/// separated by `;`.  Comments start with `#`.
+pub fn parse_data(data: &'static str) -> impl Iterator<Item=(u32, u32, Vec<&'static str>)> {
+    data.lines().filter_map(|line| {
+        let line = line.split('#').next().unwrap().trim();
+        let (range, fields) = line.split_once(';')?;
+        let (start, end) = range.split_once("..").unwrap_or((range, range));
+        let hex = |s: &str| u32::from_str_radix(s.trim(), 16).expect("invalid code point");
+        Some((hex(start), hex(end), fields.split(';').map(str::trim).collect()))
+    })
+}
+
+/// Parse a data file whose first field is a property value, into a table of the value of each
+/// range of code points.  The ranges must be sorted and not overlap.
+pub fn parse_ranges<T>(data: &'static str, parse: fn(&str) -> T) -> Vec<(u32, u32, T)> {
+    parse_data(data).map(|(start, end, fields)| (start, end, parse(fields[0]))).collect()
+}
+
+/// The value of `c` in a table from `parse_ranges`, or `None` if it isn't in any range.
+pub fn lookup<T: Copy>(table: &[(u32, u32, T)], c: char) -> Option<T> {
+    let c = c as u32;
+    match table.binary_search_by(|&(start, _, _)| start.cmp(&c)) {
+        Ok(i) => Some(table[i].2),
+        Err(i) if i > 0 && c <= table[i - 1].1 => Some(table[i - 1].2),
+        Err(_) => None,
+    }
+}
@@ -0,0 +1,638 @@
+//! Types for the computed values of properties, and how each one is computed from a specified
+//! value.
+//!
+//! https://www.w3.org/TR/css-cascade-5/#computed
+
+use crate::color;
+use crate::css::{CalcNode, CalcType, Color, Unit, Value};
+use crate::font::{default_font, Font};
+use crate::layout::Rect;
+use std::sync::Arc;
+
+/// The type of a property's value.
+pub trait PropertyType {
+    /// The representation of a computed value of this type.
+    type Computed: Clone + std::fmt::Debug;
+
+    /// Convert a specified value to a computed value, or return `None` if the value is invalid
+    /// for this type.
+    fn compute(value: Value, context: &Context) -> Option<Self::Computed>;
+
+    /// Check whether a specified value is valid for this type, when parsing.
+    fn is_valid(value: &Value) -> bool {
+        Self::compute(value.clone(), &Context::initial()).is_some()
+    }
+}
+
+/// Everything that computed values can depend on, other than the specified value itself.
+pub struct Context {
+    /// The computed `font-size` of the element, for `em`, `ex`, and `ch`.  While computing
+    /// `font-size` itself, this is the parent's font size.
+    pub font_size: f32,
+    /// The computed `font-size` of the root element, for `rem`.
+    pub root_font_size: f32,
+    pub viewport: Rect,
+    /// The computed `color` of the element, for `currentcolor`.  While computing `color` itself,
+    /// this is the parent's color.
+    pub color: Color,
+    /// The parent's computed `font-weight`, for `bolder` and `lighter`.
+    pub parent_font_weight: f32,
+    /// The element's primary font, for `ex` and `ch`.  While computing the font properties, this
+    /// is the parent's primary font.
+    pub font: Arc<Font>,
+}
+
+/// The initial value of `font-size`, in px.
+pub const DEFAULT_FONT_SIZE: f32 = 16.0;
+
+impl Context {
+    /// The context for the root element's parent, where everything has its initial value.
+    pub fn initial() -> Context {
+        Context {
+            font_size: DEFAULT_FONT_SIZE,
+            root_font_size: DEFAULT_FONT_SIZE,
+            viewport: Rect::default(),
+            color: Color { r: 0, g: 0, b: 0, a: 255 },
+            parent_font_weight: 400.0,
+            font: default_font().clone(),
+        }
+    }
+
+    /// Convert font-relative, viewport-relative, and absolute lengths to px.  Percentages depend
+    /// on the property and are left for layout to resolve.
+    ///
+    /// https://www.w3.org/TR/css-values-4/#lengths
+    pub fn resolve(&self, value: Value) -> Value {
+        match value {
+            Value::Length(f, unit) => {
+                let size = match unit {
+                    Unit::Percent => return value,
+                    Unit::Em => self.font_size,
+                    Unit::Rem => self.root_font_size,
+                    Unit::Ex => self.font.x_height(self.font_size),
+                    Unit::Ch => self.font.zero_advance(self.font_size),
+                    Unit::Vw => self.viewport.width / 100.0,
+                    Unit::Vh => self.viewport.height / 100.0,
+                    Unit::Vmin => self.viewport.width.min(self.viewport.height) / 100.0,
+                    Unit::Vmax => self.viewport.width.max(self.viewport.height) / 100.0,
+                    _ => unit.absolute_size().unwrap(),
+                };
+                Value::Length(f * size, Unit::Px)
+            }
+            Value::List(items) => Value::List(items.into_iter().map(|v| self.resolve(v)).collect()),
+            Value::CommaList(items) => {
+                Value::CommaList(items.into_iter().map(|v| self.resolve(v)).collect())
+            }
+            Value::Function(name, args) => {
+                Value::Function(name, args.into_iter().map(|v| self.resolve(v)).collect())
+            }
+            Value::Calc(node) => {
+                let node = node.map_lengths(&|f, unit| match self.resolve(Value::Length(f, unit)) {
+                    Value::Length(f, unit) => CalcNode::Length(f, unit),
+                    _ => unreachable!(),
+                });
+                // If there are no percentages left, the whole expression can be computed now.
+                if node.has_percentages() {
+                    Value::Calc(Box::new(node))
+                } else if node.calc_type() == Some(CalcType::Number) {
+                    Value::Number(node.evaluate(0.0))
+                } else {
+                    Value::Length(node.evaluate(0.0), Unit::Px)
+                }
+            }
+            value => value,
+        }
+    }
+
+    /// Resolve a value that must be a length in px, like `0` or `2em`.
+    fn length(&self, value: Value) -> Option<f32> {
+        match self.resolve(value) {
+            Value::Length(f, Unit::Px) => Some(f),
+            Value::Number(0.0) => Some(0.0),
+            _ => None,
+        }
+    }
+}
+
+/// Properties that aren't used by layout or painting yet keep their values untyped, with
+/// lengths converted to px.
+impl PropertyType for Value {
+    type Computed = Value;
+
+    fn compute(value: Value, context: &Context) -> Option<Value> {
+        Some(context.resolve(value))
+    }
+
+    fn is_valid(_: &Value) -> bool {
+        true
+    }
+}
+
+/// https://www.w3.org/TR/css-color-4/#resolving-color-values
+impl PropertyType for Color {
+    type Computed = Color;
+
+    fn compute(value: Value, context: &Context) -> Option<Color> {
+        match value {
+            Value::ColorValue(color) => Some(color),
+            Value::Keyword(ref k) if k.eq_ignore_ascii_case("currentcolor") => Some(context.color),
+            _ => None,
+        }
+    }
+}
+
+/// A `<length-percentage>` value.  Percentages are resolved during layout.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LengthPercentage {
+    /// A length in px.
+    Length(f32),
+    Percentage(f32),
+    /// A math function that contains percentages.
+    Calc(Box<CalcNode>),
+}
+
+impl LengthPercentage {
+    /// The length in px, where 100% is `reference`.
+    pub fn resolve(&self, reference: f32) -> f32 {
+        match *self {
+            LengthPercentage::Length(f) => f,
+            LengthPercentage::Percentage(f) => f / 100.0 * reference,
+            LengthPercentage::Calc(ref node) => node.evaluate(reference),
+        }
+    }
+}
+
+impl PropertyType for LengthPercentage {
+    type Computed = LengthPercentage;
+
+    fn compute(value: Value, context: &Context) -> Option<LengthPercentage> {
+        match context.resolve(value) {
+            Value::Length(f, Unit::Px) => Some(LengthPercentage::Length(f)),
+            Value::Length(f, Unit::Percent) => Some(LengthPercentage::Percentage(f)),
+            Value::Calc(node) => Some(LengthPercentage::Calc(node)),
+            Value::Number(0.0) => Some(LengthPercentage::Length(0.0)),
+            _ => None,
+        }
+    }
+}
+
+/// A `<length-percentage>` value that can't be negative, like `padding-top`.
+///
+/// https://www.w3.org/TR/css-box-4/#padding-physical
+pub struct NonNegativeLengthPercentage;
+
+impl PropertyType for NonNegativeLengthPercentage {
+    type Computed = LengthPercentage;
+
+    /// Negative lengths and percentages are invalid, but math functions are clamped to zero,
+    /// since their sign may depend on what percentages resolve to.
+    fn compute(value: Value, context: &Context) -> Option<LengthPercentage> {
+        let is_calc = matches!(value, Value::Calc(_));
+        match LengthPercentage::compute(value, context)? {
+            LengthPercentage::Length(f) if is_calc => Some(LengthPercentage::Length(f.max(0.0))),
+            LengthPercentage::Length(f) | LengthPercentage::Percentage(f) if f < 0.0 => None,
+            LengthPercentage::Calc(node) => Some(LengthPercentage::Calc(Box::new(
+                CalcNode::Max(vec![*node, CalcNode::Length(0.0, Unit::Px)])))),
+            value => Some(value),
+        }
+    }
+}
+
+/// A `<length-percentage>` value, or `auto`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LengthPercentageAuto {
+    Auto,
+    LengthPercentage(LengthPercentage),
+}
+
+impl LengthPercentageAuto {
+    /// The length in px, where 100% is `reference`, or `None` for `auto`.
+    pub fn resolve(&self, reference: f32) -> Option<f32> {
+        match self {
+            LengthPercentageAuto::Auto => None,
+            LengthPercentageAuto::LengthPercentage(value) => Some(value.resolve(reference)),
+        }
+    }
+}
+
+impl PropertyType for LengthPercentageAuto {
+    type Computed = LengthPercentageAuto;
+
+    fn compute(value: Value, context: &Context) -> Option<LengthPercentageAuto> {
+        match value {
+            Value::Keyword(ref k) if k.eq_ignore_ascii_case("auto") => {
+                Some(LengthPercentageAuto::Auto)
+            }
+            value => LengthPercentage::compute(value, context)
+                .map(LengthPercentageAuto::LengthPercentage),
+        }
+    }
+}
+
+/// The value of `border-*-width`: a length in px, or `thin`, `medium`, or `thick`.
+///
+/// https://www.w3.org/TR/css-backgrounds-3/#typedef-line-width
+pub struct LineWidth;
+
+impl PropertyType for LineWidth {
+    type Computed = f32;
+
+    fn compute(value: Value, context: &Context) -> Option<f32> {
+        match value {
+            Value::Keyword(k) => match &*k.to_ascii_lowercase() {
+                "thin" => Some(1.0),
+                "medium" => Some(3.0),
+                "thick" => Some(5.0),
+                _ => None,
+            },
+            value => context.length(value).filter(|&f| f >= 0.0),
+        }
+    }
+}
+
+/// The value of `font-size`, in px.
+///
+/// https://www.w3.org/TR/css-fonts-4/#font-size-prop
+pub struct FontSize;
+
+impl PropertyType for FontSize {
+    type Computed = f32;
+
+    /// Relative values are based on `context.font_size`, which is the parent's font size.
+    fn compute(value: Value, context: &Context) -> Option<f32> {
+        let parent = context.font_size;
+        match value {
+            Value::Keyword(k) => match &*k.to_ascii_lowercase() {
+                "xx-small" => Some(9.0),
+                "x-small" => Some(10.0),
+                "small" => Some(13.0),
+                "medium" => Some(16.0),
+                "large" => Some(18.0),
+                "x-large" => Some(24.0),
+                "xx-large" => Some(32.0),
+                "xxx-large" => Some(48.0),
+                "smaller" => Some(parent / 1.2),
+                "larger" => Some(parent * 1.2),
+                _ => None,
+            },
+            value => LengthPercentage::compute(value, context)
+                .map(|size| size.resolve(parent))
+                .filter(|&size| size >= 0.0),
+        }
+    }
+}
+
+/// A font family name, or a generic family that stands for a list of them.
+///
+/// https://www.w3.org/TR/css-fonts-4/#font-family-prop
+#[derive(Clone, Debug, PartialEq)]
+pub enum FamilyName {
+    Named(String),
+    Generic(GenericFamily),
+}
+
+/// The value of `font-family`: a list of families, in order of preference.
+pub struct FontFamily;
+
+impl PropertyType for FontFamily {
+    type Computed = Vec<FamilyName>;
+
+    fn compute(value: Value, context: &Context) -> Option<Vec<FamilyName>> {
+        let families = match value {
+            Value::CommaList(families) => families,
+            family => vec![family],
+        };
+        families.into_iter().map(|family| match family {
+            // Only unquoted names can be generic families.
+            Value::Keyword(_) => match GenericFamily::compute(family.clone(), context) {
+                Some(generic) => Some(FamilyName::Generic(generic)),
+                None => family_name(&family).map(FamilyName::Named),
+            },
+            family => family_name(&family).map(FamilyName::Named),
+        }).collect()
+    }
+}
+
+/// The family name in a value of `font-family` or the `font-family` descriptor: a string, or a
+/// sequence of identifiers separated by spaces.
+///
+/// Color names like `Black` in `Arial Black` have already been parsed as colors, so they are
+/// turned back into names, in lowercase, since family names are matched case-insensitively.
+pub fn family_name(value: &Value) -> Option<String> {
+    let identifier = |value: &Value| match value {
+        Value::Keyword(k) => Some(k.clone()),
+        &Value::ColorValue(color) => color::color_name(color).map(str::to_string),
+        _ => None,
+    };
+    match value {
+        Value::Str(s) => Some(s.clone()),
+        Value::List(items) => items.iter().map(identifier)
+            .collect::<Option<Vec<_>>>()
+            .map(|words| words.join(" ")),
+        value => identifier(value),
+    }
+}
+
+/// The value of `font-stretch`, as a percentage of the normal width.
+///
+/// https://www.w3.org/TR/css-fonts-4/#font-stretch-prop
+pub struct FontStretch;
+
+impl PropertyType for FontStretch {
+    type Computed = f32;
+
+    fn compute(value: Value, _: &Context) -> Option<f32> {
+        match value {
+            Value::Length(f, Unit::Percent) if f >= 0.0 => Some(f),
+            Value::Keyword(k) => match &*k.to_ascii_lowercase() {
+                "ultra-condensed" => Some(50.0),
+                "extra-condensed" => Some(62.5),
+                "condensed" => Some(75.0),
+                "semi-condensed" => Some(87.5),
+                "normal" => Some(100.0),
+                "semi-expanded" => Some(112.5),
+                "expanded" => Some(125.0),
+                "extra-expanded" => Some(150.0),
+                "ultra-expanded" => Some(200.0),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The value of `font-weight`, as a number from 1 to 1000.
+///
+/// https://www.w3.org/TR/css-fonts-4/#font-weight-prop
+pub struct FontWeight;
+
+impl PropertyType for FontWeight {
+    type Computed = f32;
+
+    fn compute(value: Value, context: &Context) -> Option<f32> {
+        let parent = context.parent_font_weight;
+        match context.resolve(value) {
+            Value::Number(n) if (1.0..=1000.0).contains(&n) => Some(n),
+            Value::Keyword(k) => match &*k.to_ascii_lowercase() {
+                "normal" => Some(400.0),
+                "bold" => Some(700.0),
+                // https://www.w3.org/TR/css-fonts-4/#relative-weights
+                "bolder" if parent < 350.0 => Some(400.0),
+                "bolder" if parent < 550.0 => Some(700.0),
+                "bolder" => Some(parent.max(900.0)),
+                "lighter" if parent < 100.0 => Some(parent),
+                "lighter" if parent < 550.0 => Some(100.0),
+                "lighter" if parent < 750.0 => Some(400.0),
+                "lighter" => Some(700.0),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The value of `letter-spacing` or `word-spacing`, in px.  `normal` is zero.
+pub struct Spacing;
+
+impl PropertyType for Spacing {
+    type Computed = f32;
+
+    fn compute(value: Value, context: &Context) -> Option<f32> {
+        match value {
+            Value::Keyword(ref k) if k.eq_ignore_ascii_case("normal") => Some(0.0),
+            value => context.length(value),
+        }
+    }
+}
+
+/// https://www.w3.org/TR/css-inline-3/#line-height-property
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineHeight {
+    Normal,
+    /// A multiple of the font size.  Unlike lengths, this is inherited as a number.
+    Number(f32),
+    /// A length in px.
+    Length(f32),
+}
+
+impl PropertyType for LineHeight {
+    type Computed = LineHeight;
+
+    /// Percentages are resolved against `context.font_size`, the element's own font size.
+    fn compute(value: Value, context: &Context) -> Option<LineHeight> {
+        match context.resolve(value) {
+            Value::Keyword(ref k) if k.eq_ignore_ascii_case("normal") => Some(LineHeight::Normal),
+            Value::Number(n) if n >= 0.0 => Some(LineHeight::Number(n)),
+            value => LengthPercentage::compute(value, context)
+                .map(|height| LineHeight::Length(height.resolve(context.font_size))),
+        }
+    }
+}
+
+/// Define an enum for a property whose values are all keywords.
+macro_rules! keyword_enum {
+    ($(#[$attr:meta])* pub enum $name:ident { $($variant:ident = $keyword:literal,)* }) => {
+        $(#[$attr])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum $name {
+            $($variant,)*
+        }
+
+        impl PropertyType for $name {
+            type Computed = $name;
+
+            fn compute(value: Value, _: &Context) -> Option<$name> {
+                match value {
+                    Value::Keyword(k) => match &*k.to_ascii_lowercase() {
+                        $($keyword => Some($name::$variant),)*
+                        _ => None,
+                    },
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+keyword_enum! {
+    /// The values of `display` that layout supports.
+    ///
+    /// https://www.w3.org/TR/css-display-3/#the-display-properties
+    pub enum Display {
+        Inline = "inline",
+        Block = "block",
+        ListItem = "list-item",
+        None = "none",
+    }
+}
+
+keyword_enum! {
+    /// https://www.w3.org/TR/css-backgrounds-3/#border-style
+    pub enum BorderStyle {
+        None = "none",
+        Hidden = "hidden",
+        Dotted = "dotted",
+        Dashed = "dashed",
+        Solid = "solid",
+        Double = "double",
+        Groove = "groove",
+        Ridge = "ridge",
+        Inset = "inset",
+        Outset = "outset",
+    }
+}
+
+keyword_enum! {
+    /// https://www.w3.org/TR/css-fonts-4/#font-style-prop
+    pub enum FontStyle {
+        Normal = "normal",
+        Italic = "italic",
+        Oblique = "oblique",
+    }
+}
+
+keyword_enum! {
+    /// https://www.w3.org/TR/css-fonts-4/#generic-font-families
+    pub enum GenericFamily {
+        Serif = "serif",
+        SansSerif = "sans-serif",
+        Monospace = "monospace",
+        Cursive = "cursive",
+        Fantasy = "fantasy",
+        SystemUi = "system-ui",
+        UiSerif = "ui-serif",
+        UiSansSerif = "ui-sans-serif",
+        UiMonospace = "ui-monospace",
+        UiRounded = "ui-rounded",
+        Math = "math",
+        Emoji = "emoji",
+        Fangsong = "fangsong",
+    }
+}
+
+keyword_enum! {
+    /// https://www.w3.org/TR/css-writing-modes-4/#direction
+    pub enum Direction {
+        Ltr = "ltr",
+        Rtl = "rtl",
+    }
+}
+
+keyword_enum! {
+    /// How an inline box affects the bidirectional algorithm: whether it starts an embedding or
+    /// an isolate, and whether it overrides the direction of the characters in it.
+    ///
+    /// https://www.w3.org/TR/css-writing-modes-3/#unicode-bidi
+    pub enum UnicodeBidi {
+        Normal = "normal",
+        Embed = "embed",
+        Isolate = "isolate",
+        BidiOverride = "bidi-override",
+        IsolateOverride = "isolate-override",
+        Plaintext = "plaintext",
+    }
+}
+
+keyword_enum! {
+    /// https://www.w3.org/TR/css-text-3/#text-align-property
+    pub enum TextAlign {
+        Start = "start",
+        End = "end",
+        Left = "left",
+        Right = "right",
+        Center = "center",
+        Justify = "justify",
+    }
+}
+
+keyword_enum! {
+    /// https://www.w3.org/TR/css-text-3/#text-align-last-property
+    pub enum TextAlignLast {
+        Auto = "auto",
+        Start = "start",
+        End = "end",
+        Left = "left",
+        Right = "right",
+        Center = "center",
+        Justify = "justify",
+    }
+}
+
+keyword_enum! {
+    /// https://www.w3.org/TR/css-text-3/#text-transform-property
+    pub enum TextTransform {
+        None = "none",
+        Capitalize = "capitalize",
+        Uppercase = "uppercase",
+        Lowercase = "lowercase",
+    }
+}
+
+keyword_enum! {
+    /// https://www.w3.org/TR/css-text-3/#white-space-property
+    pub enum WhiteSpace {
+        Normal = "normal",
+        Pre = "pre",
+        Nowrap = "nowrap",
+        PreWrap = "pre-wrap",
+        PreLine = "pre-line",
+    }
+}
+
+keyword_enum! {
+    /// The deprecated `break-word` is the same as `normal` with `overflow-wrap: anywhere`.
+    ///
+    /// https://www.w3.org/TR/css-text-3/#word-break-property
+    pub enum WordBreak {
+        Normal = "normal",
+        BreakAll = "break-all",
+        KeepAll = "keep-all",
+        BreakWord = "break-word",
+    }
+}
+
+keyword_enum! {
+    /// https://www.w3.org/TR/css-text-3/#overflow-wrap-property
+    pub enum OverflowWrap {
+        Normal = "normal",
+        BreakWord = "break-word",
+        Anywhere = "anywhere",
+    }
+}
+
+keyword_enum! {
+    /// https://www.w3.org/TR/css-text-3/#line-break-property
+    pub enum LineBreak {
+        Auto = "auto",
+        Loose = "loose",
+        Normal = "normal",
+        Strict = "strict",
+        Anywhere = "anywhere",
+    }
+}
+
+keyword_enum! {
+    /// https://www.w3.org/TR/css-text-3/#hyphens-property
+    pub enum Hyphens {
+        None = "none",
+        Manual = "manual",
+        Auto = "auto",
+    }
+}
+
+keyword_enum! {
+    /// https://www.w3.org/TR/CSS2/visufx.html#visibility
+    pub enum Visibility {
+        Visible = "visible",
+        Hidden = "hidden",
+        Collapse = "collapse",
+    }
+}
+
+keyword_enum! {
+    /// https://www.w3.org/TR/css-lists-3/#list-style-position-property
+    pub enum ListStylePosition {
+        Inside = "inside",
+        Outside = "outside",
+    }
+}
@@ -0,0 +1,270 @@
+//! CSS custom properties and `var()` substitution.
+//!
+//! https://www.w3.org/TR/css-variables-1/
+
+use crate::css::{self, Declaration, Value};
+use crate::properties;
+use crate::shorthands;
+use crate::style::PropertyMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// The computed values of custom properties, as source text with all `var()`s substituted.
+///
+/// These are shared between elements until one of them declares its own custom properties.
+pub type CustomProperties = Arc<HashMap<String, String>>;
+
+/// Does this source text contain a `var()` reference?
+pub fn contains_var(source: &str) -> bool {
+    find_var(source).is_some()
+}
+
+/// Compute the custom properties of an element, and substitute variables in its other
+/// declarations.  Custom properties are inherited from `parent`.
+///
+/// Custom properties are removed from `values`, and returned separately.  A declaration that
+/// refers to an undefined variable with no fallback, or to a variable in a dependency cycle, or
+/// whose value is invalid after substitution, is invalid at computed-value time.  It is removed
+/// too, which makes the property behave as if it were `unset`.
+///
+/// https://www.w3.org/TR/css-variables-1/#invalid-at-computed-value-time
+pub fn substitute(values: &mut PropertyMap, parent: &CustomProperties) -> CustomProperties {
+    let own: HashMap<String, String> = values.iter().filter_map(|(name, value)| match value {
+        Value::Unparsed(source) if name.starts_with("--") => Some((name.clone(), source.clone())),
+        _ => None
+    }).collect();
+
+    let custom = if own.is_empty() {
+        parent.clone()
+    } else {
+        let mut resolver = Resolver {
+            own: &own,
+            inherited: parent,
+            resolved: HashMap::new(),
+            stack: Vec::new(),
+            cyclic: HashSet::new(),
+        };
+        let mut computed = (**parent).clone();
+        for name in own.keys() {
+            match resolver.lookup(name) {
+                Some(value) => computed.insert(name.clone(), value),
+                None => computed.remove(name),
+            };
+        }
+        Arc::new(computed)
+    };
+
+    // Substitute variables in a declaration's source text, and parse the result.
+    let substitute_and_parse = |source: &str| {
+        let text = substitute_vars(source, &mut |name| custom.get(name).cloned())?;
+        css::try_parse_value(&text)
+    };
+    values.retain(|name, value| {
+        if name.starts_with("--") {
+            return false;
+        }
+        let declaration = match value {
+            Value::Unparsed(source) => match substitute_and_parse(source) {
+                Some(parsed) => Declaration { name: name.clone(), value: parsed, important: false },
+                None => return false,
+            },
+            Value::PendingSubstitution(shorthand, source) => {
+                let Some(parsed) = substitute_and_parse(source) else { return false };
+                let declaration = Declaration {
+                    name: shorthand.clone(),
+                    value: parsed,
+                    important: false,
+                };
+                match shorthands::expand(declaration).into_iter().find(|d| d.name == *name) {
+                    Some(longhand) => longhand,
+                    None => return false,
+                }
+            }
+            _ => return true,
+        };
+        if !properties::is_valid(&declaration) {
+            return false;
+        }
+        *value = declaration.value;
+        true
+    });
+    custom
+}
+
+/// Resolves references between the custom properties declared on a single element.
+struct Resolver<'a> {
+    own: &'a HashMap<String, String>,
+    inherited: &'a HashMap<String, String>,
+    resolved: HashMap<String, Option<String>>,
+    /// The custom properties currently being resolved, to detect cycles.
+    stack: Vec<String>,
+    /// Custom properties found to be part of a dependency cycle.
+    cyclic: HashSet<String>,
+}
+
+impl Resolver<'_> {
+    /// Find the computed value of a custom property, or `None` if it is not defined or invalid.
+    fn lookup(&mut self, name: &str) -> Option<String> {
+        if let Some(result) = self.resolved.get(name) {
+            return result.clone();
+        }
+        let own = self.own;
+        let source = match own.get(name) {
+            Some(source) => source,
+            None => return self.inherited.get(name).cloned(),
+        };
+        if let Some(i) = self.stack.iter().position(|n| n == name) {
+            // Every custom property in the cycle is invalid.
+            self.cyclic.extend(self.stack[i..].iter().cloned());
+            return None;
+        }
+
+        self.stack.push(name.to_string());
+        let result = substitute_vars(source, &mut |name| self.lookup(name));
+        self.stack.pop();
+
+        let result = if self.cyclic.contains(name) { None } else { result };
+        self.resolved.insert(name.to_string(), result.clone());
+        result
+    }
+}
+
+/// Replace each `var(--name, fallback)` in `source` with the value returned by `lookup`, or with
+/// its fallback.  Returns `None` if a variable is undefined and has no fallback.
+fn substitute_vars(source: &str, lookup: &mut dyn FnMut(&str) -> Option<String>)
+                   -> Option<String> {
+    let mut result = String::new();
+    let mut rest = source;
+    while let Some((start, end, closed)) = find_var(rest) {
+        result.push_str(&rest[..start]);
+        let args = &rest[start + "var(".len()..if closed { end - 1 } else { end }];
+        let (name, fallback) = match find_top_level(args, b',') {
+            Some(comma) => (&args[..comma], Some(&args[comma + 1..])),
+            None => (args, None),
+        };
+        match (lookup(name.trim()), fallback) {
+            (Some(value), _) => result.push_str(&value),
+            (None, Some(fallback)) => result.push_str(&substitute_vars(fallback.trim(), lookup)?),
+            (None, None) => return None,
+        }
+        rest = &rest[end..];
+    }
+    result.push_str(rest);
+    Some(result)
+}
+
+/// Find the first `var(...)` in `source`, returning the byte range from `v` through `)`, and
+/// whether there was a `)`.  A function that isn't closed runs to the end of the source.
+fn find_var(source: &str) -> Option<(usize, usize, bool)> {
+    let bytes = source.as_bytes();
+    let mut quote = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        match (quote, bytes[i]) {
+            (Some(_), b'\\') => i += 1,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, c @ (b'"' | b'\'')) => quote = Some(c),
+            (None, _) if bytes[i..].len() >= 4 && bytes[i..i + 4].eq_ignore_ascii_case(b"var(")
+                && (i == 0 || !is_identifier_byte(bytes[i - 1])) => {
+                return Some(match find_top_level(&source[i + 4..], b')') {
+                    Some(close) => (i, i + 4 + close + 1, true),
+                    // An unclosed function is closed by the end of the value.
+                    None => (i, source.len(), false),
+                });
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Find the first `target` byte in `source` outside of any strings or nested brackets.
+fn find_top_level(source: &str, target: u8) -> Option<usize> {
+    let bytes = source.as_bytes();
+    let mut quote = None;
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match (quote, bytes[i]) {
+            (Some(_), b'\\') => i += 1,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, c @ (b'"' | b'\'')) => quote = Some(c),
+            (None, c) if c == target && depth == 0 => return Some(i),
+            (None, b'(' | b'[' | b'{') => depth += 1,
+            (None, b')' | b']' | b'}') => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn is_identifier_byte(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'-' || c == b'_' || c >= 0x80
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::css::Unit;
+
+    /// Substitute the variables in the declarations of a `style` attribute.
+    fn substituted(source: &str) -> PropertyMap {
+        let mut values: PropertyMap = css::parse_inline_style(source).into_iter()
+            .map(|declaration| (declaration.name, declaration.value))
+            .collect();
+        substitute(&mut values, &Arc::new(HashMap::new()));
+        values
+    }
+
+    #[test]
+    fn substitutes_variables() {
+        let values = substituted("--x: 10px; width: var(--x); height: var(--y, 2em)");
+        assert_eq!(values["width"], Value::Length(10.0, Unit::Px));
+        assert_eq!(values["height"], Value::Length(2.0, Unit::Em));
+    }
+
+    #[test]
+    fn invalid_after_substitution() {
+        let values = substituted("--x: 10deg; --y: #12345; --z: red; \
+                                  width: var(--x); color: var(--y); height: var(--z); \
+                                  margin: var(--x) 4px");
+        assert!(values.is_empty(), "{:?}", values);
+        // If any part of a shorthand is invalid, the whole shorthand is.
+        let values = substituted("--x: -2px; padding: 1px var(--x); border: var(--x) solid red");
+        assert!(values.is_empty(), "{:?}", values);
+    }
+
+    #[test]
+    fn dependency_cycles() {
+        let mut values: PropertyMap = css::parse_inline_style(
+            "--a: var(--b); --b: var(--c); --c: var(--a); --d: var(--d, 1px); \
+             --e: var(--a, 2px); --f: var(--g); --g: 3px; \
+             width: var(--a, 4px); height: var(--b); margin-top: var(--e); \
+             margin-left: var(--f)")
+            .into_iter()
+            .map(|declaration| (declaration.name, declaration.value))
+            .collect();
+        let custom = substitute(&mut values, &Arc::new(HashMap::new()));
+        // Every property in a cycle is invalid, even with a fallback, but a property that only
+        // refers to one isn't part of the cycle, and can fall back.
+        let mut names: Vec<_> = custom.keys().map(String::as_str).collect();
+        names.sort();
+        assert_eq!(names, ["--e", "--f", "--g"]);
+        assert_eq!(values["width"], Value::Length(4.0, Unit::Px));
+        assert!(!values.contains_key("height"));
+        assert_eq!(values["margin-top"], Value::Length(2.0, Unit::Px));
+        assert_eq!(values["margin-left"], Value::Length(3.0, Unit::Px));
+    }
+
+    #[test]
+    fn unclosed_var() {
+        let values = substituted("--y: 5px; width: var(--y");
+        assert_eq!(values["width"], Value::Length(5.0, Unit::Px));
+        let values = substituted("--y: 5px; width: var(--z, var(--y");
+        assert_eq!(values["width"], Value::Length(5.0, Unit::Px));
+    }
+}